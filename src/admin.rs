@@ -0,0 +1,180 @@
+use anyhow::{anyhow, Result};
+use chrono::{TimeDelta, Utc};
+use sqlx::{postgres::PgPoolOptions, PgPool, Postgres, Transaction};
+
+use crate::{
+    app::{MultipleRiderChange, RedisJob},
+    config::Config,
+    db::{car::Car, event::Event, identity::UserIdentity, user::UserData},
+    redis::RedisQueue,
+};
+
+/// Data maintenance run from the command line by `admin`, instead of raw SQL. Each change is
+/// made in one transaction, which a dry run rolls back instead of committing, so it fails or
+/// succeeds exactly as the real run would.
+pub struct Admin {
+    pub db: PgPool,
+    pub queue: RedisQueue,
+    /// Roll back every change and queue nothing.
+    pub dry_run: bool,
+}
+
+impl Admin {
+    pub async fn connect(config: &Config, dry_run: bool) -> Result<Self> {
+        let db = PgPoolOptions::new()
+            .max_connections(config.database.max_connections)
+            .connect(&config.database.url)
+            .await
+            .map_err(|err| anyhow!("Failed to connect to Postgres: {}", err))?;
+        let queue = if config.features.notifications {
+            RedisQueue::connect(&config.redis).await?
+        } else {
+            RedisQueue::Disabled
+        };
+        Ok(Admin { db, queue, dry_run })
+    }
+
+    async fn begin(&self) -> Result<Transaction<'static, Postgres>> {
+        self.db
+            .begin()
+            .await
+            .map_err(|err| anyhow!("Failed to make SQL Transaction: {}", err))
+    }
+
+    async fn finish(&self, tx: Transaction<'static, Postgres>) -> Result<()> {
+        if self.dry_run {
+            tx.rollback()
+                .await
+                .map_err(|err| anyhow!("Failed to roll back transaction: {}", err))
+        } else {
+            tx.commit()
+                .await
+                .map_err(|err| anyhow!("Failed to commit transaction: {}", err))
+        }
+    }
+
+    async fn event(&self, id: i32) -> Result<Event> {
+        Event::select_one(id, &self.db)
+            .await?
+            .ok_or(anyhow!("Event {} does not exist", id))
+    }
+
+    async fn car(&self, event_id: i32, car_id: i32) -> Result<Car> {
+        Car::select_one(event_id, car_id, &self.db)
+            .await?
+            .ok_or(anyhow!("Car {} is not in event {}", car_id, event_id))
+    }
+
+    async fn user(&self, id: &str) -> Result<UserData> {
+        UserData::select_one(id.to_string(), &self.db)
+            .await?
+            .ok_or(anyhow!("User {} does not exist", id))
+    }
+
+    pub async fn list_events(&self, past: bool) -> Result<Vec<Event>> {
+        Event::select_all(past, &self.db).await
+    }
+
+    pub async fn show_event(&self, id: i32) -> Result<(Event, Vec<Car>)> {
+        let event = self.event(id).await?;
+        let cars = Car::select_all(id, &self.db).await?;
+        Ok((event, cars))
+    }
+
+    /// Delete an event with its cars and ride requests. Nobody is notified.
+    pub async fn delete_event(&self, id: i32) -> Result<Event> {
+        let event = self.event(id).await?;
+        let mut tx = self.begin().await?;
+        Event::delete(id, event.creator.id.clone(), &mut *tx).await?;
+        self.finish(tx).await?;
+        Ok(event)
+    }
+
+    /// Delete every event that ended more than `days` days ago. Returns their IDs.
+    pub async fn purge_events(&self, days: i64) -> Result<Vec<i32>> {
+        if days < 0 {
+            return Err(anyhow!("Days cannot be negative"));
+        }
+        let mut tx = self.begin().await?;
+        let ids = Event::delete_ended_before(Utc::now() - TimeDelta::days(days), &mut *tx).await?;
+        self.finish(tx).await?;
+        Ok(ids)
+    }
+
+    /// Take a rider out of a car. Nobody is notified.
+    pub async fn remove_rider(&self, event_id: i32, car_id: i32, rider_id: &str) -> Result<()> {
+        self.car(event_id, car_id).await?;
+        let mut tx = self.begin().await?;
+        if !Car::remove_rider(car_id, rider_id, &mut *tx).await? {
+            return Err(anyhow!("{} is not a rider in car {}", rider_id, car_id));
+        }
+        self.finish(tx).await
+    }
+
+    /// Move a rider to another car in the same event, if it has room. Nobody is notified.
+    pub async fn move_rider(
+        &self,
+        event_id: i32,
+        rider_id: &str,
+        from: i32,
+        to: i32,
+    ) -> Result<()> {
+        self.car(event_id, from).await?;
+        let to_car = self.car(event_id, to).await?;
+        if to_car.riders.unwrap_or_default().len() as i32 >= to_car.max_capacity {
+            return Err(anyhow!("Car {} is full", to));
+        }
+        let mut tx = self.begin().await?;
+        if !Car::remove_rider(from, rider_id, &mut *tx).await? {
+            return Err(anyhow!("{} is not a rider in car {}", rider_id, from));
+        }
+        if Car::user_in_car(event_id, &rider_id.to_string(), &mut *tx).await? {
+            return Err(anyhow!(
+                "{} is also in another car in event {}",
+                rider_id,
+                event_id
+            ));
+        }
+        Car::add_rider(to, rider_id, &mut *tx).await?;
+        self.finish(tx).await
+    }
+
+    /// Move everything `old_id` has to `new_id`, like linking an account does, and delete
+    /// `old_id`.
+    pub async fn merge_users(&self, old_id: &str, new_id: &str) -> Result<()> {
+        if old_id == new_id {
+            return Err(anyhow!("Cannot merge a user into themselves"));
+        }
+        self.user(old_id).await?;
+        self.user(new_id).await?;
+        let mut tx = self.begin().await?;
+        UserIdentity::merge(old_id, new_id, &mut tx).await?;
+        self.finish(tx).await
+    }
+
+    /// Queue a notification to every rider of a car that they're in it, e.g. after a failed
+    /// job was dropped. Returns how many riders there are.
+    pub async fn notify_car(&mut self, event_id: i32, car_id: i32) -> Result<usize> {
+        if let RedisQueue::Disabled = self.queue {
+            return Err(anyhow!("Notifications are turned off"));
+        }
+        let car = self.car(event_id, car_id).await?;
+        let riders: Vec<String> = car
+            .riders
+            .unwrap_or_default()
+            .into_iter()
+            .map(|rider| rider.id)
+            .collect();
+        if !self.dry_run && !riders.is_empty() {
+            self.queue
+                .insert_job(RedisJob::RiderUpdate(MultipleRiderChange {
+                    event_id,
+                    car_id,
+                    old_riders: Vec::new(),
+                    new_riders: riders.clone(),
+                }))
+                .await?;
+        }
+        Ok(riders.len())
+    }
+}
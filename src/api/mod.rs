@@ -1,27 +1,56 @@
 use actix_web::{body::BoxBody, web, HttpResponse, Scope};
-use utoipa::OpenApi;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::app::ApiError;
+use crate::error::{ApiError, ErrorCode, FieldError};
 
-mod v1;
+pub mod v1;
 
 #[derive(OpenApi)]
 #[openapi(
     nest(
         (path = "/api/v1", api = v1::ApiDoc)
     ),
-    components(schemas(ApiError))
+    components(schemas(ApiError, ErrorCode, FieldError)),
+    modifiers(&SecurityAddon),
+    security(
+        ("session_cookie" = []),
+        ("api_token" = [])
+    )
 )]
 pub(super) struct ApiDoc;
 
+/// Routes accept either the login cookie or a personal API token as a bearer token.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "session_cookie",
+                SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("id"))),
+            );
+            components.add_security_scheme(
+                "api_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
 pub async fn open_api_spec() -> HttpResponse<BoxBody> {
     let openapi = ApiDoc::openapi();
     HttpResponse::Ok().json(openapi)
 }
 
-pub fn scope() -> Scope {
-    web::scope("/api").service(v1::scope()).service(
+/// The API, with the Swagger UI and OpenAPI document under `/api/docs` if `docs` is set.
+pub fn scope(docs: bool) -> Scope {
+    let scope = web::scope("/api").service(v1::scope());
+    if !docs {
+        return scope;
+    }
+    scope.service(
         web::scope("/docs")
             .route("/openapi.json", web::get().to(open_api_spec))
             .service(SwaggerUi::new("/{_:.*}").url("/api/docs/openapi.json", Default::default())),
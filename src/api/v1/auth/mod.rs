@@ -1,14 +1,18 @@
-use crate::auth::SessionAuth;
-use crate::{api::v1::auth::models::UserInfo, app::ApiError};
+use crate::auth::{CurrentUser, SessionAuth};
+use crate::db::session::SessionInfo;
+use crate::providers::ProviderSummary;
+use crate::{
+    app::AppState,
+    error::{AppError, AppResult, OrInternal},
+};
 use actix_session::Session;
-use actix_web::{get, http::header, post, web, HttpResponse, Responder, Scope};
-use log::error;
+use actix_web::{delete, get, http::header, post, web, HttpResponse, Responder, Scope};
+use tracing::instrument;
 use utoipa::OpenApi;
 
-mod common;
-mod csh;
-mod google;
 pub mod models;
+mod provider;
+mod token;
 
 #[utoipa::path(
     responses(
@@ -16,9 +20,9 @@ pub mod models;
     )
 )]
 #[post("/logout")]
+#[instrument(skip_all)]
 async fn logout(session: Session) -> impl Responder {
-    session.remove("login");
-    session.remove("userinfo");
+    session.purge();
     HttpResponse::Found()
         .append_header((header::LOCATION, "/"))
         .finish()
@@ -27,19 +31,75 @@ async fn logout(session: Session) -> impl Responder {
 #[utoipa::path(
     responses(
         (status = 200, description = "Get current user information"),
+        (status = 401, body = ApiError),
         (status = 500, body = ApiError)
     )
 )]
 #[get("/", wrap = "SessionAuth")]
-async fn get_user_data(session: Session) -> impl Responder {
-    match session.get::<UserInfo>("userinfo") {
-        Ok(data) => HttpResponse::Ok().json(data),
-        Err(err) => {
-            error!("{}", err);
-            HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to get Session Data".to_string()))
-        }
+#[instrument(skip_all)]
+async fn get_user_data(user: CurrentUser) -> AppResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(user.profile))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List the providers users can log in with", body = [ProviderSummary])
+    )
+)]
+#[get("/providers")]
+#[instrument(skip_all)]
+async fn get_providers(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(data.auth_providers.list())
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get the current user's active sessions", body = [SessionInfo]),
+        (status = 401, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/sessions", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn get_sessions(
+    data: web::Data<AppState>,
+    session: Session,
+    user: CurrentUser,
+) -> AppResult<HttpResponse> {
+    let current = session.get::<String>("session_id").ok().flatten();
+
+    let mut sessions = SessionInfo::select_all(&user.data.id, &data.db)
+        .await
+        .or_internal("Failed to get sessions")?;
+    for info in sessions.iter_mut() {
+        info.current = current.as_ref() == Some(&info.id);
     }
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[utoipa::path(
+    params(
+        ("session_id" = String, Path, description = "ID of the Session to End")
+    ),
+    responses(
+        (status = 200, description = "End one of the current user's sessions"),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[delete("/sessions/{session_id}", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn delete_session(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    SessionInfo::revoke(&user.data.id, &path.into_inner(), &data.db)
+        .await
+        .or_internal("Failed to end session")?
+        .ok_or(AppError::NotFound("Session not found".to_string()))?;
+    Ok(HttpResponse::Ok().body("Session ended"))
 }
 
 #[derive(OpenApi)]
@@ -47,10 +107,14 @@ async fn get_user_data(session: Session) -> impl Responder {
     paths(
         logout,
         get_user_data,
+        get_providers,
+        get_sessions,
+        delete_session,
     ),
+    components(schemas(ProviderSummary, SessionInfo)),
     nest(
-        (path = "/csh", api = csh::ApiDoc),
-        (path = "/google", api = google::ApiDoc)
+        (path = "/tokens", api = token::ApiDoc),
+        (path = "/{provider}", api = provider::ApiDoc)
     ),
 )]
 pub(super) struct ApiDoc;
@@ -59,6 +123,9 @@ pub fn scope() -> Scope {
     web::scope("/auth")
         .service(logout)
         .service(get_user_data)
-        .service(csh::scope())
-        .service(google::scope())
+        .service(get_providers)
+        .service(get_sessions)
+        .service(delete_session)
+        .service(token::scope())
+        .service(provider::scope())
 }
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CSHUserInfo {
@@ -8,6 +9,7 @@ pub struct CSHUserInfo {
     pub preferred_username: String,
     pub ldap_id: String,
     pub groups: Vec<String>,
+    pub sub: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -19,6 +21,30 @@ pub struct GoogleUserInfo {
     pub sub: String,
 }
 
+/// Profile returned from a provider's userinfo endpoint.
+pub trait ProviderUserInfo {
+    /// The `sub` claim, which must match the one in the ID token.
+    fn subject(&self) -> &str;
+}
+
+impl ProviderUserInfo for CSHUserInfo {
+    fn subject(&self) -> &str {
+        &self.sub
+    }
+}
+
+impl ProviderUserInfo for GoogleUserInfo {
+    fn subject(&self) -> &str {
+        &self.sub
+    }
+}
+
+impl ProviderUserInfo for Map<String, Value> {
+    fn subject(&self) -> &str {
+        self.get("sub").and_then(Value::as_str).unwrap_or_default()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserInfo {
     pub id: String,
@@ -30,6 +56,12 @@ pub struct UserInfo {
     pub groups: Vec<String>,
 }
 
+impl UserInfo {
+    pub fn is_admin(&self, admin_group: &str) -> bool {
+        self.groups.iter().any(|group| group == admin_group)
+    }
+}
+
 impl From<CSHUserInfo> for UserInfo {
     fn from(user_info: CSHUserInfo) -> Self {
         let username = user_info.preferred_username;
@@ -0,0 +1,235 @@
+use crate::api::v1::auth::models::UserInfo;
+use crate::api::v1::user::{PendingMerge, MERGE_KEY};
+use crate::app::AppState;
+use crate::auth::SessionAuth;
+use crate::db::identity::UserIdentity;
+use crate::db::user::UserData;
+use crate::error::{AppError, AppResult, OrInternal};
+use crate::providers::PendingLogin;
+use actix_session::Session;
+use actix_web::http::header;
+use actix_web::{get, web, HttpResponse, Scope};
+use anyhow::Result;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::Deserialize;
+use tracing::instrument;
+use utoipa::{OpenApi, ToSchema};
+
+const LOGIN_KEY: &str = "pending_login";
+
+#[derive(OpenApi)]
+#[openapi(paths(login, link_account, auth), components(schemas(AuthRequest)))]
+pub(super) struct ApiDoc;
+
+pub fn login_session(session: &Session, user_info: UserInfo) -> Result<()> {
+    // A fresh key so a session fixed before login can't be used afterwards.
+    session.renew();
+    session.insert(
+        "session_id",
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 16),
+    )?;
+    session.insert("login", true)?;
+    session.insert("userinfo", user_info)?;
+    Ok(())
+}
+
+fn start_login(
+    session: &Session,
+    data: &AppState,
+    provider: String,
+    link: bool,
+) -> AppResult<HttpResponse> {
+    let provider = data
+        .auth_providers
+        .get(&provider)
+        .ok_or(AppError::NotFound("Auth provider not found".to_string()))?;
+
+    // The redirect has to come back with this state, and the ID token has to carry this nonce,
+    // so neither can be replayed from somebody else's login.
+    let (authorize_url, mut pending) = provider.login_url();
+    pending.link = link;
+    session
+        .insert(LOGIN_KEY, pending)
+        .or_internal("Failed to start login")?;
+
+    Ok(HttpResponse::Ok().body(authorize_url))
+}
+
+#[utoipa::path(
+    params(
+        ("provider" = String, Path, description = "Name of the Provider to Log In With")
+    ),
+    responses(
+        (status = 200, description = "OAuth2 Link to Log In"),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/")]
+#[instrument(skip_all)]
+async fn login(
+    session: Session,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    start_login(&session, &data, path.into_inner(), false)
+}
+
+#[utoipa::path(
+    params(
+        ("provider" = String, Path, description = "Name of the Provider to Link")
+    ),
+    responses(
+        (status = 200, description = "OAuth2 Link to log in and link the account to the current user. If the account already belongs to another user, it is only merged after confirming at /user/merge."),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/link", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn link_account(
+    session: Session,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    start_login(&session, &data, path.into_inner(), true)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AuthRequest {
+    code: String,
+    state: String,
+}
+
+#[utoipa::path(
+    params(
+        ("provider" = String, Path, description = "Name of the Provider to Log In With")
+    ),
+    responses(
+        (status = 302, description = "Successful login, Redirect to home page."),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/redirect")]
+#[instrument(skip_all)]
+async fn auth(
+    session: Session,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    params: web::Query<AuthRequest>,
+) -> AppResult<HttpResponse> {
+    let provider = data
+        .auth_providers
+        .get(&path.into_inner())
+        .ok_or(AppError::NotFound("Auth provider not found".to_string()))?;
+
+    // Each login can only be completed once, whether or not it succeeds.
+    let pending = match session.remove_as::<PendingLogin>(LOGIN_KEY) {
+        Some(Ok(pending)) => pending,
+        _ => {
+            return Err(AppError::BadRequest(
+                "Login expired, please log in again".to_string(),
+            ))
+        }
+    };
+    if pending.provider != provider.name() || pending.state != params.state {
+        return Err(AppError::BadRequest(
+            "Login state does not match, please log in again".to_string(),
+        ));
+    }
+
+    let link = pending.link;
+    let mut user_info = provider.authenticate(params.code.clone(), pending).await?;
+    let realm = provider.name().to_string();
+    let subject = user_info.id.clone();
+
+    let mut tx = data
+        .db
+        .begin()
+        .await
+        .or_internal("Failed to make SQL Transaction")?;
+    let owner = UserIdentity::select_user(&realm, &subject, &mut *tx)
+        .await
+        .or_internal("Failed to get identity")?;
+
+    if link {
+        let user_id = session
+            .get::<UserInfo>("userinfo")
+            .ok()
+            .flatten()
+            .map(|user| user.id)
+            .ok_or(AppError::Unauthorized(
+                "Failed to get user data from session".to_string(),
+            ))?;
+        // Logging in with both accounts proves they belong to the same person, but if the
+        // identity already has its own user, that user's rides and events are only merged into
+        // this one once they confirm it.
+        match owner {
+            Some(owner) if owner == user_id => {}
+            Some(owner) => session
+                .insert(
+                    MERGE_KEY,
+                    PendingMerge {
+                        realm,
+                        subject,
+                        user_id: owner,
+                        into: user_id,
+                    },
+                )
+                .or_internal("Failed to start merge")?,
+            None => {
+                UserIdentity::insert_new(&realm, &subject, &user_id, &user_info.email, &mut *tx)
+                    .await
+                    .or_internal("Failed to link account")?;
+            }
+        }
+        tx.commit()
+            .await
+            .or_internal("Failed to commit transaction")?;
+        return Ok(HttpResponse::Found()
+            .append_header((header::LOCATION, "/"))
+            .finish());
+    }
+
+    // Linked identities log in as the user they were linked to, whose profile is left alone.
+    let user_id = match owner {
+        Some(owner) if owner != subject => owner,
+        _ => {
+            UserData::insert_new(
+                subject.clone(),
+                realm.clone(),
+                format!("{} {}", user_info.given_name, user_info.family_name)
+                    .trim_end()
+                    .to_string(),
+                user_info.email.clone(),
+                &mut *tx,
+            )
+            .await
+            .or_internal("Failed to add user to database")?;
+            UserIdentity::insert_new(&realm, &subject, &subject, &user_info.email, &mut *tx)
+                .await
+                .or_internal("Failed to add user to database")?;
+            subject
+        }
+    };
+    tx.commit()
+        .await
+        .or_internal("Failed to commit transaction")?;
+    user_info.id = user_id;
+
+    login_session(&session, user_info).or_internal("Failed to Authorize Session")?;
+
+    Ok(HttpResponse::Found()
+        .append_header((header::LOCATION, "/"))
+        .finish())
+}
+
+pub fn scope() -> Scope {
+    web::scope("/{provider}")
+        .service(login)
+        .service(link_account)
+        .service(auth)
+}
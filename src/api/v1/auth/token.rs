@@ -0,0 +1,102 @@
+use crate::app::AppState;
+use crate::auth::{hash_secret, CurrentUser, SessionAuth};
+use crate::db::token::{ApiToken, NewApiToken, TokenData, TokenScope};
+use crate::error::{AppError, AppResult, OrInternal};
+use actix_web::{delete, get, post, web, HttpResponse, Scope};
+use chrono::{TimeDelta, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use tracing::instrument;
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(create_token, get_tokens, delete_token),
+    components(schemas(ApiToken, NewApiToken, TokenData, TokenScope))
+)]
+pub(super) struct ApiDoc;
+
+#[utoipa::path(
+    request_body = TokenData,
+    responses(
+        (status = 200, description = "Create a personal API token. The token is only shown once.", body = NewApiToken),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[post("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn create_token(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    token: web::Json<TokenData>,
+) -> AppResult<HttpResponse> {
+    token.validate()?;
+
+    let secret = format!(
+        "rbt_{}",
+        Alphanumeric.sample_string(&mut rand::thread_rng(), 40)
+    );
+    let expires_at = Utc::now() + TimeDelta::days(token.expires_in_days.unwrap_or(90));
+    let info = ApiToken::insert_new(
+        &user.data.id,
+        &hash_secret(&secret),
+        &token,
+        expires_at,
+        &data.db,
+    )
+    .await
+    .or_internal("Failed to create token")?;
+    Ok(HttpResponse::Ok().json(NewApiToken {
+        token: secret,
+        info,
+    }))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get the current user's API tokens", body = [ApiToken]),
+        (status = 401, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn get_tokens(data: web::Data<AppState>, user: CurrentUser) -> AppResult<HttpResponse> {
+    let tokens = ApiToken::select_all(&user.data.id, &data.db)
+        .await
+        .or_internal("Failed to get tokens")?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[utoipa::path(
+    params(
+        ("token_id" = i32, Path, description = "ID of the Token to Revoke")
+    ),
+    responses(
+        (status = 200, description = "Revoke an API token"),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[delete("/{token_id}", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn delete_token(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<i32>,
+) -> AppResult<HttpResponse> {
+    ApiToken::delete(path.into_inner(), &user.data.id, &data.db)
+        .await
+        .or_internal("Failed to revoke token")?
+        .ok_or(AppError::NotFound("Token not found".to_string()))?;
+    Ok(HttpResponse::Ok().body("Token revoked"))
+}
+
+pub fn scope() -> Scope {
+    web::scope("/tokens")
+        .service(create_token)
+        .service(get_tokens)
+        .service(delete_token)
+}
@@ -1,24 +1,30 @@
-use crate::api::v1::auth::models::UserInfo;
-use crate::app::{ApiError, AppState, MultipleRiderChange, RedisJob};
+use crate::app::{AppState, MultipleRiderChange, RedisJob};
 use crate::db::car::{Car, CarData};
-use crate::{auth::SessionAuth, db::user::UserData};
-use actix_session::Session;
+use crate::db::ride_request::RideRequest;
+use crate::error::{AppError, AppResult, OrInternal};
+use crate::{
+    auth::{CurrentUser, SessionAuth},
+    db::user::UserData,
+};
 use actix_web::{
     delete, get, post, put,
     web::{self},
-    HttpResponse, Responder, Scope,
+    HttpResponse, Scope,
 };
 use sqlx::query;
 use utoipa::OpenApi;
 
 use log::error;
+use tracing::instrument;
 
 mod rider;
+mod transfer;
 
 #[derive(OpenApi)]
 #[openapi(
     nest(
         (path = "/{car_id}/rider", api = rider::ApiDoc),
+        (path = "/{car_id}/transfer", api = transfer::ApiDoc),
     ),
     paths(
         create_car,
@@ -43,56 +49,32 @@ pub struct ApiDoc;
     )
 )]
 #[post("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn create_car(
     data: web::Data<AppState>,
-    session: Session,
+    user: CurrentUser,
     car: web::Json<CarData>,
     path: web::Path<i32>,
-) -> impl Responder {
+) -> AppResult<HttpResponse> {
     let event_id: i32 = path.into_inner();
-    let user_id = match session.get::<UserInfo>("userinfo").ok().flatten() {
-        Some(user) => user.id,
-        None => {
-            return HttpResponse::Unauthorized().json(ApiError::from(
-                "Failed to get user data from session".to_string(),
-            ))
-        }
-    };
+    let user_id = user.data.id;
 
-    let other_cars = match Car::select_all(event_id, &data.db).await {
-        Ok(cars) => cars,
-        Err(err) => {
-            error!("{}", err);
-            return HttpResponse::InternalServerError().json(ApiError::from(
-                "Failed to get other cars for data validation".to_string(),
-            ));
-        }
-    };
-    if let Err(errs) = car.validate(&user_id, other_cars) {
-        return HttpResponse::BadRequest().json(ApiError::from(errs));
-    }
+    let other_cars = Car::select_all(event_id, &data.db)
+        .await
+        .or_internal("Failed to get other cars for data validation")?;
+    car.validate(&user_id, other_cars, &data.rules)?;
 
-    let mut tx = match data.db.begin().await {
-        Ok(tx) => tx,
-        Err(err) => {
-            error!("{}", err);
-            return HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to make SQL Transaction".to_string()));
-        }
-    };
+    let mut tx = data
+        .db
+        .begin()
+        .await
+        .or_internal("Failed to make SQL Transaction")?;
 
-    let record = match Car::insert_new(event_id, user_id, &car, &mut *tx).await {
-        Ok(car) => car,
-        Err(err) => {
-            error!("{}", err);
-            tx.rollback().await.unwrap();
-            return HttpResponse::InternalServerError().json(ApiError::from(
-                "Failed to create new car in database".to_string(),
-            ));
-        }
-    };
+    let record = Car::insert_new(event_id, user_id, &car, &mut *tx)
+        .await
+        .or_internal("Failed to create new car in database")?;
 
-    if let Err(err) = query!(
+    query!(
         r#"
         INSERT INTO rider (car_id, rider) SELECT $1, * FROM UNNEST($2::VARCHAR[])
         "#,
@@ -101,17 +83,15 @@ async fn create_car(
     )
     .execute(&mut *tx)
     .await
-    {
-        error!("{}", err);
-        tx.rollback().await.unwrap();
-        return HttpResponse::InternalServerError()
-            .json(ApiError::from("Failed to add riders to car".to_string()));
-    }
-    if let Err(err) = tx.commit().await {
-        error!("{}", err);
-        return HttpResponse::InternalServerError()
-            .json(ApiError::from("Failed to commit transaction".to_string()));
-    }
+    .or_internal("Failed to add riders to car")?;
+    let mut placed = car.riders.clone();
+    placed.push(record.driver.id.clone());
+    RideRequest::delete_many(event_id, &placed, &mut *tx)
+        .await
+        .or_internal("Failed to clear ride requests")?;
+    tx.commit()
+        .await
+        .or_internal("Failed to commit transaction")?;
     match data.redis.lock().map(|mut mutex| async move {
         mutex
             .insert_job(RedisJob::RiderUpdate(MultipleRiderChange {
@@ -129,7 +109,7 @@ async fn create_car(
         }
         Err(err) => error!("{}", err),
     }
-    HttpResponse::Ok().json(record.id)
+    Ok(HttpResponse::Ok().json(record.id))
 }
 
 #[utoipa::path(
@@ -143,16 +123,17 @@ async fn create_car(
     )
 )]
 #[get("/{car_id}", wrap = "SessionAuth")]
-async fn get_car(data: web::Data<AppState>, path: web::Path<(i32, i32)>) -> impl Responder {
+#[instrument(skip_all)]
+async fn get_car(
+    data: web::Data<AppState>,
+    path: web::Path<(i32, i32)>,
+) -> AppResult<HttpResponse> {
     let (event_id, car_id) = path.into_inner();
-    let result = Car::select_one(event_id, car_id, &data.db).await;
-
-    match result {
-        Ok(Some(car)) => HttpResponse::Ok().json(car),
-        Ok(None) => HttpResponse::NotFound().json(ApiError::from("Car not found".to_string())),
-        Err(_) => HttpResponse::InternalServerError()
-            .json(ApiError::from("Failed to get Car".to_string())),
-    }
+    let car = Car::select_one(event_id, car_id, &data.db)
+        .await
+        .or_internal("Failed to get Car")?
+        .ok_or(AppError::NotFound("Car not found".to_string()))?;
+    Ok(HttpResponse::Ok().json(car))
 }
 
 #[utoipa::path(
@@ -165,18 +146,13 @@ async fn get_car(data: web::Data<AppState>, path: web::Path<(i32, i32)>) -> impl
     )
 )]
 #[get("/", wrap = "SessionAuth")]
-async fn get_all_cars(data: web::Data<AppState>, path: web::Path<i32>) -> impl Responder {
+#[instrument(skip_all)]
+async fn get_all_cars(data: web::Data<AppState>, path: web::Path<i32>) -> AppResult<HttpResponse> {
     let event_id: i32 = path.into_inner();
-    let result = Car::select_all(event_id, &data.db).await;
-
-    match result {
-        Ok(cars) => HttpResponse::Ok().json(cars),
-        Err(e) => {
-            error!("{}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to get cars".to_string()))
-        }
-    }
+    let cars = Car::select_all(event_id, &data.db)
+        .await
+        .or_internal("Failed to get cars")?;
+    Ok(HttpResponse::Ok().json(cars))
 }
 
 #[utoipa::path(
@@ -192,80 +168,51 @@ async fn get_all_cars(data: web::Data<AppState>, path: web::Path<i32>) -> impl R
     )
 )]
 #[put("/{car_id}", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn update_car(
     data: web::Data<AppState>,
-    session: Session,
+    user: CurrentUser,
     path: web::Path<(i32, i32)>,
     car: web::Json<CarData>,
-) -> impl Responder {
+) -> AppResult<HttpResponse> {
     let (event_id, car_id) = path.into_inner();
-    let user_id = match session.get::<UserInfo>("userinfo").ok().flatten() {
-        Some(user) => user.id,
-        None => {
-            return HttpResponse::Unauthorized().json(ApiError::from(
-                "Failed to get user data from session".to_string(),
-            ))
-        }
-    };
+    let user_id = user.data.id;
 
-    let other_cars = match Car::select_all(event_id, &data.db).await {
-        Ok(cars) => cars.into_iter().filter(|car| car.id != car_id).collect(),
-        Err(err) => {
-            error!("{}", err);
-            return HttpResponse::InternalServerError().json(ApiError::from(
-                "Failed to get other cars for data validation".to_string(),
-            ));
-        }
-    };
-    if let Err(errs) = car.validate(&user_id, other_cars) {
-        return HttpResponse::BadRequest().json(ApiError::from(errs));
-    }
-
-    let mut tx = match data.db.begin().await {
-        Ok(tx) => tx,
-        Err(err) => {
-            error!("{}", err);
-            return HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to make SQL Transaction".to_string()));
-        }
-    };
+    let other_cars = Car::select_all(event_id, &data.db)
+        .await
+        .or_internal("Failed to get other cars for data validation")?
+        .into_iter()
+        .filter(|car| car.id != car_id)
+        .collect();
+    car.validate(&user_id, other_cars, &data.rules)?;
 
-    let updated = Car::update(car_id, event_id, user_id, &car, &mut *tx).await;
+    // Dropping the transaction on an early return rolls it back.
+    let mut tx = data
+        .db
+        .begin()
+        .await
+        .or_internal("Failed to make SQL Transaction")?;
 
-    match updated {
-        Ok(Some(_)) => {}
-        Ok(None) => {
-            tx.rollback().await.unwrap();
-            return HttpResponse::NotFound().json(ApiError::from(
-                "Car not found or you are not the driver.".to_string(),
-            ));
-        }
-        Err(err) => {
-            tx.rollback().await.unwrap();
-            error!("{}", err);
-            return HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to update car".to_string()));
-        }
-    }
+    Car::update(car_id, event_id, user_id, &car, &mut *tx)
+        .await
+        .or_internal("Failed to update car")?
+        .ok_or(AppError::NotFound(
+            "Car not found or you are not the driver.".to_string(),
+        ))?;
 
     // Used for sending pings
-    let current_riders: Vec<String> = match query!(
+    let current_riders: Vec<String> = query!(
         r#"DELETE FROM rider WHERE car_id = $1 RETURNING rider"#,
         car_id
     )
     .fetch_all(&mut *tx)
     .await
-    {
-        Ok(riders) => riders.iter().map(|record| record.rider.clone()).collect(),
-        Err(err) => {
-            error!("{}", err);
-            tx.rollback().await.unwrap();
-            return HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to remove old riders".to_string()));
-        }
-    };
+    .or_internal("Failed to remove old riders")?
+    .into_iter()
+    .map(|record| record.rider)
+    .collect();
 
-    if let Err(err) = query!(
+    query!(
         r#"
         INSERT INTO rider (car_id, rider) SELECT $1, * FROM UNNEST($2::VARCHAR[])
         "#,
@@ -274,17 +221,13 @@ async fn update_car(
     )
     .execute(&mut *tx)
     .await
-    {
-        error!("{}", err);
-        tx.rollback().await.unwrap();
-        return HttpResponse::InternalServerError()
-            .json(ApiError::from("Failed to add new riders".to_string()));
-    }
-    if let Err(err) = tx.commit().await {
-        error!("{}", err);
-        return HttpResponse::InternalServerError()
-            .json(ApiError::from("Failed to commit transaction".to_string()));
-    }
+    .or_internal("Failed to add new riders")?;
+    RideRequest::delete_many(event_id, &car.riders, &mut *tx)
+        .await
+        .or_internal("Failed to clear ride requests")?;
+    tx.commit()
+        .await
+        .or_internal("Failed to commit transaction")?;
 
     match data.redis.lock().map(|mut mutex| async move {
         mutex
@@ -303,7 +246,7 @@ async fn update_car(
         }
         Err(err) => error!("{}", err),
     }
-    HttpResponse::Ok().body("Car updated successfully")
+    Ok(HttpResponse::Ok().body("Car updated successfully"))
 }
 
 #[utoipa::path(
@@ -318,34 +261,21 @@ async fn update_car(
     )
 )]
 #[delete("/{car_id}", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn delete_car(
     data: web::Data<AppState>,
-    session: Session,
+    user: CurrentUser,
     path: web::Path<(i32, i32)>,
-) -> impl Responder {
+) -> AppResult<HttpResponse> {
     let (event_id, car_id) = path.into_inner();
-    let user_id = match session.get::<UserInfo>("userinfo").ok().flatten() {
-        Some(user) => user.id,
-        None => {
-            return HttpResponse::Unauthorized().json(ApiError::from(
-                "Failed to get user data from session".to_string(),
-            ))
-        }
-    };
 
-    let deleted = Car::delete(car_id, event_id, user_id, &data.db).await;
-
-    match deleted {
-        Ok(Some(_)) => HttpResponse::Ok().json("Car deleted"),
-        Ok(None) => HttpResponse::NotFound().json(ApiError::from(
+    Car::delete(car_id, event_id, user.data.id, &data.db)
+        .await
+        .or_internal("Failed to delete car")?
+        .ok_or(AppError::NotFound(
             "Car not found or you are not the driver.".to_string(),
-        )),
-        Err(err) => {
-            error!("{}", err);
-            HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to delete car".to_string()))
-        }
-    }
+        ))?;
+    Ok(HttpResponse::Ok().json("Car deleted"))
 }
 
 pub fn scope() -> Scope {
@@ -356,4 +286,5 @@ pub fn scope() -> Scope {
         .service(update_car)
         .service(delete_car)
         .service(rider::scope())
+        .service(transfer::scope())
 }
@@ -1,18 +1,23 @@
-use crate::app::{ApiError, AppState, SimpleRiderChange};
-use crate::auth::SessionAuth;
+use crate::app::RedisJob;
+use crate::app::{AppState, MultipleRiderChange, SimpleRiderChange};
+use crate::auth::{CurrentUser, SessionAuth};
 use crate::db::car::Car;
-use crate::{api::v1::event::UserInfo, app::RedisJob};
-use actix_session::Session;
+use crate::db::ride_request::{InviteData, RideRequest};
+use crate::error::{AppError, AppResult, OrInternal};
 use actix_web::{
     delete, post,
     web::{self},
-    HttpResponse, Responder, Scope,
+    HttpResponse, Scope,
 };
 use log::error;
+use tracing::instrument;
 use utoipa::OpenApi;
 
 #[derive(OpenApi)]
-#[openapi(paths(create_rider, delete_rider))]
+#[openapi(
+    paths(create_rider, invite_rider, delete_rider),
+    components(schemas(InviteData))
+)]
 pub struct ApiDoc;
 
 #[utoipa::path(
@@ -28,66 +33,55 @@ pub struct ApiDoc;
     )
 )]
 #[post("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn create_rider(
     data: web::Data<AppState>,
-    session: Session,
+    user: CurrentUser,
     path: web::Path<(i32, i32)>,
-) -> impl Responder {
+) -> AppResult<HttpResponse> {
     let (event_id, car_id) = path.into_inner();
-    let user_id = match session.get::<UserInfo>("userinfo").ok().flatten() {
-        Some(user) => user.id,
-        None => {
-            return HttpResponse::Unauthorized().json(ApiError::from(
-                "Failed to get user data from session".to_string(),
-            ))
-        }
-    };
+    let user_id = user.data.id;
 
-    match Car::select_one(event_id, car_id, &data.db).await {
-        Ok(Some(car)) => {
-            if car.max_capacity <= car.riders.map(|riders| riders.len()).unwrap_or(0) as i32 {
-                return HttpResponse::BadRequest().json(ApiError::from("Car is full.".to_string()));
-            }
-        }
-        Ok(None) => {
-            return HttpResponse::BadRequest()
-                .json(ApiError::from("Car does not exist.".to_string()))
-        }
-        Err(err) => {
-            error!("{}", err);
-            return HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to check car capacity".to_string()));
-        }
+    let car = Car::select_one(event_id, car_id, &data.db)
+        .await
+        .or_internal("Failed to check car capacity")?
+        .ok_or(AppError::BadRequest("Car does not exist.".to_string()))?;
+    if car.max_capacity <= car.riders.map(|riders| riders.len()).unwrap_or(0) as i32 {
+        return Err(AppError::BadRequest("Car is full.".to_string()));
     }
 
-    match Car::user_in_car(event_id, &user_id, &data.db).await {
-        Ok(false) => {}
-        Ok(true) => {
-            return HttpResponse::BadRequest()
-                .json(ApiError::from("User is already in a car.".to_string()))
-        }
-        Err(err) => {
-            error!("{}", err);
-            return HttpResponse::InternalServerError().json(ApiError::from(
-                "Failed to check user's occupancy in other cars".to_string(),
-            ));
-        }
+    if Car::user_in_car(event_id, &user_id, &data.db)
+        .await
+        .or_internal("Failed to check user's occupancy in other cars")?
+    {
+        return Err(AppError::BadRequest(
+            "User is already in a car.".to_string(),
+        ));
     }
 
-    if let Err(err) = sqlx::query!(
+    let mut tx = data
+        .db
+        .begin()
+        .await
+        .or_internal("Failed to make SQL Transaction")?;
+
+    sqlx::query!(
         r#"
         INSERT INTO rider (car_id, rider) VALUES ($1, $2)
         "#,
         car_id,
         user_id
     )
-    .execute(&data.db)
+    .execute(&mut *tx)
     .await
-    {
-        error!("{}", err);
-        return HttpResponse::InternalServerError()
-            .json(ApiError::from("Failed to join ride".to_string()));
-    };
+    .or_internal("Failed to join ride")?;
+
+    RideRequest::delete(event_id, user_id.clone(), &mut *tx)
+        .await
+        .or_internal("Failed to clear ride request")?;
+    tx.commit()
+        .await
+        .or_internal("Failed to commit transaction")?;
 
     match data.redis.lock().map(|mut mutex| async move {
         mutex
@@ -105,7 +99,107 @@ async fn create_rider(
         }
         Err(err) => error!("{}", err),
     }
-    HttpResponse::Ok().body("Joined Car")
+    Ok(HttpResponse::Ok().body("Joined Car"))
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event this Rider Applies To"),
+        ("car_id" = i32, Path, description = "ID of the Car this Rider Applies To")
+    ),
+    responses(
+        (status = 200, description = "Place someone who needs a ride into your car. Must be done by driver."),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 409, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[post("/invite", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn invite_rider(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<(i32, i32)>,
+    invite: web::Json<InviteData>,
+) -> AppResult<HttpResponse> {
+    let (event_id, car_id) = path.into_inner();
+    let user_id = user.data.id;
+
+    let mut tx = data
+        .db
+        .begin()
+        .await
+        .or_internal("Failed to make SQL Transaction")?;
+
+    // Locked so two invites can't both take the last seat.
+    Car::lock(car_id, &mut *tx)
+        .await
+        .or_internal("Failed to lock car")?;
+    let car = Car::select_one(event_id, car_id, &mut *tx)
+        .await
+        .or_internal("Failed to check car capacity")?
+        .filter(|car| car.driver.id == user_id)
+        .ok_or(AppError::NotFound(
+            "Car not found or you are not the driver.".to_string(),
+        ))?;
+    let riders = car.riders.unwrap_or_default();
+    if car.max_capacity <= riders.len() as i32 {
+        return Err(AppError::Conflict("Car is full.".to_string()));
+    }
+    let old_riders: Vec<String> = riders.into_iter().map(|rider| rider.id).collect();
+
+    RideRequest::delete(event_id, invite.rider.clone(), &mut *tx)
+        .await
+        .or_internal("Failed to clear ride request")?
+        .ok_or(AppError::BadRequest(
+            "User is not looking for a ride.".to_string(),
+        ))?;
+
+    if Car::user_in_car(event_id, &invite.rider, &mut *tx)
+        .await
+        .or_internal("Failed to check user's occupancy in other cars")?
+    {
+        return Err(AppError::BadRequest(
+            "User is already in a car.".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO rider (car_id, rider) VALUES ($1, $2)
+        "#,
+        car_id,
+        invite.rider
+    )
+    .execute(&mut *tx)
+    .await
+    .or_internal("Failed to add rider")?;
+    tx.commit()
+        .await
+        .or_internal("Failed to commit transaction")?;
+
+    let mut new_riders = old_riders.clone();
+    new_riders.push(invite.rider.clone());
+    match data.redis.lock().map(|mut mutex| async move {
+        mutex
+            .insert_job(RedisJob::RiderUpdate(MultipleRiderChange {
+                event_id,
+                car_id,
+                old_riders,
+                new_riders,
+            }))
+            .await
+    }) {
+        Ok(res) => {
+            if let Err(err) = res.await {
+                error!("{}", err);
+            }
+        }
+        Err(err) => error!("{}", err),
+    }
+    Ok(HttpResponse::Ok().body("Rider added"))
 }
 
 #[utoipa::path(
@@ -120,33 +214,23 @@ async fn create_rider(
     )
 )]
 #[delete("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn delete_rider(
     data: web::Data<AppState>,
-    session: Session,
+    user: CurrentUser,
     path: web::Path<(i32, i32)>,
-) -> impl Responder {
+) -> AppResult<HttpResponse> {
     let (event_id, car_id) = path.into_inner();
-    let user_id = match session.get::<UserInfo>("userinfo").ok().flatten() {
-        Some(user) => user.id,
-        None => {
-            return HttpResponse::Unauthorized().json(ApiError::from(
-                "Failed to get user data from session".to_string(),
-            ))
-        }
-    };
+    let user_id = user.data.id;
 
-    if let Err(err) = sqlx::query!(
+    sqlx::query!(
         "DELETE FROM rider WHERE car_id = $1 AND rider = $2",
         car_id,
         user_id
     )
     .execute(&data.db)
     .await
-    {
-        error!("{}", err);
-        return HttpResponse::InternalServerError()
-            .json(ApiError::from("Failed to delete rider".to_string()));
-    }
+    .or_internal("Failed to delete rider")?;
 
     match data.redis.lock().map(|mut mutex| async move {
         mutex
@@ -165,11 +249,12 @@ async fn delete_rider(
         Err(err) => error!("{}", err),
     }
 
-    HttpResponse::Ok().body("Rider deleted")
+    Ok(HttpResponse::Ok().body("Rider deleted"))
 }
 
 pub fn scope() -> Scope {
     web::scope("/{car_id}/rider")
         .service(create_rider)
+        .service(invite_rider)
         .service(delete_rider)
 }
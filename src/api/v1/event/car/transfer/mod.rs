@@ -0,0 +1,285 @@
+use crate::app::RedisJob;
+use crate::app::{AppState, DriverChange, SimpleRiderChange};
+use crate::auth::{CurrentUser, SessionAuth};
+use crate::db::car::Car;
+use crate::db::ride_request::RideRequest;
+use crate::db::transfer::{CarTransfer, TransferData};
+use crate::db::user::UserData;
+use crate::error::{AppError, AppResult, OrInternal};
+use actix_web::{
+    delete, get, post,
+    web::{self},
+    HttpResponse, Scope,
+};
+use log::error;
+use tracing::instrument;
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(create_transfer, get_transfer, accept_transfer, delete_transfer),
+    components(schemas(CarTransfer, TransferData))
+)]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event this Transfer Applies To"),
+        ("car_id" = i32, Path, description = "ID of the Car being Transferred")
+    ),
+    responses(
+        (status = 200, description = "Offer the car to another user. Must be done by driver."),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[post("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn create_transfer(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<(i32, i32)>,
+    transfer: web::Json<TransferData>,
+) -> AppResult<HttpResponse> {
+    let (event_id, car_id) = path.into_inner();
+    let user_id = user.data.id;
+
+    if transfer.new_driver == user_id {
+        return Err(AppError::BadRequest(
+            "You are already the driver.".to_string(),
+        ));
+    }
+
+    let car = Car::select_one(event_id, car_id, &data.db)
+        .await
+        .or_internal("Failed to get Car")?
+        .ok_or(AppError::NotFound("Car not found".to_string()))?;
+
+    UserData::select_one(transfer.new_driver.clone(), &data.db)
+        .await
+        .or_internal("Failed to get user")?
+        .ok_or(AppError::BadRequest("User does not exist.".to_string()))?;
+
+    // Riders of this car may take it over, anyone in another car may not.
+    let is_rider = car
+        .riders
+        .unwrap_or_default()
+        .iter()
+        .any(|rider| rider.id == transfer.new_driver);
+    if !is_rider
+        && Car::user_in_car(event_id, &transfer.new_driver, &data.db)
+            .await
+            .or_internal("Failed to check user's occupancy in other cars")?
+    {
+        return Err(AppError::BadRequest(
+            "User is already in another car.".to_string(),
+        ));
+    }
+
+    CarTransfer::insert_new(
+        event_id,
+        car_id,
+        user_id,
+        transfer.new_driver.clone(),
+        &data.db,
+    )
+    .await
+    .or_internal("Failed to offer car")?
+    .ok_or(AppError::NotFound(
+        "Car not found or you are not the driver.".to_string(),
+    ))?;
+
+    match data.redis.lock().map(|mut mutex| async move {
+        mutex
+            .insert_job(RedisJob::TransferOffer(SimpleRiderChange {
+                event_id,
+                car_id,
+                rider_id: transfer.new_driver.clone(),
+            }))
+            .await
+    }) {
+        Ok(res) => {
+            if let Err(err) = res.await {
+                error!("{}", err);
+            }
+        }
+        Err(err) => error!("{}", err),
+    }
+    Ok(HttpResponse::Ok().body("Car offered"))
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event this Transfer Applies To"),
+        ("car_id" = i32, Path, description = "ID of the Car being Transferred")
+    ),
+    responses(
+        (status = 200, description = "Get the pending transfer for a car", body = CarTransfer),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn get_transfer(
+    data: web::Data<AppState>,
+    path: web::Path<(i32, i32)>,
+) -> AppResult<HttpResponse> {
+    let (event_id, car_id) = path.into_inner();
+    let transfer = CarTransfer::select_one(event_id, car_id, &data.db)
+        .await
+        .or_internal("Failed to get transfer")?
+        .ok_or(AppError::NotFound(
+            "No pending transfer for this car".to_string(),
+        ))?;
+    Ok(HttpResponse::Ok().json(transfer))
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event this Transfer Applies To"),
+        ("car_id" = i32, Path, description = "ID of the Car being Transferred")
+    ),
+    responses(
+        (status = 200, description = "Accept a transfer and become the driver of the car."),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[post("/accept", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn accept_transfer(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<(i32, i32)>,
+) -> AppResult<HttpResponse> {
+    let (event_id, car_id) = path.into_inner();
+    let user_id = user.data.id;
+
+    // Dropping the transaction on an early return rolls it back.
+    let mut tx = data
+        .db
+        .begin()
+        .await
+        .or_internal("Failed to make SQL Transaction")?;
+
+    // Locked so the driver read here is the one being replaced.
+    Car::lock(car_id, &mut *tx)
+        .await
+        .or_internal("Failed to lock car")?;
+    let old_driver = Car::select_one(event_id, car_id, &mut *tx)
+        .await
+        .or_internal("Failed to get Car")?
+        .ok_or(AppError::NotFound("Car not found".to_string()))?
+        .driver
+        .id;
+
+    CarTransfer::accept(car_id, &user_id, &mut *tx)
+        .await
+        .or_internal("Failed to accept transfer")?
+        .ok_or(AppError::NotFound(
+            "This car has not been offered to you.".to_string(),
+        ))?;
+
+    // The new driver stops being a rider of this car.
+    sqlx::query!(
+        "DELETE FROM rider WHERE car_id = $1 AND rider = $2",
+        car_id,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .or_internal("Failed to remove rider")?;
+
+    if Car::user_in_car(event_id, &user_id, &mut *tx)
+        .await
+        .or_internal("Failed to check user's occupancy in other cars")?
+    {
+        return Err(AppError::BadRequest(
+            "User is already in another car.".to_string(),
+        ));
+    }
+
+    RideRequest::delete(event_id, user_id.clone(), &mut *tx)
+        .await
+        .or_internal("Failed to clear ride request")?;
+
+    Car::update_driver(car_id, event_id, &user_id, &mut *tx)
+        .await
+        .or_internal("Failed to change driver")?
+        .ok_or(AppError::NotFound("Car not found".to_string()))?;
+
+    let riders = Car::select_one(event_id, car_id, &mut *tx)
+        .await
+        .or_internal("Failed to get Car")?
+        .and_then(|car| car.riders)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|rider| rider.id)
+        .collect();
+
+    tx.commit()
+        .await
+        .or_internal("Failed to commit transaction")?;
+
+    match data.redis.lock().map(|mut mutex| async move {
+        mutex
+            .insert_job(RedisJob::DriverChange(DriverChange {
+                event_id,
+                car_id,
+                old_driver,
+                riders,
+            }))
+            .await
+    }) {
+        Ok(res) => {
+            if let Err(err) = res.await {
+                error!("{}", err);
+            }
+        }
+        Err(err) => error!("{}", err),
+    }
+    Ok(HttpResponse::Ok().body("Transfer accepted"))
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event this Transfer Applies To"),
+        ("car_id" = i32, Path, description = "ID of the Car being Transferred")
+    ),
+    responses(
+        (status = 200, description = "Cancel or decline a transfer. Must be done by driver or offered user."),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[delete("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn delete_transfer(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<(i32, i32)>,
+) -> AppResult<HttpResponse> {
+    let (event_id, car_id) = path.into_inner();
+
+    CarTransfer::delete(event_id, car_id, user.data.id, &data.db)
+        .await
+        .or_internal("Failed to cancel transfer")?
+        .ok_or(AppError::NotFound(
+            "Transfer not found or you are not involved in it.".to_string(),
+        ))?;
+    Ok(HttpResponse::Ok().body("Transfer cancelled"))
+}
+
+pub fn scope() -> Scope {
+    web::scope("/{car_id}/transfer")
+        .service(create_transfer)
+        .service(get_transfer)
+        .service(accept_transfer)
+        .service(delete_transfer)
+}
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+
+use crate::app::{AppState, MultipleRiderChange, RedisJob};
+use crate::auth::{CurrentUser, SessionAuth};
+use crate::db::car::Car;
+use crate::db::event::Event;
+use crate::db::ride_request::RideRequest;
+use crate::error::{AppError, AppResult, OrInternal};
+use crate::matching::{self, CarAssignment, MatchProposal};
+use actix_web::{
+    get, post,
+    web::{self},
+    HttpResponse, Scope,
+};
+use log::error;
+use sqlx::query;
+use tracing::instrument;
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(preview_match, apply_match),
+    components(schemas(MatchProposal, CarAssignment))
+)]
+pub struct ApiDoc;
+
+/// Fails unless the user is allowed to organize the event: its creator, or an admin, like
+/// exporting and importing.
+async fn check_organizer(data: &AppState, event_id: i32, user: &CurrentUser) -> AppResult<()> {
+    let event = Event::select_one(event_id, &data.db)
+        .await
+        .or_internal("Failed to get event")?
+        .ok_or(AppError::NotFound("Event not found".to_string()))?;
+    if event.creator.id != user.data.id && !user.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only the event creator or an admin can match riders.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event to Match Riders For")
+    ),
+    responses(
+        (status = 200, description = "Propose seats for everyone who needs a ride. Nothing is saved.", body = MatchProposal),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn preview_match(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<i32>,
+) -> AppResult<HttpResponse> {
+    let event_id = path.into_inner();
+
+    check_organizer(&data, event_id, &user).await?;
+
+    let cars = Car::select_all(event_id, &data.db)
+        .await
+        .or_internal("Failed to get cars")?;
+    let requests = RideRequest::select_all(event_id, &data.db)
+        .await
+        .or_internal("Failed to get ride requests")?;
+
+    Ok(HttpResponse::Ok().json(matching::propose(&cars, &requests)))
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event to Match Riders For")
+    ),
+    request_body = MatchProposal,
+    responses(
+        (status = 200, description = "Apply a proposal in one transaction."),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 409, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[post("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn apply_match(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<i32>,
+    proposal: web::Json<MatchProposal>,
+) -> AppResult<HttpResponse> {
+    let event_id = path.into_inner();
+
+    check_organizer(&data, event_id, &user).await?;
+
+    let mut seen = HashSet::new();
+    for rider in proposal.assignments.iter().flat_map(|a| a.riders.iter()) {
+        if !seen.insert(rider) {
+            return Err(AppError::BadRequest(format!(
+                "{} is assigned to more than one car.",
+                rider
+            )));
+        }
+    }
+
+    // Dropping the transaction on an early return rolls it back.
+    let mut tx = data
+        .db
+        .begin()
+        .await
+        .or_internal("Failed to make SQL Transaction")?;
+
+    let mut changes = Vec::new();
+    for assignment in proposal.assignments.iter() {
+        // Cars and requests may have changed since the preview, so everything the proposal was
+        // based on is checked again under the car's lock.
+        Car::lock(assignment.car_id, &mut *tx)
+            .await
+            .or_internal("Failed to lock Car")?;
+        let car = Car::select_one(event_id, assignment.car_id, &mut *tx)
+            .await
+            .or_internal("Failed to get Car")?
+            .ok_or(AppError::BadRequest(format!(
+                "Car {} does not exist.",
+                assignment.car_id
+            )))?;
+        let old_riders: Vec<String> = car
+            .riders
+            .unwrap_or_default()
+            .into_iter()
+            .map(|rider| rider.id)
+            .collect();
+        if old_riders.len() + assignment.riders.len() > car.max_capacity as usize {
+            return Err(AppError::BadRequest(format!(
+                "Car {} does not have enough open seats.",
+                car.id
+            )));
+        }
+
+        // Riders must still be waiting for a ride, otherwise the proposal is stale.
+        let removed = RideRequest::delete_many(event_id, &assignment.riders, &mut *tx)
+            .await
+            .or_internal("Failed to clear ride requests")?;
+        if removed.len() != assignment.riders.len() {
+            return Err(AppError::Conflict(
+                "Some riders no longer need a ride. Preview the match again.".to_string(),
+            ));
+        }
+        if let Some(window) = removed.iter().find(|window| {
+            !matching::in_window(
+                &car.departure_time,
+                window.earliest_departure,
+                window.latest_departure,
+            )
+        }) {
+            return Err(AppError::Conflict(format!(
+                "Car {} no longer leaves when {} can. Preview the match again.",
+                car.id, window.rider
+            )));
+        }
+        for rider in assignment.riders.iter() {
+            if Car::user_in_car(event_id, rider, &mut *tx)
+                .await
+                .or_internal("Failed to check user's occupancy in other cars")?
+            {
+                return Err(AppError::BadRequest(format!(
+                    "{} is already in another car or is a driver.",
+                    rider
+                )));
+            }
+        }
+
+        query!(
+            r#"
+            INSERT INTO rider (car_id, rider) SELECT $1, * FROM UNNEST($2::VARCHAR[])
+            "#,
+            car.id,
+            &assignment.riders
+        )
+        .execute(&mut *tx)
+        .await
+        .or_internal("Failed to add riders to car")?;
+
+        let mut new_riders = old_riders.clone();
+        new_riders.extend(assignment.riders.iter().cloned());
+        changes.push(MultipleRiderChange {
+            event_id,
+            car_id: car.id,
+            old_riders,
+            new_riders,
+        });
+    }
+
+    tx.commit()
+        .await
+        .or_internal("Failed to commit transaction")?;
+
+    for change in changes {
+        match data
+            .redis
+            .lock()
+            .map(|mut mutex| async move { mutex.insert_job(RedisJob::RiderUpdate(change)).await })
+        {
+            Ok(res) => {
+                if let Err(err) = res.await {
+                    error!("{}", err);
+                }
+            }
+            Err(err) => error!("{}", err),
+        }
+    }
+    Ok(HttpResponse::Ok().body("Riders matched"))
+}
+
+pub fn scope() -> Scope {
+    web::scope("/{event_id}/match")
+        .service(preview_match)
+        .service(apply_match)
+}
@@ -1,39 +1,64 @@
 use crate::{
-    api::v1::auth::models::UserInfo,
-    app::ApiError,
-    db::event::{Event, EventData},
+    db::{
+        car::Car,
+        event::{Event, EventData},
+        ride_request::RideRequest,
+        stats::{DepartureWindow, EventSummary},
+    },
+    error::{AppError, AppResult, OrInternal},
+    export::{self, ExportFormat},
+    import::{self, ImportCar, ImportError, ImportEvent, ImportFormat, ImportResult},
 };
-use actix_session::Session;
 use actix_web::{
-    delete, get, post, put,
+    delete, get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    post, put,
     web::{self},
-    HttpResponse, Responder, Scope,
+    HttpResponse, Scope,
 };
 use log::error;
 use serde::Deserialize;
 
-use crate::app::AppState;
-use crate::auth::SessionAuth;
+use crate::app::{AppState, MultipleRiderChange, RedisJob};
+use crate::auth::{CurrentUser, SessionAuth};
 
+use tracing::instrument;
 use utoipa::OpenApi;
 
 use crate::db::user::UserData;
 
 mod car;
+mod matching;
+mod request;
 
 #[derive(OpenApi)]
 #[openapi(
     nest(
         (path = "/{event_id}/car", api = car::ApiDoc),
+        (path = "/{event_id}/request", api = request::ApiDoc),
+        (path = "/{event_id}/match", api = matching::ApiDoc),
     ),
     paths(
         create_event,
         get_event,
         get_all_events,
+        get_event_summary,
+        export_event,
+        import_events,
+        import_cars,
         update_event,
         delete_event
     ),
-    components(schemas(Event, EventData, UserData))
+    components(schemas(
+        Event,
+        EventData,
+        EventSummary,
+        DepartureWindow,
+        ImportEvent,
+        ImportCar,
+        ImportResult,
+        UserData
+    ))
 )]
 pub(super) struct ApiDoc;
 
@@ -46,56 +71,228 @@ pub(super) struct ApiDoc;
     )
 )]
 #[post("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn create_event(
     data: web::Data<AppState>,
-    session: Session,
+    user: CurrentUser,
     event: web::Json<EventData>,
-) -> impl Responder {
-    let user_id = match session.get::<UserInfo>("userinfo").ok().flatten() {
-        Some(user) => user.id,
-        None => {
-            return HttpResponse::Unauthorized().json(ApiError::from(
-                "Failed to get user data from session".to_string(),
-            ))
-        }
-    };
+) -> AppResult<HttpResponse> {
+    event.validate(&data.rules)?;
+    let record = Event::insert_new(&event, user.data.id, &data.db)
+        .await
+        .or_internal("Failed to create event")?;
+    Ok(HttpResponse::Ok().json(record.id))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get event specified by ID", body = Event),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[get("/{event_id}", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn get_event(data: web::Data<AppState>, path: web::Path<i32>) -> AppResult<HttpResponse> {
+    let event = find_event(path.into_inner(), &data).await?;
+    Ok(HttpResponse::Ok().json(event))
+}
 
-    if let Err(errs) = event.validate() {
-        return HttpResponse::BadRequest().json(ApiError::from(errs));
+async fn find_event(event_id: i32, data: &AppState) -> AppResult<Event> {
+    Event::select_one(event_id, &data.db)
+        .await
+        .or_internal("Failed to get event")?
+        .ok_or(AppError::NotFound("Event not found".to_string()))
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event to Summarize")
+    ),
+    responses(
+        (status = 200, description = "Get seat totals for an event", body = EventSummary),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[get("/{event_id}/summary", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn get_event_summary(
+    data: web::Data<AppState>,
+    path: web::Path<i32>,
+) -> AppResult<HttpResponse> {
+    let event_id = path.into_inner();
+    find_event(event_id, &data).await?;
+
+    let cars = Car::select_all(event_id, &data.db)
+        .await
+        .or_internal("Failed to get cars")?;
+    let unplaced = RideRequest::select_all(event_id, &data.db)
+        .await
+        .or_internal("Failed to get ride requests")?
+        .len() as i32;
+
+    Ok(HttpResponse::Ok().json(EventSummary::from_cars(event_id, &cars, unplaced)))
+}
+
+#[derive(Deserialize)]
+struct ExportQueryParams {
+    format: Option<ExportFormat>,
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event to Export"),
+        ("format" = Option<String>, Query, description = "Either csv (default) or json")
+    ),
+    responses(
+        (status = 200, description = "Download the car roster for an event. Creator or admin only."),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[get("/{event_id}/export", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn export_event(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<i32>,
+    params: web::Query<ExportQueryParams>,
+) -> AppResult<HttpResponse> {
+    let event_id = path.into_inner();
+
+    let event = find_event(event_id, &data).await?;
+    if event.creator.id != user.data.id && !user.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only the event creator can export the roster.".to_string(),
+        ));
     }
-    let result = Event::insert_new(&event, user_id, &data.db).await;
-
-    match result {
-        Ok(record) => HttpResponse::Ok().json(record.id),
-        Err(err) => {
-            error!("{}", err);
-            HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to create event".to_string()))
+
+    let cars = Car::select_all(event_id, &data.db)
+        .await
+        .or_internal("Failed to get cars")?;
+
+    let format = params.format.unwrap_or_default();
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "event-{}.{}",
+                event_id,
+                format.extension()
+            ))],
+        });
+    Ok(match format {
+        ExportFormat::Csv => response.streaming(export::csv_stream(cars)),
+        ExportFormat::Json => response.streaming(export::json_stream(cars)),
+    })
+}
+
+#[derive(Deserialize)]
+struct ImportQueryParams {
+    format: Option<ImportFormat>,
+}
+
+async fn finish_import(
+    data: &AppState,
+    result: Result<(ImportResult, Vec<MultipleRiderChange>), ImportError>,
+) -> AppResult<HttpResponse> {
+    let (result, changes) = result?;
+    for change in changes {
+        match data
+            .redis
+            .lock()
+            .map(|mut mutex| async move { mutex.insert_job(RedisJob::RiderUpdate(change)).await })
+        {
+            Ok(res) => {
+                if let Err(err) = res.await {
+                    error!("{}", err);
+                }
+            }
+            Err(err) => error!("{}", err),
         }
     }
+    Ok(HttpResponse::Ok().json(result))
 }
 
 #[utoipa::path(
+    params(
+        ("format" = Option<String>, Query, description = "Either csv (default) or json")
+    ),
+    request_body(content = [ImportEvent], description = "Events with their cars, as JSON or CSV rows"),
     responses(
-        (status = 200, description = "Get event specified by ID", body = Event),
+        (status = 200, description = "Import events and cars. Admin only. Nothing is written unless every row is valid.", body = ImportResult),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 500, body = ApiError),
+    )
+)]
+#[post("/import", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn import_events(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    params: web::Query<ImportQueryParams>,
+    body: web::Bytes,
+) -> AppResult<HttpResponse> {
+    if !user.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only admins can import events.".to_string(),
+        ));
+    }
+
+    let events = import::parse_events(params.format.unwrap_or_default(), &body)?;
+    finish_import(
+        &data,
+        import::import(events, user.data.id, &data.rules, &data.db).await,
+    )
+    .await
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event to Import Cars Into"),
+        ("format" = Option<String>, Query, description = "Either csv (default) or json")
+    ),
+    request_body(content = [ImportCar], description = "Cars, as JSON or CSV rows"),
+    responses(
+        (status = 200, description = "Import cars into an event. Creator or admin only. Nothing is written unless every row is valid.", body = ImportResult),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
         (status = 404, body = ApiError),
         (status = 500, body = ApiError),
     )
 )]
-#[get("/{event_id}", wrap = "SessionAuth")]
-async fn get_event(data: web::Data<AppState>, path: web::Path<i32>) -> impl Responder {
+#[post("/{event_id}/import", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn import_cars(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<i32>,
+    params: web::Query<ImportQueryParams>,
+    body: web::Bytes,
+) -> AppResult<HttpResponse> {
     let event_id = path.into_inner();
-    let result = Event::select_one(event_id, &data.db).await;
-
-    match result {
-        Ok(Some(user)) => HttpResponse::Ok().json(user),
-        Ok(None) => HttpResponse::NotFound().json(ApiError::from("Event not found".to_string())),
-        Err(err) => {
-            error!("{}", err);
-            HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to get event".to_string()))
-        }
+
+    let event = find_event(event_id, &data).await?;
+    if event.creator.id != user.data.id && !user.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only the event creator can import cars.".to_string(),
+        ));
     }
+
+    let event = import::parse_cars(params.format.unwrap_or_default(), event_id, &body)?;
+    finish_import(
+        &data,
+        import::import(vec![event], user.data.id, &data.rules, &data.db).await,
+    )
+    .await
 }
 
 #[derive(Deserialize)]
@@ -110,22 +307,17 @@ struct EventQueryParams {
     )
 )]
 #[get("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn get_all_events(
     data: web::Data<AppState>,
     params: web::Query<EventQueryParams>,
-) -> impl Responder {
+) -> AppResult<HttpResponse> {
     let past: bool = params.past.unwrap_or(false);
 
-    let result = crate::db::event::Event::select_all(past, &data.db).await;
-
-    match result {
-        Ok(events) => HttpResponse::Ok().json(events),
-        Err(e) => {
-            error!("{}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to get events".to_string()))
-        }
-    }
+    let events = Event::select_all(past, &data.db)
+        .await
+        .or_internal("Failed to get events")?;
+    Ok(HttpResponse::Ok().json(events))
 }
 
 #[utoipa::path(
@@ -139,39 +331,24 @@ async fn get_all_events(
     )
 )]
 #[put("/{event_id}", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn update_event(
     data: web::Data<AppState>,
-    session: Session,
+    user: CurrentUser,
     path: web::Path<i32>,
     event: web::Json<EventData>,
-) -> impl Responder {
+) -> AppResult<HttpResponse> {
     let event_id = path.into_inner();
-    let user_id = match session.get::<UserInfo>("userinfo").ok().flatten() {
-        Some(user) => user.id,
-        None => {
-            return HttpResponse::Unauthorized().json(ApiError::from(
-                "Failed to get user data from session".to_string(),
-            ))
-        }
-    };
 
-    if let Err(errs) = event.validate() {
-        return HttpResponse::BadRequest().json(ApiError::from(errs));
-    }
-
-    let updated = Event::update(event_id, user_id, &event, &data.db).await;
+    event.validate(&data.rules)?;
 
-    match updated {
-        Ok(Some(_)) => HttpResponse::Ok().body("Event updated successfully"),
-        Ok(None) => HttpResponse::NotFound().json(ApiError::from(
+    Event::update(event_id, user.data.id, &event, &data.db)
+        .await
+        .or_internal("Failed to update event")?
+        .ok_or(AppError::NotFound(
             "Event not found or you are not the creator".to_string(),
-        )),
-        Err(err) => {
-            error!("{}", err);
-            HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to update event".to_string()))
-        }
-    }
+        ))?;
+    Ok(HttpResponse::Ok().body("Event updated successfully"))
 }
 
 #[utoipa::path(
@@ -183,34 +360,21 @@ async fn update_event(
     )
 )]
 #[delete("/{event_id}", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn delete_event(
     data: web::Data<AppState>,
-    session: Session,
+    user: CurrentUser,
     path: web::Path<i32>,
-) -> impl Responder {
+) -> AppResult<HttpResponse> {
     let event_id = path.into_inner();
-    let user_id = match session.get::<UserInfo>("userinfo").ok().flatten() {
-        Some(user) => user.id,
-        None => {
-            return HttpResponse::Unauthorized().json(ApiError::from(
-                "Failed to get user data from session".to_string(),
-            ))
-        }
-    };
-
-    let deleted = Event::delete(event_id, user_id, &data.db).await;
 
-    match deleted {
-        Ok(Some(_)) => HttpResponse::Ok().body("Event deleted"),
-        Ok(None) => HttpResponse::NotFound().json(ApiError::from(
+    Event::delete(event_id, user.data.id, &data.db)
+        .await
+        .or_internal("Failed to delete event")?
+        .ok_or(AppError::NotFound(
             "Event not found or you are not the creator".to_string(),
-        )),
-        Err(err) => {
-            error!("{}", err);
-            HttpResponse::InternalServerError()
-                .json(ApiError::from("Failed to delete event".to_string()))
-        }
-    }
+        ))?;
+    Ok(HttpResponse::Ok().body("Event deleted"))
 }
 
 pub fn scope() -> Scope {
@@ -218,7 +382,13 @@ pub fn scope() -> Scope {
         .service(create_event)
         .service(get_event)
         .service(get_all_events)
+        .service(get_event_summary)
+        .service(export_event)
+        .service(import_events)
+        .service(import_cars)
         .service(update_event)
         .service(delete_event)
         .service(car::scope())
+        .service(request::scope())
+        .service(matching::scope())
 }
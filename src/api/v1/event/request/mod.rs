@@ -0,0 +1,121 @@
+use crate::app::AppState;
+use crate::auth::{CurrentUser, SessionAuth};
+use crate::db::car::Car;
+use crate::db::event::Event;
+use crate::db::ride_request::{RideRequest, RideRequestData};
+use crate::error::{AppError, AppResult, OrInternal};
+use actix_web::{
+    delete, get, post,
+    web::{self},
+    HttpResponse, Scope,
+};
+use tracing::instrument;
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(create_request, get_all_requests, delete_request),
+    components(schemas(RideRequest, RideRequestData))
+)]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event this Request Applies To")
+    ),
+    responses(
+        (status = 200, description = "Register as needing a ride to the event."),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[post("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn create_request(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<i32>,
+    request: web::Json<RideRequestData>,
+) -> AppResult<HttpResponse> {
+    let event_id = path.into_inner();
+    let user_id = user.data.id;
+
+    request.validate(&data.rules)?;
+
+    Event::select_one(event_id, &data.db)
+        .await
+        .or_internal("Failed to get event")?
+        .ok_or(AppError::NotFound("Event not found".to_string()))?;
+
+    if Car::user_in_car(event_id, &user_id, &data.db)
+        .await
+        .or_internal("Failed to check user's occupancy in other cars")?
+    {
+        return Err(AppError::BadRequest(
+            "User is already in a car.".to_string(),
+        ));
+    }
+
+    RideRequest::insert_new(event_id, user_id, &request, &data.db)
+        .await
+        .or_internal("Failed to request ride")?;
+    Ok(HttpResponse::Ok().body("Ride requested"))
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event this Request Applies To")
+    ),
+    responses(
+        (status = 200, description = "Get everyone who needs a ride to the event", body = [RideRequest]),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn get_all_requests(
+    data: web::Data<AppState>,
+    path: web::Path<i32>,
+) -> AppResult<HttpResponse> {
+    let event_id = path.into_inner();
+    let requests = RideRequest::select_all(event_id, &data.db)
+        .await
+        .or_internal("Failed to get ride requests")?;
+    Ok(HttpResponse::Ok().json(requests))
+}
+
+#[utoipa::path(
+    params(
+        ("event_id" = i32, Path, description = "ID of the Event this Request Applies To")
+    ),
+    responses(
+        (status = 200, description = "Withdraw your ride request."),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[delete("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn delete_request(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<i32>,
+) -> AppResult<HttpResponse> {
+    let event_id = path.into_inner();
+
+    RideRequest::delete(event_id, user.data.id, &data.db)
+        .await
+        .or_internal("Failed to delete ride request")?
+        .ok_or(AppError::NotFound("Ride request not found".to_string()))?;
+    Ok(HttpResponse::Ok().body("Ride request deleted"))
+}
+
+pub fn scope() -> Scope {
+    web::scope("/{event_id}/request")
+        .service(create_request)
+        .service(get_all_requests)
+        .service(delete_request)
+}
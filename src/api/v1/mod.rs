@@ -1,8 +1,9 @@
 use actix_web::{web, Scope};
 use utoipa::OpenApi;
 
-mod auth;
+pub mod auth;
 mod event;
+mod stats;
 mod user;
 
 #[derive(OpenApi)]
@@ -10,6 +11,7 @@ mod user;
     nest(
         (path = "/auth", api = auth::ApiDoc),
         (path = "/event", api = event::ApiDoc),
+        (path = "/stats", api = stats::ApiDoc),
         (path = "/user", api = user::ApiDoc)
     ),
 )]
@@ -19,5 +21,6 @@ pub fn scope() -> Scope {
     web::scope("/v1")
         .service(auth::scope())
         .service(event::scope())
+        .service(stats::scope())
         .service(user::scope())
 }
@@ -0,0 +1,42 @@
+use actix_web::{get, web, HttpResponse, Scope};
+use tracing::instrument;
+use utoipa::OpenApi;
+
+use crate::app::AppState;
+use crate::auth::{CurrentUser, SessionAuth};
+use crate::db::stats::{DriverCount, MonthCount, SiteStats};
+use crate::error::{AppError, AppResult, OrInternal};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_stats),
+    components(schemas(SiteStats, MonthCount, DriverCount))
+)]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get site-wide statistics. Admin only.", body = SiteStats),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn get_stats(data: web::Data<AppState>, user: CurrentUser) -> AppResult<HttpResponse> {
+    if !user.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only admins can view statistics.".to_string(),
+        ));
+    }
+
+    let stats = SiteStats::select(&data.db)
+        .await
+        .or_internal("Failed to get statistics")?;
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+pub fn scope() -> Scope {
+    web::scope("/stats").service(get_stats)
+}
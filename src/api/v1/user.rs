@@ -1,17 +1,48 @@
-use actix_web::{get, web, HttpResponse, Responder, Scope};
-use serde::Deserialize;
+use actix_session::Session;
+use actix_web::{delete, get, post, put, web, HttpResponse, Scope};
+use log::error;
+use serde::{Deserialize, Serialize};
 
-use crate::app::{ApiError, AppState};
-use crate::auth::SessionAuth;
+use crate::app::{AppState, DeletedUserRide, RedisJob};
+use crate::auth::{CurrentUser, SessionAuth};
+use crate::error::{AppError, AppResult, OrInternal};
 
+use tracing::instrument;
 use utoipa::OpenApi;
 
-use crate::db::user::UserData;
+use crate::db::identity::UserIdentity;
+use crate::db::session::SessionInfo;
+use crate::db::user::{LocaleData, UserData};
 
 #[derive(OpenApi)]
-#[openapi(paths(user_search), components(schemas(UserData)))]
+#[openapi(
+    paths(
+        user_search,
+        get_identities,
+        delete_identity,
+        get_pending_merge,
+        confirm_merge,
+        cancel_merge,
+        delete_user_sessions,
+        set_locale,
+        delete_account
+    ),
+    components(schemas(UserData, UserIdentity, LocaleData))
+)]
 pub struct ApiDoc;
 
+pub(crate) const MERGE_KEY: &str = "pending_merge";
+
+/// Another user's account that `into` logged in with while linking. It's only merged into
+/// theirs once they confirm it.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PendingMerge {
+    pub realm: String,
+    pub subject: String,
+    pub user_id: String,
+    pub into: String,
+}
+
 #[derive(Deserialize)]
 struct UserSearchParams {
     query: String,
@@ -24,21 +55,301 @@ struct UserSearchParams {
     )
 )]
 #[get("/", wrap = "SessionAuth")]
+#[instrument(skip_all)]
 async fn user_search(
     data: web::Data<AppState>,
     params: web::Query<UserSearchParams>,
-) -> impl Responder {
+) -> AppResult<HttpResponse> {
     let query = format!("%{}%", params.query.to_lowercase());
 
-    let result = UserData::select_search(query, &data.db).await;
+    let users = UserData::select_search(query, &data.db)
+        .await
+        .or_internal("Failed to search users")?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get the accounts linked to the current user", body = [UserIdentity]),
+        (status = 401, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/identity", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn get_identities(data: web::Data<AppState>, user: CurrentUser) -> AppResult<HttpResponse> {
+    let identities = UserIdentity::select_all(&user.data.id, &data.db)
+        .await
+        .or_internal("Failed to get identities")?;
+    Ok(HttpResponse::Ok().json(identities))
+}
+
+#[utoipa::path(
+    params(
+        ("realm" = String, Path, description = "Provider of the Account to Unlink")
+    ),
+    responses(
+        (status = 200, description = "Unlink an account. The account the user was created with can't be unlinked."),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[delete("/identity/{realm}", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn delete_identity(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    let realm = path.into_inner();
+
+    UserIdentity::delete(&user.data.id, &realm, &data.db)
+        .await
+        .or_internal("Failed to unlink account")?
+        .ok_or(AppError::NotFound(
+            "No linked account that can be removed".to_string(),
+        ))?;
+    Ok(HttpResponse::Ok().body("Account unlinked"))
+}
+
+fn pending_merge(session: &Session, user: &CurrentUser) -> AppResult<PendingMerge> {
+    session
+        .get::<PendingMerge>(MERGE_KEY)
+        .ok()
+        .flatten()
+        .filter(|pending| pending.into == user.data.id)
+        .ok_or(AppError::NotFound(
+            "No account is waiting to be merged".to_string(),
+        ))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get the account that was logged in with while linking, which belongs to another user. Its rides and events are only merged into the current user's once they confirm it.", body = UserData),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[get("/merge", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn get_pending_merge(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    session: Session,
+) -> AppResult<HttpResponse> {
+    let pending = pending_merge(&session, &user)?;
+
+    let other = UserData::select_one(pending.user_id, &data.db)
+        .await
+        .or_internal("Failed to get user")?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+    Ok(HttpResponse::Ok().json(other))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Merge the account waiting to be merged into the current user's. The other user's seats are dropped in events the current user already has a place in."),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 409, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[post("/merge", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn confirm_merge(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    session: Session,
+) -> AppResult<HttpResponse> {
+    let pending = pending_merge(&session, &user)?;
+
+    let mut tx = data
+        .db
+        .begin()
+        .await
+        .or_internal("Failed to make SQL Transaction")?;
+    // The account may have been unlinked or merged somewhere else since.
+    let owner = UserIdentity::select_user(&pending.realm, &pending.subject, &mut *tx)
+        .await
+        .or_internal("Failed to get identity")?;
+    if owner.as_ref() != Some(&pending.user_id) {
+        session.remove(MERGE_KEY);
+        return Err(AppError::NotFound(
+            "No account is waiting to be merged".to_string(),
+        ));
+    }
+    if let Some(event_id) = UserIdentity::shared_event(&pending.user_id, &user.data.id, &mut *tx)
+        .await
+        .or_internal("Failed to check for shared events")?
+    {
+        return Err(AppError::Conflict(format!(
+            "Both accounts drive a car in event {}. Delete one of the cars, then merge the accounts again.",
+            event_id
+        )));
+    }
+    UserIdentity::merge(&pending.user_id, &user.data.id, &mut tx)
+        .await
+        .or_internal("Failed to merge accounts")?;
+    tx.commit()
+        .await
+        .or_internal("Failed to commit transaction")?;
+    session.remove(MERGE_KEY);
+    Ok(HttpResponse::Ok().body("Accounts merged"))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Leave the other user's account alone instead of merging it"),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError)
+    )
+)]
+#[delete("/merge", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn cancel_merge(user: CurrentUser, session: Session) -> AppResult<HttpResponse> {
+    pending_merge(&session, &user)?;
+    session.remove(MERGE_KEY);
+    Ok(HttpResponse::Ok().body("Merge cancelled"))
+}
+
+#[utoipa::path(
+    params(
+        ("user_id" = String, Path, description = "ID of the User to Log Out")
+    ),
+    responses(
+        (status = 200, description = "Log a user out everywhere. Admins only."),
+        (status = 401, body = ApiError),
+        (status = 403, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[delete("/{user_id}/sessions", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn delete_user_sessions(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    path: web::Path<String>,
+) -> AppResult<HttpResponse> {
+    if !user.is_admin() {
+        return Err(AppError::Forbidden(
+            "Only admins can log other users out.".to_string(),
+        ));
+    }
+
+    let count = SessionInfo::revoke_all(&path.into_inner(), &data.db)
+        .await
+        .or_internal("Failed to end sessions")?;
+    Ok(HttpResponse::Ok().body(format!("Ended {} sessions", count)))
+}
+
+#[utoipa::path(
+    request_body = LocaleData,
+    responses(
+        (status = 200, description = "Pick the locale the current user's notifications are sent in"),
+        (status = 400, body = ApiError),
+        (status = 401, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[put("/locale", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn set_locale(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    body: web::Json<LocaleData>,
+) -> AppResult<HttpResponse> {
+    body.validate()?;
+
+    UserData::update_locale(&user.data.id, body.locale.as_deref(), &data.db)
+        .await
+        .or_internal("Failed to update locale")?;
+    Ok(HttpResponse::Ok().body("Locale updated"))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Delete the current user's account and log them out. Events that already ended and cars that already left are kept under a placeholder user. Upcoming events they created are handed to the driver of another car in them, or deleted if nobody else is driving. Their upcoming cars, rides and ride requests are deleted, and the people in their upcoming cars and who asked for a ride to their deleted events are notified."),
+        (status = 401, body = ApiError),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+#[delete("/me", wrap = "SessionAuth")]
+#[instrument(skip_all)]
+async fn delete_account(
+    data: web::Data<AppState>,
+    user: CurrentUser,
+    session: Session,
+) -> AppResult<HttpResponse> {
+    let mut tx = data
+        .db
+        .begin()
+        .await
+        .or_internal("Failed to make SQL Transaction")?;
+    SessionInfo::revoke_all(&user.data.id, &mut *tx)
+        .await
+        .or_internal("Failed to end sessions")?;
+    let deleted = UserData::delete_account(&user.data.id, &mut tx)
+        .await
+        .or_internal("Failed to delete account")?
+        .ok_or(AppError::NotFound("User not found".to_string()))?;
+    tx.commit()
+        .await
+        .or_internal("Failed to commit transaction")?;
+    session.purge();
 
-    match result {
-        Ok(users) => HttpResponse::Ok().json(users),
-        Err(_) => HttpResponse::InternalServerError()
-            .json(ApiError::from("Failed to get events".to_string())),
+    let name = deleted.name;
+    let jobs: Vec<RedisJob> = deleted
+        .left
+        .into_iter()
+        .map(|ride| {
+            RedisJob::RiderDeleted(DeletedUserRide {
+                name: name.clone(),
+                ride,
+            })
+        })
+        .chain(deleted.cancelled.into_iter().map(|ride| {
+            RedisJob::DriverDeleted(DeletedUserRide {
+                name: name.clone(),
+                ride,
+            })
+        }))
+        .chain(deleted.events.into_iter().map(|ride| {
+            RedisJob::CreatorDeleted(DeletedUserRide {
+                name: name.clone(),
+                ride,
+            })
+        }))
+        .collect();
+    for job in jobs {
+        match data
+            .redis
+            .lock()
+            .map(|mut mutex| async move { mutex.insert_job(job).await })
+        {
+            Ok(res) => {
+                if let Err(err) = res.await {
+                    error!("{}", err);
+                }
+            }
+            Err(err) => error!("{}", err),
+        }
     }
+    Ok(HttpResponse::Ok().body("Account deleted"))
 }
 
 pub fn scope() -> Scope {
-    web::scope("/user").service(user_search)
+    web::scope("/user")
+        .service(user_search)
+        .service(get_identities)
+        .service(delete_identity)
+        .service(get_pending_merge)
+        .service(confirm_merge)
+        .service(cancel_merge)
+        .service(delete_user_sessions)
+        .service(set_locale)
+        .service(delete_account)
 }
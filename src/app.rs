@@ -1,45 +1,69 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-use oauth2::basic::BasicClient;
+use anyhow::{anyhow, Result};
+use redis_work_queue::{KeyPrefix, WorkQueue};
 use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
-use utoipa::ToSchema;
 
+use crate::config::{Config, FeatureConfig, SessionSettings};
+use crate::db::user::AffectedRide;
+use crate::health::Probes;
+use crate::providers::ProviderRegistry;
 use crate::redis::RedisQueue;
+use crate::validation::ValidationRules;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
     pub redis: Arc<Mutex<RedisQueue>>,
-    pub google_oauth: BasicClient,
-    pub google_userinfo_url: String,
-    pub csh_oauth: BasicClient,
-    pub csh_userinfo_url: String,
+    pub auth_providers: Arc<ProviderRegistry>,
+    pub admin_group: String,
+    pub rules: ValidationRules,
+    pub session: SessionSettings,
+    pub features: FeatureConfig,
+    pub probes: Probes,
 }
 
-#[derive(Serialize, Deserialize, ToSchema)]
-pub struct ApiError {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    errors: Option<Vec<String>>,
-}
+impl AppState {
+    /// Connect to Postgres and Redis and set up the auth providers. This should only be run
+    /// once at startup, and the state cloned into each worker.
+    pub async fn connect(config: &Config) -> Result<Self> {
+        let db = PgPoolOptions::new()
+            .max_connections(config.database.max_connections)
+            .min_connections(config.database.min_connections)
+            .connect(&config.database.url)
+            .await
+            .map_err(|err| anyhow!("Failed to connect to Postgres: {}", err))?;
 
-impl From<String> for ApiError {
-    fn from(value: String) -> Self {
-        ApiError {
-            error: Some(value),
-            errors: None,
-        }
-    }
-}
+        let (queue, probe) = if config.features.notifications {
+            let queue = RedisQueue::connect(&config.redis).await?;
+            let probe = match &queue {
+                RedisQueue::Redis { redis, .. } => Some((
+                    redis.clone(),
+                    WorkQueue::new(KeyPrefix::from(config.redis.key_prefix.as_str())),
+                )),
+                _ => None,
+            };
+            (queue, probe)
+        } else {
+            (RedisQueue::Disabled, None)
+        };
 
-impl From<Vec<String>> for ApiError {
-    fn from(value: Vec<String>) -> Self {
-        ApiError {
-            error: None,
-            errors: Some(value),
-        }
+        let auth_providers =
+            ProviderRegistry::new(config.auth.providers.clone(), &config.redirect_domain()).await?;
+
+        Ok(AppState {
+            probes: Probes::new(db.clone(), probe),
+            db,
+            redis: Arc::new(Mutex::new(queue)),
+            auth_providers: Arc::new(auth_providers),
+            admin_group: config.auth.admin_group.clone(),
+            rules: config.validation.clone(),
+            session: config.session_settings()?,
+            features: config.features.clone(),
+        })
     }
 }
 
@@ -58,10 +82,61 @@ pub struct MultipleRiderChange {
     pub new_riders: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct DriverChange {
+    pub event_id: i32,
+    pub car_id: i32,
+    pub old_driver: String,
+    pub riders: Vec<String>,
+}
+
+/// A ride changed by a user deleting their account. The user, and a car they drove, are gone by
+/// the time the worker runs, so their name and the ride are sent along.
+#[derive(Serialize, Deserialize)]
+pub struct DeletedUserRide {
+    pub name: String,
+    #[serde(flatten)]
+    pub ride: AffectedRide,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum RedisJob {
     Join(SimpleRiderChange),
     Leave(SimpleRiderChange),
     RiderUpdate(MultipleRiderChange),
+    TransferOffer(SimpleRiderChange),
+    DriverChange(DriverChange),
+    /// Tells the driver that the user left.
+    RiderDeleted(DeletedUserRide),
+    /// Tells the riders that the car was cancelled.
+    DriverDeleted(DeletedUserRide),
+    /// Tells who asked for a ride that the event was cancelled.
+    CreatorDeleted(DeletedUserRide),
+}
+
+impl RedisJob {
+    /// The job's type, as it's tagged in the queue.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RedisJob::Join(_) => "Join",
+            RedisJob::Leave(_) => "Leave",
+            RedisJob::RiderUpdate(_) => "RiderUpdate",
+            RedisJob::TransferOffer(_) => "TransferOffer",
+            RedisJob::DriverChange(_) => "DriverChange",
+            RedisJob::RiderDeleted(_) => "RiderDeleted",
+            RedisJob::DriverDeleted(_) => "DriverDeleted",
+            RedisJob::CreatorDeleted(_) => "CreatorDeleted",
+        }
+    }
+}
+
+/// A job as it's kept in the queue, with the trace context of the request that queued it.
+/// Jobs queued without one still parse.
+#[derive(Serialize, Deserialize)]
+pub struct QueuedJob {
+    #[serde(flatten)]
+    pub job: RedisJob,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub trace_context: HashMap<String, String>,
 }
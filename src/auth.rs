@@ -1,16 +1,47 @@
 use std::{
-    env,
     future::{ready, Ready},
+    rc::Rc,
     task::Poll,
 };
 
 use actix_session::SessionExt;
 use actix_web::{
-    dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    HttpResponse,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, Method},
+    web, FromRequest, HttpMessage, HttpRequest, ResponseError,
 };
+use anyhow::anyhow;
 use futures_util::future::LocalBoxFuture;
-use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
+use sha2::{Digest, Sha256};
+
+use crate::api::v1::auth::models::UserInfo;
+use crate::app::AppState;
+use crate::db::token::{ApiToken, TokenScope};
+use crate::db::user::UserData;
+use crate::error::{AppError, AppResult, OrInternal};
+
+/// Hash a session key or API token for storage, so a database dump can't be used to log in.
+pub fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// Which token scope a request needs, or `None` if API tokens can't be used for it at all.
+/// Tokens can never manage logins, sessions or other tokens.
+fn required_scope(method: &Method, path: &str) -> Option<TokenScope> {
+    let segments: Vec<&str> = path
+        .trim_end_matches('/')
+        .split('/')
+        .skip_while(|segment| *segment != "v1")
+        .skip(1)
+        .collect();
+    match segments.as_slice() {
+        ["auth", ..] | ["user", "identity", ..] | ["user", _, "sessions"] => None,
+        _ if method == Method::GET => Some(TokenScope::Read),
+        ["event", _, "car", ..] | ["event", _, "request", ..] => Some(TokenScope::ManageCars),
+        ["event", ..] => Some(TokenScope::ManageEvents),
+        _ => None,
+    }
+}
 
 pub struct SessionAuth;
 
@@ -21,6 +52,7 @@ where
         Response = ServiceResponse<actix_web::body::BoxBody>,
         Error = actix_web::Error,
     >,
+    S: 'static,
     S::Future: 'static,
 {
     type Response = ServiceResponse<actix_web::body::BoxBody>;
@@ -30,12 +62,14 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(SessionAuthMiddleware { service }))
+        ready(Ok(SessionAuthMiddleware {
+            service: Rc::new(service),
+        }))
     }
 }
 
 pub struct SessionAuthMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 impl<S> Service<ServiceRequest> for SessionAuthMiddleware<S>
@@ -45,6 +79,7 @@ where
         Response = ServiceResponse<actix_web::body::BoxBody>,
         Error = actix_web::Error,
     >,
+    S: 'static,
     S::Future: 'static,
 {
     type Response = ServiceResponse<actix_web::body::BoxBody>;
@@ -66,52 +101,143 @@ where
                 Ok(response)
             });
         }
-        Box::pin(async { Ok(req.into_response(HttpResponse::Unauthorized().finish())) })
+
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        let Some(token) = token else {
+            return Box::pin(async {
+                Ok(req.into_response(
+                    AppError::Unauthorized("Not logged in".to_string()).error_response(),
+                ))
+            });
+        };
+        let required = required_scope(req.method(), req.path());
+        let data = req.app_data::<web::Data<AppState>>().cloned();
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            // The token's user is only attached to this request, never to the session.
+            match token_user(&token, required, data).await {
+                Ok(user) => {
+                    req.extensions_mut().insert(TokenUser(user));
+                    service.call(req).await
+                }
+                Err(err) => Ok(req.into_response(err.error_response())),
+            }
+        })
     }
 }
 
-fn get_oauth_client(
-    client_id: String,
-    client_secret: Option<String>,
-    auth_url: String,
-    token_url: Option<String>,
-    redirect_url: String,
-) -> BasicClient {
-    let client_id = ClientId::new(client_id);
-    let client_secret = client_secret.map(ClientSecret::new);
-    let auth_url = AuthUrl::new(auth_url).expect("Invalid authorization endpoint URL");
-
-    let token_url = token_url.map(|val| TokenUrl::new(val).expect("Invalid token endpoint URL"));
-
-    BasicClient::new(client_id, client_secret, auth_url, token_url)
-        .set_redirect_uri(RedirectUrl::new(redirect_url).expect("Invalid redirect URL"))
+/// The user a bearer token acts as, left in the request's extensions for `CurrentUser`.
+#[derive(Clone)]
+struct TokenUser(UserData);
+
+/// Check a bearer token against the scope the request needs, and load the user it acts as.
+async fn token_user(
+    token: &str,
+    required: Option<TokenScope>,
+    data: Option<web::Data<AppState>>,
+) -> AppResult<UserData> {
+    let (Some(required), Some(data)) = (required, data) else {
+        return Err(AppError::Forbidden(
+            "API tokens can't be used here".to_string(),
+        ));
+    };
+    let (user, scopes) = ApiToken::authenticate(&hash_secret(token), &data.db)
+        .await
+        .or_internal("Failed to check API token")?
+        .ok_or(AppError::Unauthorized(
+            "API token is invalid or expired".to_string(),
+        ))?;
+    // Every scope can read, but only the matching scope can change things.
+    if !scopes
+        .iter()
+        .any(|scope| *scope == required || required == TokenScope::Read)
+    {
+        return Err(AppError::Forbidden(format!(
+            "This API token needs the {} scope",
+            required.as_str()
+        )));
+    }
+    Ok(user)
 }
 
-pub fn get_clients(host: &str, port: i32) -> (BasicClient, BasicClient) {
-    let redirect_domain =
-        env::var("REDIRECT_DOMAIN").unwrap_or(format!("http://{}:{}", host, port));
-
-    (
-        get_oauth_client(
-            env::var("GOOGLE_CLIENT_ID")
-                .expect("Missing the GOOGLE_CLIENT_ID environment variable."),
-            Some(
-                env::var("GOOGLE_CLIENT_SECRET")
-                    .expect("Missing the GOOGLE_CLIENT_SECRET environment variable."),
-            ),
-            "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
-            Some("https://www.googleapis.com/oauth2/v3/token".to_string()),
-            format!("{}/api/v1/auth/google/redirect", redirect_domain),
-        ),
-        get_oauth_client(
-            env::var("CSH_CLIENT_ID").expect("Missing the GOOGLE_CLIENT_ID environment variable."),
-            Some(
-                env::var("CSH_CLIENT_SECRET")
-                    .expect("Missing the GOOGLE_CLIENT_SECRET environment variable."),
-            ),
-            env::var("CSH_AUTH_URL").unwrap_or("".to_string()),
-            Some(env::var("CSH_TOKEN_URL").unwrap_or("".to_string())),
-            format!("{}/api/v1/auth/csh/redirect", redirect_domain),
-        ),
-    )
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+/// The user making the request, whether they logged in or used an API token. Their details
+/// are loaded fresh from the database, so merged or deleted accounts stop working right away.
+/// Tokens never act as admins.
+pub struct CurrentUser {
+    pub data: UserData,
+    pub role: Role,
+    /// What the provider said about the user when they logged in. Built from `data` for tokens.
+    pub profile: UserInfo,
+}
+
+impl CurrentUser {
+    /// Admins are members of `ADMIN_GROUP` according to the groups the provider sent when they
+    /// logged in, which are kept for the whole session. Someone removed from the group stays an
+    /// admin until they log in again, so revoke their sessions to take it away right away.
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+impl FromRequest for CurrentUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, AppResult<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(TokenUser(user)) = req.extensions().get::<TokenUser>() {
+            let profile = UserInfo {
+                id: user.id.clone(),
+                username: None,
+                email: user.email.clone(),
+                given_name: user.name.clone(),
+                family_name: String::new(),
+                picture: String::new(),
+                groups: Vec::new(),
+            };
+            let user = CurrentUser {
+                data: user.clone(),
+                role: Role::User,
+                profile,
+            };
+            return Box::pin(ready(Ok(user)));
+        }
+        let session = req.get_session();
+        let data = req.app_data::<web::Data<AppState>>().cloned();
+        Box::pin(async move {
+            let info = session.get::<UserInfo>("userinfo").ok().flatten().ok_or(
+                AppError::Unauthorized("Failed to get user data from session".to_string()),
+            )?;
+            let data = data.ok_or(AppError::internal(
+                "Failed to get user data",
+                anyhow!("AppState is missing"),
+            ))?;
+
+            let user = UserData::select_one(info.id.clone(), &data.db)
+                .await
+                .or_internal("Failed to get user data")?
+                .ok_or(AppError::Unauthorized("User no longer exists".to_string()))?;
+            let role = if info.is_admin(&data.admin_group) {
+                Role::Admin
+            } else {
+                Role::User
+            };
+            Ok(CurrentUser {
+                data: user,
+                role,
+                profile: info,
+            })
+        })
+    }
 }
@@ -0,0 +1,616 @@
+use std::env;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use actix_web::cookie::{time::Duration, Key};
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use chrono::TimeDelta;
+use log::warn;
+use serde::Deserialize;
+
+use crate::notifications::NotificationTemplates;
+use crate::providers::{ProviderConfig, ProviderRegistry};
+use crate::validation::ValidationRules;
+
+/// Where the config file is looked for when neither `--config` nor `CONFIG_FILE` is given.
+const DEFAULT_CONFIG_FILE: &str = "rideboard.toml";
+
+/// Reads overrides from environment variables, collecting every problem so they can be reported
+/// together instead of one per restart.
+#[derive(Default)]
+struct EnvReader {
+    errs: Vec<String>,
+}
+
+impl EnvReader {
+    /// The variable's value, treating an empty value as unset.
+    fn optional(&self, name: &str) -> Option<String> {
+        env::var(name).ok().filter(|value| !value.is_empty())
+    }
+
+    fn set(&self, name: &str, value: &mut String) {
+        if let Some(env_value) = self.optional(name) {
+            *value = env_value;
+        }
+    }
+
+    fn set_parsed<T>(&mut self, name: &str, value: &mut T)
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.optional(name).map(|env_value| env_value.parse::<T>()) {
+            Some(Ok(env_value)) => *value = env_value,
+            Some(Err(err)) => self.errs.push(format!("{} is invalid: {}", name, err)),
+            None => {}
+        }
+    }
+}
+
+/// Fail with every problem found, if there were any.
+fn check(errs: Vec<String>) -> Result<()> {
+    if errs.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!("Invalid configuration:\n  {}", errs.join("\n  ")))
+}
+
+/// Fill in unset environment variables from `path`, or from `.env` if it exists.
+pub fn load_env_file(path: Option<&Path>) -> Result<()> {
+    match path {
+        Some(path) => dotenv::from_path(path)
+            .map_err(|err| anyhow!("Failed to read {}: {}", path.display(), err)),
+        None => {
+            dotenv::dotenv().ok();
+            Ok(())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Where the browser reaches the server, used for OAuth redirects. Defaults to the host and
+    /// port.
+    pub redirect_domain: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            redirect_domain: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerConfig {
+    /// Where the worker serves `/healthz`, `/readyz` and `/metrics`.
+    pub host: String,
+    pub port: u16,
+    /// Jobs run at once.
+    pub concurrency: usize,
+    /// How long a job is leased for before another worker may take it. Leases are renewed
+    /// while the job runs.
+    pub lease_seconds: u64,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8081,
+            concurrency: 4,
+            lease_seconds: 30,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            url: String::new(),
+            max_connections: 5,
+            min_connections: 0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    pub url: String,
+    /// Prefix of the job queue's keys. The server and worker have to agree on it.
+    pub key_prefix: String,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            url: String::new(),
+            key_prefix: "rideboard".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Base64 encoded key of at least 64 bytes. Required unless `generate_key` is set.
+    pub key: Option<String>,
+    /// Make a new key on every start when `key` isn't set, which logs everyone out. Only for
+    /// development.
+    pub generate_key: bool,
+    /// Sessions expire after this long without a request.
+    pub ttl_hours: i64,
+    /// Only send the cookie over HTTPS.
+    pub secure: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            key: None,
+            generate_key: false,
+            ttl_hours: 24 * 7,
+            secure: true,
+        }
+    }
+}
+
+/// The session settings the server runs with, once the key has been decoded or generated.
+#[derive(Clone)]
+pub struct SessionSettings {
+    pub key: Key,
+    pub ttl: Duration,
+    pub secure: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Members of this group are admins.
+    pub admin_group: String,
+    /// Providers users can log in with, including their OAuth endpoints. When empty, they're
+    /// read from the environment by `ProviderRegistry::configs_from_env`.
+    pub providers: Vec<ProviderConfig>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            admin_group: "rtp".to_string(),
+            providers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PingRoutes {
+    pub join: String,
+    pub leave: String,
+    pub add: String,
+    /// Used for removals, and for rides and events cancelled by a user deleting their account.
+    pub remove: String,
+    /// Used for transfer offers and driver changes.
+    pub transfer: String,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PingsConfig {
+    pub base_url: String,
+    pub token: String,
+    pub routes: PingRoutes,
+}
+
+impl Default for PingsConfig {
+    fn default() -> Self {
+        PingsConfig {
+            base_url: "https://pings.csh.rit.edu".to_string(),
+            token: String::new(),
+            routes: PingRoutes::default(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP collector to export traces to, e.g. `http://localhost:4318`. Traces aren't
+    /// exported without one.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            otlp_endpoint: None,
+            service_name: "rideboard".to_string(),
+        }
+    }
+}
+
+/// What the worker does with events once they're older than the retention period.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionMode {
+    /// Keep the events and their cars, so statistics still count them, but remove who rode in
+    /// them, who asked for a ride and what the drivers wrote.
+    Anonymize,
+    /// Delete the events with everything in them.
+    Purge,
+}
+
+impl FromStr for RetentionMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "anonymize" => Ok(RetentionMode::Anonymize),
+            "purge" => Ok(RetentionMode::Purge),
+            _ => Err("expected anonymize or purge".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Events that ended more than this many months ago are anonymized or purged. 0 keeps them
+    /// forever.
+    pub months: u32,
+    pub mode: RetentionMode,
+    /// How often the worker applies the policy.
+    pub interval_hours: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            months: 0,
+            mode: RetentionMode::Anonymize,
+            interval_hours: 24,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureConfig {
+    /// Serve the Swagger UI and OpenAPI document under `/api/docs`.
+    pub api_docs: bool,
+    /// Queue notifications for the worker to send. When off, nothing is queued and the worker
+    /// doesn't need Pings credentials.
+    pub notifications: bool,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        FeatureConfig {
+            api_docs: true,
+            notifications: true,
+        }
+    }
+}
+
+/// Settings for the server and worker. Each comes from the defaults, then the TOML config file,
+/// then environment variables.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub worker: WorkerConfig,
+    pub database: DatabaseConfig,
+    pub redis: RedisConfig,
+    pub session: SessionConfig,
+    pub auth: AuthConfig,
+    pub pings: PingsConfig,
+    pub notifications: NotificationTemplates,
+    pub validation: ValidationRules,
+    pub retention: RetentionConfig,
+    pub telemetry: TelemetryConfig,
+    pub features: FeatureConfig,
+    /// Why the providers couldn't be read from the environment. Only the server needs them, so
+    /// this is reported by `check_server` rather than failing every command.
+    #[serde(skip)]
+    provider_error: Option<String>,
+}
+
+impl Config {
+    /// Read `path`, `CONFIG_FILE` or `rideboard.toml` (if it exists), then apply the environment
+    /// on top. Returns the file that was read, if any. The values are only checked by
+    /// `check_server` and `check_worker`.
+    pub fn load(path: Option<&Path>) -> Result<(Self, Option<PathBuf>)> {
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => match env::var("CONFIG_FILE").ok().filter(|file| !file.is_empty()) {
+                Some(file) => Some(PathBuf::from(file)),
+                None => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()),
+            },
+        };
+        let mut config = match &path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|err| anyhow!("Failed to read {}: {}", path.display(), err))?;
+                toml::from_str(&contents)
+                    .map_err(|err| anyhow!("Failed to parse {}: {}", path.display(), err))?
+            }
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        Ok((config, path))
+    }
+
+    /// Override settings with the environment variables that are set.
+    fn apply_env(&mut self) -> Result<()> {
+        let mut env = EnvReader::default();
+
+        env.set("HOST", &mut self.server.host);
+        env.set_parsed("PORT", &mut self.server.port);
+        if let Some(domain) = env.optional("REDIRECT_DOMAIN") {
+            self.server.redirect_domain = Some(domain);
+        }
+
+        env.set("WORKER_HOST", &mut self.worker.host);
+        env.set_parsed("WORKER_PORT", &mut self.worker.port);
+        env.set_parsed("WORKER_CONCURRENCY", &mut self.worker.concurrency);
+        env.set_parsed("WORKER_LEASE_SECONDS", &mut self.worker.lease_seconds);
+
+        env.set("DATABASE_URL", &mut self.database.url);
+        env.set_parsed(
+            "DATABASE_MAX_CONNECTIONS",
+            &mut self.database.max_connections,
+        );
+        env.set_parsed(
+            "DATABASE_MIN_CONNECTIONS",
+            &mut self.database.min_connections,
+        );
+
+        env.set("REDIS_URL", &mut self.redis.url);
+        env.set("REDIS_KEY_PREFIX", &mut self.redis.key_prefix);
+
+        if let Some(key) = env.optional("SESSION_KEY") {
+            self.session.key = Some(key);
+        }
+        env.set_parsed("SESSION_TTL_HOURS", &mut self.session.ttl_hours);
+        if env::var("DEVELOPMENT").is_ok() {
+            self.session.secure = false;
+            self.session.generate_key = true;
+        }
+
+        env.set("ADMIN_GROUP", &mut self.auth.admin_group);
+        if self.auth.providers.is_empty()
+            || env.optional("AUTH_PROVIDERS").is_some()
+            || env.optional("AUTH_PROVIDERS_FILE").is_some()
+        {
+            match ProviderRegistry::configs_from_env() {
+                Ok(providers) => self.auth.providers = providers,
+                Err(err) => self.provider_error = Some(err.to_string()),
+            }
+        }
+
+        env.set("PINGS_BASE_URL", &mut self.pings.base_url);
+        env.set("PINGS_TOKEN", &mut self.pings.token);
+        env.set("PINGS_JOIN_ROUTE", &mut self.pings.routes.join);
+        env.set("PINGS_LEAVE_ROUTE", &mut self.pings.routes.leave);
+        env.set("PINGS_ADD_ROUTE", &mut self.pings.routes.add);
+        env.set("PINGS_REMOVE_ROUTE", &mut self.pings.routes.remove);
+        env.set("PINGS_TRANSFER_ROUTE", &mut self.pings.routes.transfer);
+
+        env.set(
+            "NOTIFICATIONS_DEFAULT_LOCALE",
+            &mut self.notifications.default_locale,
+        );
+        env.set_parsed("NOTIFICATIONS_TIMEZONE", &mut self.notifications.timezone);
+
+        let rules = &mut self.validation;
+        env.set_parsed("MAX_NAME_LENGTH", &mut rules.max_name_length);
+        env.set_parsed("MAX_LOCATION_LENGTH", &mut rules.max_location_length);
+        env.set_parsed("MAX_COMMENT_LENGTH", &mut rules.max_comment_length);
+        env.set_parsed("MAX_NOTE_LENGTH", &mut rules.max_note_length);
+        env.set_parsed("MAX_CAPACITY", &mut rules.max_capacity);
+        let mut hours = rules.max_event_duration.num_hours();
+        env.set_parsed("MAX_EVENT_DURATION_HOURS", &mut hours);
+        rules.max_event_duration = TimeDelta::hours(hours);
+
+        env.set_parsed("RETENTION_MONTHS", &mut self.retention.months);
+        env.set_parsed("RETENTION_MODE", &mut self.retention.mode);
+        env.set_parsed(
+            "RETENTION_INTERVAL_HOURS",
+            &mut self.retention.interval_hours,
+        );
+
+        if let Some(endpoint) = env.optional("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            self.telemetry.otlp_endpoint = Some(endpoint);
+        }
+        env.set("OTEL_SERVICE_NAME", &mut self.telemetry.service_name);
+
+        env.set_parsed("FEATURE_API_DOCS", &mut self.features.api_docs);
+        env.set_parsed("FEATURE_NOTIFICATIONS", &mut self.features.notifications);
+
+        check(env.errs)
+    }
+
+    pub fn redirect_domain(&self) -> String {
+        self.server
+            .redirect_domain
+            .clone()
+            .unwrap_or(format!("http://{}:{}", self.server.host, self.server.port))
+    }
+
+    fn check_common(&self, errs: &mut Vec<String>) {
+        if self.database.url.is_empty() {
+            errs.push("database.url (DATABASE_URL) must be set".to_string());
+        }
+        if self.database.max_connections == 0 {
+            errs.push("database.max_connections must be at least 1".to_string());
+        }
+        if self.database.min_connections > self.database.max_connections {
+            errs.push(
+                "database.min_connections cannot be more than database.max_connections".to_string(),
+            );
+        }
+    }
+
+    fn check_redis(&self, errs: &mut Vec<String>) {
+        if self.redis.url.is_empty() {
+            errs.push("redis.url (REDIS_URL) must be set".to_string());
+        }
+        if self.redis.key_prefix.is_empty() {
+            errs.push("redis.key_prefix cannot be empty".to_string());
+        }
+    }
+
+    /// Check everything the server needs, listing every problem.
+    pub fn check_server(&self) -> Result<()> {
+        let mut errs = Vec::new();
+        self.check_common(&mut errs);
+        // Redis is only used to hand notifications to the worker.
+        if self.features.notifications {
+            self.check_redis(&mut errs);
+        }
+        match self.decode_session_key() {
+            Some(Err(err)) => errs.push(err),
+            None if !self.session.generate_key => {
+                errs.push("session.key (SESSION_KEY) must be set".to_string())
+            }
+            _ => {}
+        }
+        if self.session.ttl_hours <= 0 {
+            errs.push("session.ttl_hours must be at least 1".to_string());
+        }
+        if let Some(err) = &self.provider_error {
+            errs.push(err.clone());
+        } else if self.auth.providers.is_empty() {
+            errs.push("At least one auth provider must be configured".to_string());
+        }
+        let mut names: Vec<&str> = self
+            .auth
+            .providers
+            .iter()
+            .map(|provider| provider.name.as_str())
+            .collect();
+        names.sort();
+        // User IDs from generic providers start with the name, which keeps them apart from
+        // `DELETED_USER_ID`.
+        if names.first().is_some_and(|name| name.is_empty()) {
+            errs.push("Auth provider names cannot be empty".to_string());
+        }
+        for pair in names.windows(2).filter(|pair| pair[0] == pair[1]) {
+            errs.push(format!("Auth provider {} is configured twice", pair[0]));
+        }
+        check(errs)
+    }
+
+    /// Check everything `import` needs, listing every problem.
+    pub fn check_import(&self) -> Result<()> {
+        let mut errs = Vec::new();
+        self.check_common(&mut errs);
+        // To notify riders placed in imported cars.
+        if self.features.notifications {
+            self.check_redis(&mut errs);
+        }
+        check(errs)
+    }
+
+    /// Check everything `admin` needs, listing every problem.
+    pub fn check_admin(&self) -> Result<()> {
+        let mut errs = Vec::new();
+        self.check_common(&mut errs);
+        // Only to queue notifications again.
+        if self.features.notifications {
+            self.check_redis(&mut errs);
+        }
+        check(errs)
+    }
+
+    /// Check everything the worker needs, listing every problem.
+    pub fn check_worker(&self) -> Result<()> {
+        let mut errs = Vec::new();
+        self.check_common(&mut errs);
+        self.check_redis(&mut errs);
+        if self.worker.concurrency == 0 {
+            errs.push("worker.concurrency (WORKER_CONCURRENCY) must be at least 1".to_string());
+        }
+        if self.worker.lease_seconds < 3 {
+            errs.push("worker.lease_seconds (WORKER_LEASE_SECONDS) must be at least 3".to_string());
+        }
+        if self.retention.interval_hours == 0 {
+            errs.push(
+                "retention.interval_hours (RETENTION_INTERVAL_HOURS) must be at least 1"
+                    .to_string(),
+            );
+        }
+        if self.features.notifications {
+            let routes = &self.pings.routes;
+            for (name, value) in [
+                ("pings.base_url (PINGS_BASE_URL)", &self.pings.base_url),
+                ("pings.token (PINGS_TOKEN)", &self.pings.token),
+                ("pings.routes.join (PINGS_JOIN_ROUTE)", &routes.join),
+                ("pings.routes.leave (PINGS_LEAVE_ROUTE)", &routes.leave),
+                ("pings.routes.add (PINGS_ADD_ROUTE)", &routes.add),
+                ("pings.routes.remove (PINGS_REMOVE_ROUTE)", &routes.remove),
+                (
+                    "pings.routes.transfer (PINGS_TRANSFER_ROUTE)",
+                    &routes.transfer,
+                ),
+            ] {
+                if value.is_empty() {
+                    errs.push(format!("{} must be set", name));
+                }
+            }
+            errs.extend(self.notifications.check());
+        }
+        check(errs)
+    }
+
+    fn decode_session_key(&self) -> Option<Result<Key, String>> {
+        let key = self.session.key.as_ref()?;
+        Some(match BASE64_STANDARD.decode(key) {
+            Ok(key) if key.len() >= 64 => Ok(Key::from(&key)),
+            Ok(_) => Err("session.key (SESSION_KEY) must be at least 64 bytes".to_string()),
+            Err(err) => Err(format!(
+                "session.key (SESSION_KEY) is not valid base64: {}",
+                err
+            )),
+        })
+    }
+
+    /// The session settings to run with. Only call this once, since with `generate_key` and no
+    /// configured key each call makes a new one.
+    pub fn session_settings(&self) -> Result<SessionSettings> {
+        let key = match self.decode_session_key() {
+            Some(key) => key.map_err(|err| anyhow!(err))?,
+            None if self.session.generate_key => {
+                warn!(
+                    "SESSION_KEY is not set. Everyone will be logged out when the server restarts."
+                );
+                Key::generate()
+            }
+            None => return Err(anyhow!("session.key (SESSION_KEY) must be set")),
+        };
+        Ok(SessionSettings {
+            key,
+            ttl: Duration::hours(self.session.ttl_hours),
+            secure: self.session.secure,
+        })
+    }
+}
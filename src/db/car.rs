@@ -2,9 +2,13 @@ use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as, Executor, Postgres};
+use tracing::instrument;
 use utoipa::ToSchema;
 
 use crate::db::user::UserData;
+use crate::error::FieldError;
+use crate::metrics;
+use crate::validation::{ValidationRules, Validator};
 
 #[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
@@ -17,23 +21,62 @@ pub struct CarData {
 }
 
 impl CarData {
-    pub fn validate(&self, user: &String, other_cars: Vec<Car>) -> Result<(), Vec<String>> {
-        let mut errs = Vec::new();
+    pub fn validate(
+        &self,
+        user: &String,
+        other_cars: Vec<Car>,
+        rules: &ValidationRules,
+    ) -> Result<(), Vec<FieldError>> {
+        let mut errs = Validator::default();
         if self.return_time < self.departure_time {
-            errs.push("Return time cannot be before departure.".to_string())
+            errs.add(FieldError::new(
+                "returnTime",
+                "before_departure",
+                "Return time cannot be before departure.",
+            ));
         }
         if self.departure_time < Utc::now() {
-            errs.push("Car cannot leave in the past.".to_string());
+            errs.add(FieldError::new(
+                "departureTime",
+                "in_past",
+                "Car cannot leave in the past.",
+            ));
         }
         if self.max_capacity < 0 {
-            errs.push("Capacity must be greater than or equal to 0".to_string());
+            errs.add(
+                FieldError::new(
+                    "maxCapacity",
+                    "too_small",
+                    "Capacity must be greater than or equal to 0",
+                )
+                .with_param("min", 0),
+            );
+        } else if self.max_capacity > rules.max_capacity {
+            errs.add(
+                FieldError::new(
+                    "maxCapacity",
+                    "too_large",
+                    format!("Capacity cannot be more than {}.", rules.max_capacity),
+                )
+                .with_param("max", rules.max_capacity),
+            );
         }
         if self.riders.len() > (self.max_capacity as usize) {
-            errs.push("You have too many riders for your capacity.".to_string());
-        }
-        if self.riders.contains(user) {
-            errs.push("You cannot be a rider in your own car.".to_string());
+            errs.add(
+                FieldError::new(
+                    "riders",
+                    "too_many",
+                    "You have too many riders for your capacity.",
+                )
+                .with_param("max", self.max_capacity),
+            );
         }
+        errs.max_length(
+            "comment",
+            "Comment",
+            &self.comment,
+            rules.max_comment_length,
+        );
         let other_car_members: Vec<String> = other_cars
             .iter()
             .filter_map(|car| {
@@ -48,22 +91,30 @@ impl CarData {
             })
             .map(|user| user.id)
             .collect();
-        for rider in self.riders.iter() {
-            if other_car_members.contains(rider) {
-                errs.push(format!(
-                    "{} is already in another car or is a driver.",
-                    rider
-                ))
+        for (i, rider) in self.riders.iter().enumerate() {
+            let field = format!("riders[{}]", i);
+            if rider == user {
+                errs.add(FieldError::new(
+                    &field,
+                    "is_driver",
+                    "You cannot be a rider in your own car.",
+                ));
+            } else if other_car_members.contains(rider) {
+                errs.add(
+                    FieldError::new(
+                        &field,
+                        "already_taken",
+                        format!("{} is already in another car or is a driver.", rider),
+                    )
+                    .with_param("user", rider.clone()),
+                );
             }
         }
-        if !errs.is_empty() {
-            return Err(errs);
-        }
-        Ok(())
+        errs.finish()
     }
 }
 
-#[derive(Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+#[derive(Serialize, Deserialize, sqlx::FromRow, ToSchema, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Car {
     pub id: i32,
@@ -77,6 +128,7 @@ pub struct Car {
 }
 
 impl Car {
+    #[instrument(name = "car::insert_new", skip_all)]
     pub async fn insert_new<'c, C>(
         event_id: i32,
         driver_id: String,
@@ -86,6 +138,7 @@ impl Car {
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("car::insert_new");
         query_as!(
             Car,
             r#"
@@ -93,18 +146,11 @@ impl Car {
                 INSERT INTO car (event_id, driver, max_capacity, departure_time, return_time, comment)
                 VALUES ($1, $2, $3, $4, $5, $6) RETURNING *
             )
-            SELECT car.id, car.event_id, car.max_capacity, car.departure_time, car.return_time, car.comment,
+            SELECT new_car.id, new_car.event_id, new_car.max_capacity, new_car.departure_time, new_car.return_time, new_car.comment,
             (driverUser.id, driverUser.realm::text, driverUser.name, driverUser.email) AS "driver!: UserData",
-            ARRAY_REMOVE(ARRAY_AGG(
-                CASE WHEN riderUser.id IS NOT NULL
-                THEN (riderUser.id, riderUser.realm::text, riderUser.name, riderUser.email)
-                END
-            ), NULL) as "riders!: Vec<UserData>"
-            FROM car
-            JOIN users driverUser ON car.driver = driverUser.id
-            LEFT JOIN rider on car.id = rider.car_id
-            LEFT JOIN users riderUser ON rider.rider = riderUser.id
-            GROUP BY car.id, driverUser.id
+            ARRAY[]::record[] as "riders!: Vec<UserData>"
+            FROM new_car
+            JOIN users driverUser ON new_car.driver = driverUser.id
             "#,
             event_id,
             driver_id,
@@ -116,6 +162,7 @@ impl Car {
         .fetch_one(conn)
         .await.map_err(|err| anyhow!("Failed to Create Car: {}", err))
     }
+    #[instrument(name = "car::update", skip_all)]
     pub async fn update<'c, C>(
         id: i32,
         event_id: i32,
@@ -126,6 +173,7 @@ impl Car {
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("car::update");
         sqlx::query_as!(
             Car,
             r#"
@@ -161,10 +209,12 @@ impl Car {
         .fetch_optional(conn)
         .await.map_err(|err| anyhow!("Failed to update Car: {}", err))
     }
+    #[instrument(name = "car::select_all", skip_all)]
     pub async fn select_all<'c, C>(event_id: i32, conn: C) -> Result<Vec<Self>>
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("car::select_all");
         query_as!(
             Car,
             r#"SELECT car.id, car.event_id, car.max_capacity, car.departure_time, car.return_time, car.comment,
@@ -183,10 +233,12 @@ impl Car {
             .fetch_all(conn)
             .await.map_err(|err| anyhow!("Failed to get cars: {}", err))
     }
+    #[instrument(name = "car::select_one", skip_all)]
     pub async fn select_one<'c, C>(event_id: i32, car_id: i32, conn: C) -> Result<Option<Self>>
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("car::select_one");
         query_as!(
             Car,
             r#"SELECT car.id, car.event_id, car.max_capacity, car.departure_time, car.return_time, car.comment,
@@ -207,10 +259,12 @@ impl Car {
         .fetch_optional(conn)
         .await.map_err(|err| anyhow!("Failed to get car: {}", err))
     }
+    #[instrument(name = "car::user_in_car", skip_all)]
     pub async fn user_in_car<'c, C>(event_id: i32, user_id: &String, conn: C) -> Result<bool>
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("car::user_in_car");
         match query!(
             r#"
             SELECT COUNT(*) as count
@@ -235,6 +289,29 @@ impl Car {
             Err(err) => Err(anyhow!("Failed to get Car Data: {}", err)),
         }
     }
+    #[instrument(name = "car::update_driver", skip_all)]
+    pub async fn update_driver<'c, C>(
+        id: i32,
+        event_id: i32,
+        driver_id: &String,
+        conn: C,
+    ) -> Result<Option<i32>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("car::update_driver");
+        query!(
+            "UPDATE car SET driver = $1 WHERE event_id = $2 AND id = $3 RETURNING id",
+            driver_id,
+            event_id,
+            id
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.id))
+        .map_err(|err| anyhow!("Failed to change driver: {}", err))
+    }
+    #[instrument(name = "car::delete", skip_all)]
     pub async fn delete<'c, C>(
         id: i32,
         event_id: i32,
@@ -244,6 +321,7 @@ impl Car {
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("car::delete");
         query!(
             "DELETE FROM car WHERE event_id = $1 AND id = $2 AND driver = $3 RETURNING id",
             event_id,
@@ -255,4 +333,51 @@ impl Car {
         .map(|res| res.map(|rec| rec.id))
         .map_err(|err| anyhow!("Failed to Delete Car: {}", err))
     }
+    #[instrument(name = "car::add_rider", skip_all)]
+    pub async fn add_rider<'c, C>(id: i32, rider_id: &str, conn: C) -> Result<()>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("car::add_rider");
+        query!(
+            "INSERT INTO rider (car_id, rider) VALUES ($1, $2)",
+            id,
+            rider_id
+        )
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(|err| anyhow!("Failed to add rider: {}", err))
+    }
+    /// Returns whether the rider was in the car.
+    #[instrument(name = "car::remove_rider", skip_all)]
+    pub async fn remove_rider<'c, C>(id: i32, rider_id: &str, conn: C) -> Result<bool>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("car::remove_rider");
+        query!(
+            "DELETE FROM rider WHERE car_id = $1 AND rider = $2",
+            id,
+            rider_id
+        )
+        .execute(conn)
+        .await
+        .map(|res| res.rows_affected() > 0)
+        .map_err(|err| anyhow!("Failed to remove rider: {}", err))
+    }
+    /// Hold the car's row until the transaction ends, so its riders can be counted and changed
+    /// without another transaction doing the same in between.
+    #[instrument(name = "car::lock", skip_all)]
+    pub async fn lock<'c, C>(id: i32, conn: C) -> Result<()>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("car::lock");
+        query!("SELECT id FROM car WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| anyhow!("Failed to lock car: {}", err))
+    }
 }
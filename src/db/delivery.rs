@@ -0,0 +1,68 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{query, Executor, Postgres};
+use tracing::instrument;
+
+use crate::metrics;
+
+/// A notification a job has delivered, so a retried job can skip the recipients it already
+/// reached.
+pub struct Delivery;
+
+impl Delivery {
+    #[instrument(name = "delivery::exists", skip_all)]
+    pub async fn exists<'c, C>(job_id: &str, recipient: &str, conn: C) -> Result<bool>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("delivery::exists");
+        query!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM notification_delivery WHERE job_id = $1 AND recipient = $2
+            ) AS "exists!"
+            "#,
+            job_id,
+            recipient
+        )
+        .fetch_one(conn)
+        .await
+        .map(|rec| rec.exists)
+        .map_err(|err| anyhow!("Failed to check delivery: {}", err))
+    }
+    #[instrument(name = "delivery::insert", skip_all)]
+    pub async fn insert<'c, C>(job_id: &str, recipient: &str, conn: C) -> Result<()>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("delivery::insert");
+        query!(
+            r#"
+            INSERT INTO notification_delivery (job_id, recipient) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            job_id,
+            recipient
+        )
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(|err| anyhow!("Failed to record delivery: {}", err))
+    }
+    /// Forget deliveries made before `cutoff`, once their jobs can no longer be retried.
+    #[instrument(name = "delivery::delete_before", skip_all)]
+    pub async fn delete_before<'c, C>(cutoff: DateTime<Utc>, conn: C) -> Result<u64>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("delivery::delete_before");
+        query!(
+            r#"DELETE FROM notification_delivery WHERE delivered_at < $1"#,
+            cutoff
+        )
+        .execute(conn)
+        .await
+        .map(|res| res.rows_affected())
+        .map_err(|err| anyhow!("Failed to delete deliveries: {}", err))
+    }
+}
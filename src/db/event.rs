@@ -1,10 +1,14 @@
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{query_as, Executor, Postgres};
+use sqlx::{query, query_as, Executor, PgConnection, Postgres};
+use tracing::instrument;
 use utoipa::ToSchema;
 
-use crate::db::user::UserData;
+use crate::db::user::{UserData, DELETED_USER_ID};
+use crate::error::FieldError;
+use crate::metrics;
+use crate::validation::{ValidationRules, Validator};
 
 #[derive(Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 #[serde(rename_all = "camelCase")]
@@ -27,32 +31,54 @@ pub struct EventData {
 }
 
 impl EventData {
-    pub fn validate(&self) -> Result<(), Vec<String>> {
-        let mut errs = Vec::new();
-        if self.name.is_empty() {
-            errs.push("Missing Name.".to_string());
-        }
-        if self.location.is_empty() {
-            errs.push("Missing Location.".to_string());
-        }
+    pub fn validate(&self, rules: &ValidationRules) -> Result<(), Vec<FieldError>> {
+        let mut errs = Validator::default();
+        errs.required("name", &self.name, "Missing Name.");
+        errs.max_length("name", "Name", &self.name, rules.max_name_length);
+        errs.required("location", &self.location, "Missing Location.");
+        errs.max_length(
+            "location",
+            "Location",
+            &self.location,
+            rules.max_location_length,
+        );
         if self.start_time > self.end_time {
-            errs.push("Start date cannot be after end date.".to_string());
+            errs.add(FieldError::new(
+                "endTime",
+                "before_start",
+                "Start date cannot be after end date.",
+            ));
+        } else if self.end_time - self.start_time > rules.max_event_duration {
+            errs.add(
+                FieldError::new(
+                    "endTime",
+                    "too_long",
+                    format!(
+                        "Event cannot be longer than {} hours.",
+                        rules.max_event_duration.num_hours()
+                    ),
+                )
+                .with_param("maxHours", rules.max_event_duration.num_hours()),
+            );
         }
         if self.end_time < Utc::now() {
-            errs.push("Event cannot be in the past.".to_string())
+            errs.add(FieldError::new(
+                "endTime",
+                "in_past",
+                "Event cannot be in the past.",
+            ));
         }
-        if !errs.is_empty() {
-            return Err(errs);
-        }
-        Ok(())
+        errs.finish()
     }
 }
 
 impl Event {
+    #[instrument(name = "event::insert_new", skip_all)]
     pub async fn insert_new<'c, C>(data: &EventData, creator_id: String, conn: C) -> Result<Self>
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("event::insert_new");
         query_as!(
             Event,
             r#"
@@ -68,6 +94,7 @@ impl Event {
         .fetch_one(conn)
         .await.map_err(|err| anyhow!("Failed to Create Event: {}", err))
     }
+    #[instrument(name = "event::update", skip_all)]
     pub async fn update<'c, C>(
         id: i32,
         creator_id: String,
@@ -77,6 +104,7 @@ impl Event {
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("event::update");
         sqlx::query_as!(
             Event,
             r#"
@@ -103,10 +131,12 @@ impl Event {
         .fetch_optional(conn)
         .await.map_err(|err| anyhow!("Failed to update Event: {}", err))
     }
+    #[instrument(name = "event::select_all", skip_all)]
     pub async fn select_all<'c, C>(past: bool, conn: C) -> Result<Vec<Self>>
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("event::select_all");
         query_as!(
             Event,
             r#"
@@ -124,10 +154,12 @@ impl Event {
         .await
         .map_err(|err| anyhow!("Failed to Get Events: {}", err))
     }
+    #[instrument(name = "event::select_one", skip_all)]
     pub async fn select_one<'c, C>(id: i32, conn: C) -> Result<Option<Self>>
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("event::select_one");
         query_as!(
             Event,
             r#"
@@ -144,10 +176,12 @@ impl Event {
         .await
         .map_err(|err| anyhow!("Failed to Get Events: {}", err))
     }
+    #[instrument(name = "event::delete", skip_all)]
     pub async fn delete<'c, C>(id: i32, creator_id: String, conn: C) -> Result<Option<i32>>
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("event::delete");
         sqlx::query!(
             "DELETE FROM event WHERE id = $1 AND creator = $2 RETURNING id",
             id,
@@ -158,4 +192,93 @@ impl Event {
         .map(|res| res.map(|rec| rec.id))
         .map_err(|err| anyhow!("Failed to Delete Event: {}", err))
     }
+    /// Delete every event that ended before `cutoff`, with its cars and ride requests.
+    #[instrument(name = "event::delete_ended_before", skip_all)]
+    pub async fn delete_ended_before<'c, C>(cutoff: DateTime<Utc>, conn: C) -> Result<Vec<i32>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("event::delete_ended_before");
+        sqlx::query!("DELETE FROM event WHERE end_time < $1 RETURNING id", cutoff)
+            .fetch_all(conn)
+            .await
+            .map(|res| res.into_iter().map(|rec| rec.id).collect())
+            .map_err(|err| anyhow!("Failed to Delete Events: {}", err))
+    }
+    /// Remove who rode in, asked for a ride to or was offered a car in every event that ended
+    /// before `cutoff`, clear the drivers' comments, and move the cars and events to
+    /// `DELETED_USER_ID`. The events and cars are kept. Returns how many rows were removed or
+    /// changed.
+    #[instrument(name = "event::anonymize_ended_before", skip_all)]
+    pub async fn anonymize_ended_before(
+        cutoff: DateTime<Utc>,
+        conn: &mut PgConnection,
+    ) -> Result<u64> {
+        let _timer = metrics::time_query("event::anonymize_ended_before");
+        let riders = query!(
+            r#"
+            DELETE FROM rider USING car, event
+            WHERE rider.car_id = car.id AND car.event_id = event.id AND event.end_time < $1
+            "#,
+            cutoff
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to remove riders: {}", err))?;
+        let requests = query!(
+            r#"
+            DELETE FROM ride_request USING event
+            WHERE ride_request.event_id = event.id AND event.end_time < $1
+            "#,
+            cutoff
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to remove ride requests: {}", err))?;
+        let transfers = query!(
+            r#"
+            DELETE FROM car_transfer USING car, event
+            WHERE car_transfer.car_id = car.id AND car.event_id = event.id AND event.end_time < $1
+            "#,
+            cutoff
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to remove transfers: {}", err))?;
+        let comments = query!(
+            r#"
+            UPDATE car SET comment = '' FROM event
+            WHERE car.event_id = event.id AND event.end_time < $1 AND car.comment <> ''
+            "#,
+            cutoff
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to clear comments: {}", err))?;
+        let drivers = query!(
+            r#"
+            UPDATE car SET driver = $2 FROM event
+            WHERE car.event_id = event.id AND event.end_time < $1 AND car.driver <> $2
+            "#,
+            cutoff,
+            DELETED_USER_ID
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to remove drivers: {}", err))?;
+        let creators = query!(
+            r#"UPDATE event SET creator = $2 WHERE end_time < $1 AND creator <> $2"#,
+            cutoff,
+            DELETED_USER_ID
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to remove creators: {}", err))?;
+        Ok(riders.rows_affected()
+            + requests.rows_affected()
+            + transfers.rows_affected()
+            + comments.rows_affected()
+            + drivers.rows_affected()
+            + creators.rows_affected())
+    }
 }
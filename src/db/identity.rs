@@ -0,0 +1,257 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, PgConnection, Postgres};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::metrics;
+
+/// A login from one auth provider. Every identity belongs to exactly one user, and a user may
+/// have one identity per provider they have linked.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserIdentity {
+    pub realm: String,
+    pub subject: String,
+    pub email: String,
+}
+
+impl UserIdentity {
+    /// The id of the user this identity logs in as.
+    #[instrument(name = "identity::select_user", skip_all)]
+    pub async fn select_user<'c, C>(realm: &str, subject: &str, conn: C) -> Result<Option<String>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("identity::select_user");
+        query!(
+            r#"SELECT user_id FROM user_identity WHERE realm = $1 AND subject = $2"#,
+            realm,
+            subject
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.user_id))
+        .map_err(|err| anyhow!("Failed to get identity: {}", err))
+    }
+    #[instrument(name = "identity::insert_new", skip_all)]
+    pub async fn insert_new<'c, C>(
+        realm: &str,
+        subject: &str,
+        user_id: &str,
+        email: &str,
+        conn: C,
+    ) -> Result<()>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("identity::insert_new");
+        query!(
+            r#"
+            INSERT INTO user_identity (realm, subject, user_id, email) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (realm, subject) DO UPDATE SET email = EXCLUDED.email
+            "#,
+            realm,
+            subject,
+            user_id,
+            email
+        )
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(|err| anyhow!("Failed to insert identity: {}", err))
+    }
+    #[instrument(name = "identity::select_all", skip_all)]
+    pub async fn select_all<'c, C>(user_id: &str, conn: C) -> Result<Vec<Self>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("identity::select_all");
+        query_as!(
+            UserIdentity,
+            r#"SELECT realm, subject, email FROM user_identity WHERE user_id = $1 ORDER BY realm"#,
+            user_id
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get identities: {}", err))
+    }
+    /// Unlink an identity. The one the user's id came from can't be removed, since the user
+    /// would no longer be able to log in as themselves with it.
+    #[instrument(name = "identity::delete", skip_all)]
+    pub async fn delete<'c, C>(user_id: &str, realm: &str, conn: C) -> Result<Option<String>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("identity::delete");
+        query!(
+            r#"
+            DELETE FROM user_identity
+            WHERE user_id = $1 AND realm = $2 AND subject != user_id
+            RETURNING subject
+            "#,
+            user_id,
+            realm
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.subject))
+        .map_err(|err| anyhow!("Failed to delete identity: {}", err))
+    }
+    /// An event both users drive a car in. They can't be merged until one of the cars is gone,
+    /// since nobody can drive two cars to the same event.
+    #[instrument(name = "identity::shared_event", skip_all)]
+    pub async fn shared_event<'c, C>(old_id: &str, new_id: &str, conn: C) -> Result<Option<i32>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("identity::shared_event");
+        query!(
+            r#"
+            SELECT old.event_id AS "event_id!" FROM car old
+            JOIN car new ON new.event_id = old.event_id
+            WHERE old.driver = $1 AND new.driver = $2
+            LIMIT 1
+            "#,
+            old_id,
+            new_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.event_id))
+        .map_err(|err| anyhow!("Failed to check for shared events: {}", err))
+    }
+    /// Move everything owned by `old_id` to `new_id` and delete the old user. Fails if both
+    /// drive in the same event. Otherwise the merged user keeps one place per event: their car
+    /// if either drove, else `new_id`'s seat, else `old_id`'s. Ride requests for events they
+    /// have a place in, and transfers of their own cars to themselves, are dropped.
+    #[instrument(name = "identity::merge", skip_all)]
+    pub async fn merge(old_id: &str, new_id: &str, conn: &mut PgConnection) -> Result<()> {
+        let _timer = metrics::time_query("identity::merge");
+        if let Some(event_id) = Self::shared_event(old_id, new_id, &mut *conn).await? {
+            return Err(anyhow!(
+                "Both users drive a car in event {}, delete one of the cars first",
+                event_id
+            ));
+        }
+        query!(
+            r#"
+            DELETE FROM rider USING car
+            WHERE car.id = rider.car_id AND rider.rider IN ($1, $2)
+            AND car.event_id IN (SELECT event_id FROM car WHERE driver IN ($1, $2))
+            "#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge riders: {}", err))?;
+        query!(
+            r#"
+            DELETE FROM rider USING car
+            WHERE car.id = rider.car_id AND rider.rider = $1
+            AND car.event_id IN (
+                SELECT kept.event_id FROM rider JOIN car kept ON kept.id = rider.car_id
+                WHERE rider.rider = $2
+            )
+            "#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge riders: {}", err))?;
+        query!(
+            r#"UPDATE rider SET rider = $2 WHERE rider = $1"#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge riders: {}", err))?;
+        query!(
+            r#"
+            DELETE FROM ride_request WHERE rider IN ($1, $2)
+            AND event_id IN (
+                SELECT event_id FROM car WHERE driver IN ($1, $2)
+                UNION
+                SELECT car.event_id FROM rider JOIN car ON car.id = rider.car_id
+                WHERE rider.rider IN ($1, $2)
+            )
+            "#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge ride requests: {}", err))?;
+        query!(
+            r#"
+            DELETE FROM ride_request WHERE rider = $1
+            AND event_id IN (SELECT event_id FROM ride_request WHERE rider = $2)
+            "#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge ride requests: {}", err))?;
+        query!(
+            r#"UPDATE ride_request SET rider = $2 WHERE rider = $1"#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge ride requests: {}", err))?;
+        query!(
+            r#"UPDATE event SET creator = $2 WHERE creator = $1"#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge events: {}", err))?;
+        query!(
+            r#"UPDATE car SET driver = $2 WHERE driver = $1"#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge cars: {}", err))?;
+        query!(
+            r#"
+            DELETE FROM car_transfer USING car
+            WHERE car.id = car_transfer.car_id
+            AND car.driver IN ($1, $2) AND car_transfer.new_driver IN ($1, $2)
+            "#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge transfers: {}", err))?;
+        query!(
+            r#"UPDATE car_transfer SET new_driver = $2 WHERE new_driver = $1"#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge transfers: {}", err))?;
+        query!(
+            r#"UPDATE user_identity SET user_id = $2 WHERE user_id = $1"#,
+            old_id,
+            new_id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to merge identities: {}", err))?;
+        query!(r#"DELETE FROM users WHERE id = $1"#, old_id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| anyhow!("Failed to delete merged user: {}", err))?;
+        Ok(())
+    }
+}
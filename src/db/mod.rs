@@ -1,3 +1,10 @@
 pub mod car;
+pub mod delivery;
 pub mod event;
+pub mod identity;
+pub mod ride_request;
+pub mod session;
+pub mod stats;
+pub mod token;
+pub mod transfer;
 pub mod user;
@@ -0,0 +1,169 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, Postgres};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::db::user::UserData;
+use crate::error::FieldError;
+use crate::metrics;
+use crate::validation::{ValidationRules, Validator};
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RideRequestData {
+    pub earliest_departure: Option<DateTime<Utc>>,
+    pub latest_departure: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub note: String,
+}
+
+impl RideRequestData {
+    pub fn validate(&self, rules: &ValidationRules) -> Result<(), Vec<FieldError>> {
+        let mut errs = Validator::default();
+        if let (Some(earliest), Some(latest)) = (self.earliest_departure, self.latest_departure) {
+            if latest < earliest {
+                errs.add(FieldError::new(
+                    "latestDeparture",
+                    "before_start",
+                    "Latest departure cannot be before earliest departure.",
+                ));
+            }
+        }
+        if let Some(latest) = self.latest_departure {
+            if latest < Utc::now() {
+                errs.add(FieldError::new(
+                    "latestDeparture",
+                    "in_past",
+                    "Departure window cannot be in the past.",
+                ));
+            }
+        }
+        errs.max_length("note", "Note", &self.note, rules.max_note_length);
+        errs.finish()
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteData {
+    pub rider: String,
+}
+
+/// The departure window of a request that was cleared.
+pub struct RideWindow {
+    pub rider: String,
+    pub earliest_departure: Option<DateTime<Utc>>,
+    pub latest_departure: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RideRequest {
+    pub event_id: i32,
+    pub rider: UserData,
+    pub earliest_departure: Option<DateTime<Utc>>,
+    pub latest_departure: Option<DateTime<Utc>>,
+    pub note: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RideRequest {
+    #[instrument(name = "ride_request::insert_new", skip_all)]
+    pub async fn insert_new<'c, C>(
+        event_id: i32,
+        rider_id: String,
+        data: &RideRequestData,
+        conn: C,
+    ) -> Result<()>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("ride_request::insert_new");
+        query!(
+            r#"
+            INSERT INTO ride_request (event_id, rider, earliest_departure, latest_departure, note)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (event_id, rider) DO UPDATE SET
+            earliest_departure = EXCLUDED.earliest_departure,
+            latest_departure = EXCLUDED.latest_departure,
+            note = EXCLUDED.note
+            "#,
+            event_id,
+            rider_id,
+            data.earliest_departure,
+            data.latest_departure,
+            data.note
+        )
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(|err| anyhow!("Failed to create ride request: {}", err))
+    }
+    #[instrument(name = "ride_request::select_all", skip_all)]
+    pub async fn select_all<'c, C>(event_id: i32, conn: C) -> Result<Vec<Self>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("ride_request::select_all");
+        query_as!(
+            RideRequest,
+            r#"
+            SELECT ride_request.event_id, ride_request.earliest_departure, ride_request.latest_departure,
+            ride_request.note, ride_request.created_at,
+            (users.id, users.realm::text, users.name, users.email) AS "rider!: UserData"
+            FROM ride_request
+            JOIN users ON ride_request.rider = users.id
+            WHERE ride_request.event_id = $1
+            ORDER BY ride_request.created_at ASC
+            "#,
+            event_id
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get ride requests: {}", err))
+    }
+    #[instrument(name = "ride_request::delete", skip_all)]
+    pub async fn delete<'c, C>(event_id: i32, rider_id: String, conn: C) -> Result<Option<String>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("ride_request::delete");
+        query!(
+            "DELETE FROM ride_request WHERE event_id = $1 AND rider = $2 RETURNING rider",
+            event_id,
+            rider_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.rider))
+        .map_err(|err| anyhow!("Failed to delete ride request: {}", err))
+    }
+    /// Remove the requests of every listed rider, returning the windows of the riders that
+    /// actually had one.
+    #[instrument(name = "ride_request::delete_many", skip_all)]
+    pub async fn delete_many<'c, C>(
+        event_id: i32,
+        rider_ids: &[String],
+        conn: C,
+    ) -> Result<Vec<RideWindow>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("ride_request::delete_many");
+        query_as!(
+            RideWindow,
+            r#"
+            DELETE FROM ride_request
+            WHERE event_id = $1 AND rider IN (SELECT UNNEST($2::VARCHAR[]))
+            RETURNING rider, earliest_departure, latest_departure
+            "#,
+            event_id,
+            rider_ids
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to delete ride requests: {}", err))
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, Postgres};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::metrics;
+
+/// A logged in session as shown to its user. The session key itself is never exposed, only
+/// the `session_id` stored alongside it at login.
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default)]
+    pub current: bool,
+}
+
+impl SessionInfo {
+    #[instrument(name = "session::select_state", skip_all)]
+    pub async fn select_state<'c, C>(key_hash: &str, conn: C) -> Result<Option<String>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("session::select_state");
+        query!(
+            r#"SELECT state FROM session WHERE key_hash = $1 AND expires_at > NOW()"#,
+            key_hash
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.state))
+        .map_err(|err| anyhow!("Failed to load session: {}", err))
+    }
+    #[instrument(name = "session::insert_new", skip_all)]
+    pub async fn insert_new<'c, C>(
+        key_hash: &str,
+        user_id: Option<String>,
+        session_id: Option<String>,
+        state: &str,
+        expires_at: DateTime<Utc>,
+        conn: C,
+    ) -> Result<()>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("session::insert_new");
+        query!(
+            r#"
+            INSERT INTO session (key_hash, user_id, session_id, state, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            key_hash,
+            user_id,
+            session_id,
+            state,
+            expires_at
+        )
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(|err| anyhow!("Failed to save session: {}", err))
+    }
+    /// Returns false if the session no longer exists, e.g. because it was revoked.
+    #[instrument(name = "session::update", skip_all)]
+    pub async fn update<'c, C>(
+        key_hash: &str,
+        user_id: Option<String>,
+        session_id: Option<String>,
+        state: &str,
+        expires_at: DateTime<Utc>,
+        conn: C,
+    ) -> Result<bool>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("session::update");
+        query!(
+            r#"
+            UPDATE session SET user_id = $2, session_id = $3, state = $4, expires_at = $5,
+            last_seen = NOW()
+            WHERE key_hash = $1 AND expires_at > NOW()
+            "#,
+            key_hash,
+            user_id,
+            session_id,
+            state,
+            expires_at
+        )
+        .execute(conn)
+        .await
+        .map(|res| res.rows_affected() > 0)
+        .map_err(|err| anyhow!("Failed to update session: {}", err))
+    }
+    #[instrument(name = "session::update_expiry", skip_all)]
+    pub async fn update_expiry<'c, C>(
+        key_hash: &str,
+        expires_at: DateTime<Utc>,
+        conn: C,
+    ) -> Result<()>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("session::update_expiry");
+        query!(
+            r#"
+            UPDATE session SET expires_at = $2, last_seen = NOW()
+            WHERE key_hash = $1 AND expires_at > NOW()
+            "#,
+            key_hash,
+            expires_at
+        )
+        .execute(conn)
+        .await
+        .map(|_| ())
+        .map_err(|err| anyhow!("Failed to extend session: {}", err))
+    }
+    #[instrument(name = "session::delete", skip_all)]
+    pub async fn delete<'c, C>(key_hash: &str, conn: C) -> Result<()>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("session::delete");
+        query!(r#"DELETE FROM session WHERE key_hash = $1"#, key_hash)
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| anyhow!("Failed to delete session: {}", err))
+    }
+    /// Returns how many sessions were deleted.
+    #[instrument(name = "session::delete_expired", skip_all)]
+    pub async fn delete_expired<'c, C>(conn: C) -> Result<u64>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("session::delete_expired");
+        query!(r#"DELETE FROM session WHERE expires_at <= NOW()"#)
+            .execute(conn)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(|err| anyhow!("Failed to delete expired sessions: {}", err))
+    }
+    #[instrument(name = "session::select_all", skip_all)]
+    pub async fn select_all<'c, C>(user_id: &str, conn: C) -> Result<Vec<SessionInfo>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("session::select_all");
+        query_as!(
+            SessionInfo,
+            r#"
+            SELECT session_id AS "id!", created_at, last_seen, expires_at, FALSE AS "current!"
+            FROM session
+            WHERE user_id = $1 AND session_id IS NOT NULL AND expires_at > NOW()
+            ORDER BY last_seen DESC
+            "#,
+            user_id
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get sessions: {}", err))
+    }
+    /// End one of the user's sessions. Returns None if it doesn't exist.
+    #[instrument(name = "session::revoke", skip_all)]
+    pub async fn revoke<'c, C>(user_id: &str, session_id: &str, conn: C) -> Result<Option<()>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("session::revoke");
+        query!(
+            r#"DELETE FROM session WHERE user_id = $1 AND session_id = $2 RETURNING session_id"#,
+            user_id,
+            session_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|_| ()))
+        .map_err(|err| anyhow!("Failed to end session: {}", err))
+    }
+    /// End every session of a user. Returns how many were ended.
+    #[instrument(name = "session::revoke_all", skip_all)]
+    pub async fn revoke_all<'c, C>(user_id: &str, conn: C) -> Result<u64>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("session::revoke_all");
+        query!(r#"DELETE FROM session WHERE user_id = $1"#, user_id)
+            .execute(conn)
+            .await
+            .map(|res| res.rows_affected())
+            .map_err(|err| anyhow!("Failed to end sessions: {}", err))
+    }
+}
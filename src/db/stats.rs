@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, Postgres};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::db::{
+    car::Car,
+    user::{UserData, DELETED_USER_ID},
+};
+use crate::metrics;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DepartureWindow {
+    pub start: DateTime<Utc>,
+    pub cars: i32,
+    pub capacity: i32,
+    pub riders: i32,
+    pub open_seats: i32,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EventSummary {
+    pub event_id: i32,
+    pub drivers: i32,
+    pub total_capacity: i32,
+    pub riders: i32,
+    pub open_seats: i32,
+    pub unplaced: i32,
+    pub departures: Vec<DepartureWindow>,
+}
+
+impl EventSummary {
+    /// Aggregate an event's cars, bucketing departures by the hour they leave in.
+    pub fn from_cars(event_id: i32, cars: &[Car], unplaced: i32) -> Self {
+        let mut windows: BTreeMap<DateTime<Utc>, DepartureWindow> = BTreeMap::new();
+        for car in cars {
+            let riders = car.riders.as_ref().map(|riders| riders.len()).unwrap_or(0) as i32;
+            let start = car
+                .departure_time
+                .duration_trunc(TimeDelta::hours(1))
+                .unwrap_or(car.departure_time);
+            let window = windows.entry(start).or_insert(DepartureWindow {
+                start,
+                cars: 0,
+                capacity: 0,
+                riders: 0,
+                open_seats: 0,
+            });
+            window.cars += 1;
+            window.capacity += car.max_capacity;
+            window.riders += riders;
+            window.open_seats += (car.max_capacity - riders).max(0);
+        }
+        let departures: Vec<DepartureWindow> = windows.into_values().collect();
+        EventSummary {
+            event_id,
+            drivers: cars.len() as i32,
+            total_capacity: departures.iter().map(|window| window.capacity).sum(),
+            riders: departures.iter().map(|window| window.riders).sum(),
+            open_seats: departures.iter().map(|window| window.open_seats).sum(),
+            unplaced,
+            departures,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MonthCount {
+    pub month: DateTime<Utc>,
+    pub events: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DriverCount {
+    pub driver: UserData,
+    pub cars: i64,
+    pub riders: i64,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteStats {
+    pub events_per_month: Vec<MonthCount>,
+    pub rides_given: i64,
+    pub top_drivers: Vec<DriverCount>,
+}
+
+impl SiteStats {
+    #[instrument(name = "stats::select", skip_all)]
+    pub async fn select<'c, C>(conn: C) -> Result<Self>
+    where
+        C: Executor<'c, Database = Postgres> + Copy,
+    {
+        let _timer = metrics::time_query("stats::select");
+        let events_per_month = query_as!(
+            MonthCount,
+            r#"
+            SELECT DATE_TRUNC('month', start_time) AS "month!", COUNT(*) AS "events!"
+            FROM event GROUP BY 1 ORDER BY 1 ASC
+            "#
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get events per month: {}", err))?;
+
+        let rides_given = query!(r#"SELECT COUNT(*) AS "count!" FROM rider"#)
+            .fetch_one(conn)
+            .await
+            .map(|record| record.count)
+            .map_err(|err| anyhow!("Failed to get ride count: {}", err))?;
+
+        let top_drivers = query_as!(
+            DriverCount,
+            r#"
+            SELECT (users.id, users.realm::text, users.name, users.email) AS "driver!: UserData",
+            COUNT(DISTINCT car.id) AS "cars!", COUNT(rider.rider) AS "riders!"
+            FROM car
+            JOIN users ON car.driver = users.id
+            LEFT JOIN rider ON car.id = rider.car_id
+            WHERE users.id <> $1
+            GROUP BY users.id
+            ORDER BY 3 DESC, 2 DESC
+            LIMIT 10
+            "#,
+            DELETED_USER_ID
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get top drivers: {}", err))?;
+
+        Ok(SiteStats {
+            events_per_month,
+            rides_given,
+            top_drivers,
+        })
+    }
+}
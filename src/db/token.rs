@@ -0,0 +1,193 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, Postgres};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::db::user::UserData;
+use crate::metrics;
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// Read anything the user can see.
+    Read,
+    /// Drive, ride in and request rides in cars.
+    ManageCars,
+    /// Create, edit and organize events.
+    ManageEvents,
+}
+
+impl TokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::ManageCars => "manage_cars",
+            TokenScope::ManageEvents => "manage_events",
+        }
+    }
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "read" => Some(TokenScope::Read),
+            "manage_cars" => Some(TokenScope::ManageCars),
+            "manage_events" => Some(TokenScope::ManageEvents),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenData {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    /// Defaults to 90 days, and can be at most a year.
+    pub expires_in_days: Option<i64>,
+}
+
+impl TokenData {
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errs = Vec::new();
+        if self.name.trim().is_empty() {
+            errs.push("Token name must not be empty.".to_string());
+        }
+        if self.scopes.is_empty() {
+            errs.push("Token must have at least one scope.".to_string());
+        }
+        if let Some(days) = self.expires_in_days {
+            if !(1..=365).contains(&days) {
+                errs.push("Tokens must expire within 1 to 365 days.".to_string());
+            }
+        }
+        if errs.is_empty() {
+            Ok(())
+        } else {
+            Err(errs)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+/// Returned once when a token is created. The secret can't be looked up again.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewApiToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub info: ApiToken,
+}
+
+impl ApiToken {
+    #[instrument(name = "token::insert_new", skip_all)]
+    pub async fn insert_new<'c, C>(
+        user_id: &str,
+        token_hash: &str,
+        data: &TokenData,
+        expires_at: DateTime<Utc>,
+        conn: C,
+    ) -> Result<Self>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("token::insert_new");
+        let scopes: Vec<String> = data
+            .scopes
+            .iter()
+            .map(|scope| scope.as_str().to_string())
+            .collect();
+        query_as!(
+            ApiToken,
+            r#"
+            INSERT INTO api_token (user_id, name, token_hash, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, scopes, created_at, expires_at, last_used
+            "#,
+            user_id,
+            data.name.trim(),
+            token_hash,
+            &scopes,
+            expires_at
+        )
+        .fetch_one(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to create token: {}", err))
+    }
+    #[instrument(name = "token::select_all", skip_all)]
+    pub async fn select_all<'c, C>(user_id: &str, conn: C) -> Result<Vec<Self>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("token::select_all");
+        query_as!(
+            ApiToken,
+            r#"
+            SELECT id, name, scopes, created_at, expires_at, last_used
+            FROM api_token WHERE user_id = $1 ORDER BY created_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get tokens: {}", err))
+    }
+    #[instrument(name = "token::delete", skip_all)]
+    pub async fn delete<'c, C>(id: i32, user_id: &str, conn: C) -> Result<Option<i32>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("token::delete");
+        query!(
+            r#"DELETE FROM api_token WHERE id = $1 AND user_id = $2 RETURNING id"#,
+            id,
+            user_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.id))
+        .map_err(|err| anyhow!("Failed to delete token: {}", err))
+    }
+    /// Find the owner and scopes of an unexpired token, and mark it as used.
+    #[instrument(name = "token::authenticate", skip_all)]
+    pub async fn authenticate<'c, C>(
+        token_hash: &str,
+        conn: C,
+    ) -> Result<Option<(UserData, Vec<TokenScope>)>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("token::authenticate");
+        let record = query!(
+            r#"
+            UPDATE api_token SET last_used = NOW()
+            FROM users
+            WHERE api_token.token_hash = $1 AND api_token.expires_at > NOW()
+            AND users.id = api_token.user_id
+            RETURNING (users.id, users.realm::text, users.name, users.email) AS "user!: UserData",
+            api_token.scopes
+            "#,
+            token_hash
+        )
+        .fetch_optional(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to check token: {}", err))?;
+        Ok(record.map(|record| {
+            let scopes = record
+                .scopes
+                .iter()
+                .filter_map(|scope| TokenScope::parse(scope))
+                .collect();
+            (record.user, scopes)
+        }))
+    }
+}
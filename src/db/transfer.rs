@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, Executor, Postgres};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::db::user::UserData;
+use crate::metrics;
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferData {
+    pub new_driver: String,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CarTransfer {
+    pub car_id: i32,
+    pub new_driver: UserData,
+}
+
+impl CarTransfer {
+    /// Offer the car to another user. Only succeeds if `driver_id` currently drives the car.
+    /// Any previous pending offer for the car is replaced.
+    #[instrument(name = "transfer::insert_new", skip_all)]
+    pub async fn insert_new<'c, C>(
+        event_id: i32,
+        car_id: i32,
+        driver_id: String,
+        new_driver_id: String,
+        conn: C,
+    ) -> Result<Option<i32>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("transfer::insert_new");
+        query!(
+            r#"
+            INSERT INTO car_transfer (car_id, new_driver)
+            SELECT id, $4 FROM car WHERE event_id = $1 AND id = $2 AND driver = $3
+            ON CONFLICT (car_id) DO UPDATE SET new_driver = EXCLUDED.new_driver
+            RETURNING car_id
+            "#,
+            event_id,
+            car_id,
+            driver_id,
+            new_driver_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.car_id))
+        .map_err(|err| anyhow!("Failed to create transfer: {}", err))
+    }
+    #[instrument(name = "transfer::select_one", skip_all)]
+    pub async fn select_one<'c, C>(event_id: i32, car_id: i32, conn: C) -> Result<Option<Self>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("transfer::select_one");
+        query_as!(
+            CarTransfer,
+            r#"
+            SELECT car_transfer.car_id,
+            (users.id, users.realm::text, users.name, users.email) AS "new_driver!: UserData"
+            FROM car_transfer
+            JOIN car ON car_transfer.car_id = car.id
+            JOIN users ON car_transfer.new_driver = users.id
+            WHERE car.event_id = $1 AND car_transfer.car_id = $2
+            "#,
+            event_id,
+            car_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get transfer: {}", err))
+    }
+    /// Claim a pending offer. Returns `None` if the car was not offered to `new_driver_id`.
+    #[instrument(name = "transfer::accept", skip_all)]
+    pub async fn accept<'c, C>(car_id: i32, new_driver_id: &String, conn: C) -> Result<Option<i32>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("transfer::accept");
+        query!(
+            "DELETE FROM car_transfer WHERE car_id = $1 AND new_driver = $2 RETURNING car_id",
+            car_id,
+            new_driver_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.car_id))
+        .map_err(|err| anyhow!("Failed to accept transfer: {}", err))
+    }
+    /// Cancel an offer, either by the current driver or by the user it was offered to.
+    #[instrument(name = "transfer::delete", skip_all)]
+    pub async fn delete<'c, C>(
+        event_id: i32,
+        car_id: i32,
+        user_id: String,
+        conn: C,
+    ) -> Result<Option<i32>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("transfer::delete");
+        query!(
+            r#"
+            DELETE FROM car_transfer USING car
+            WHERE car_transfer.car_id = car.id AND car.event_id = $1 AND car.id = $2
+            AND (car_transfer.new_driver = $3 OR car.driver = $3)
+            RETURNING car_transfer.car_id
+            "#,
+            event_id,
+            car_id,
+            user_id
+        )
+        .fetch_optional(conn)
+        .await
+        .map(|res| res.map(|rec| rec.car_id))
+        .map_err(|err| anyhow!("Failed to delete transfer: {}", err))
+    }
+}
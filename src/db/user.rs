@@ -1,18 +1,19 @@
 use std::collections::HashMap;
 
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{query_as, Executor, Postgres};
+use sqlx::{query, query_as, Executor, PgConnection, Postgres};
+use tracing::instrument;
 use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, sqlx::Type)]
-#[sqlx(type_name = "user_realm", rename_all = "lowercase")]
-pub enum UserRealm {
-    Csh,
-    Google,
-}
+use crate::metrics;
+
+/// The user that past cars and events of deleted or anonymized users are moved to, added by the
+/// `10-deleted-user` migration. It can't log in, and isn't found by searches.
+pub const DELETED_USER_ID: &str = ":deleted";
 
-#[derive(Serialize, Deserialize, sqlx::Type, ToSchema, Clone)]
+#[derive(Serialize, Deserialize, sqlx::Type, ToSchema, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UserData {
     pub id: String,
@@ -21,10 +22,60 @@ pub struct UserData {
     pub email: String,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct LocaleData {
+    /// A language tag such as `en` or `pt-BR`, or null to use the default.
+    pub locale: Option<String>,
+}
+
+impl LocaleData {
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        match &self.locale {
+            Some(locale)
+                if locale.is_empty()
+                    || locale.len() > 35
+                    || !locale
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                Err(vec![
+                    "Locale must be a language tag such as en or pt-BR.".to_string()
+                ])
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An upcoming car or event a deleted account was in, with who to tell about it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AffectedRide {
+    pub event_name: String,
+    pub location: String,
+    /// When the car leaves, or when the event starts.
+    pub departure_time: DateTime<Utc>,
+    /// The driver of a car the user rode in, the riders of a car they drove, or who asked for a
+    /// ride to an event they created.
+    pub recipients: Vec<String>,
+}
+
+/// What deleting an account changed.
+pub struct DeletedAccount {
+    pub name: String,
+    /// Upcoming cars the user rode in. The cars are kept.
+    pub left: Vec<AffectedRide>,
+    /// Upcoming cars with riders that the user drove. The cars are deleted.
+    pub cancelled: Vec<AffectedRide>,
+    /// Upcoming events the user created that nobody else drives to, with ride requests. The
+    /// events are deleted.
+    pub events: Vec<AffectedRide>,
+}
+
 impl UserData {
+    #[instrument(name = "user::insert_new", skip_all)]
     pub async fn insert_new<'c, C>(
         id: String,
-        realm: UserRealm,
+        realm: String,
         name: String,
         email: String,
         conn: C,
@@ -32,6 +83,7 @@ impl UserData {
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("user::insert_new");
         query_as!(
             UserData,
             r#"INSERT INTO users (id, realm, name, email)
@@ -39,25 +91,29 @@ impl UserData {
             ON CONFLICT (id) DO UPDATE SET realm = EXCLUDED.realm, name = EXCLUDED.name, email = EXCLUDED.email
             RETURNING id AS "id!", realm::text AS "realm!", name AS "name!", email AS "email!";"#,
             id,
-            realm as _,
+            realm,
             name,
             email
         )
         .fetch_one(conn)
         .await.map_err(|err| anyhow!("Failed to insert/update user: {}", err))
     }
+    #[instrument(name = "user::select_search", skip_all)]
     pub async fn select_search<'c, C>(query: String, conn: C) -> Result<Vec<Self>>
     where
         C: Executor<'c, Database = Postgres>,
     {
-        query_as!(UserData, r#"SELECT id AS "id!", realm::text AS "realm!", name AS "name!", email AS "email!" FROM users WHERE LOWER(name) LIKE $1 OR LOWER(email) LIKE $1;"#, query.to_lowercase())
+        let _timer = metrics::time_query("user::select_search");
+        query_as!(UserData, r#"SELECT id AS "id!", realm::text AS "realm!", name AS "name!", email AS "email!" FROM users WHERE (LOWER(name) LIKE $1 OR LOWER(email) LIKE $1) AND id <> $2;"#, query.to_lowercase(), DELETED_USER_ID)
         .fetch_all(conn)
         .await.map_err(|err| anyhow!("Failed to get users: {}", err))
     }
+    #[instrument(name = "user::select_map", skip_all)]
     pub async fn select_map<'c, C>(ids: Vec<String>, conn: C) -> Result<HashMap<String, Self>>
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("user::select_map");
         let data = query_as!(
             UserData,
             r#"
@@ -73,10 +129,47 @@ impl UserData {
             data.iter().map(|user| (user.id.clone(), user.clone())),
         ))
     }
+    /// Look up users by email address or CSH username, keyed by the lowercased identifier.
+    /// An identifier may match several users if the same email is used in both realms.
+    #[instrument(name = "user::select_by_identifiers", skip_all)]
+    pub async fn select_by_identifiers<'c, C>(
+        identifiers: Vec<String>,
+        conn: C,
+    ) -> Result<HashMap<String, Vec<Self>>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("user::select_by_identifiers");
+        let records = query!(
+            r#"
+            SELECT LOWER(ident) AS "identifier!",
+            users.id AS "id!", users.realm::text AS "realm!", users.name AS "name!", users.email AS "email!"
+            FROM UNNEST($1::VARCHAR[]) AS ident
+            JOIN users ON LOWER(users.email) = LOWER(ident)
+            OR (users.realm = 'csh' AND LOWER(users.email) = LOWER(ident) || '@csh.rit.edu')
+            "#,
+            &identifiers
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get users: {}", err))?;
+        let mut out: HashMap<String, Vec<Self>> = HashMap::new();
+        for record in records {
+            out.entry(record.identifier).or_default().push(UserData {
+                id: record.id,
+                realm: record.realm,
+                name: record.name,
+                email: record.email,
+            });
+        }
+        Ok(out)
+    }
+    #[instrument(name = "user::select_one", skip_all)]
     pub async fn select_one<'c, C>(id: String, conn: C) -> Result<Option<Self>>
     where
         C: Executor<'c, Database = Postgres>,
     {
+        let _timer = metrics::time_query("user::select_one");
         query_as!(
             UserData,
             r#"
@@ -86,4 +179,172 @@ impl UserData {
         ).fetch_optional(conn).await
         .map_err(|err| anyhow!("Failed to Get Events: {}", err))
     }
+    /// The locale the user picked for their notifications, if any.
+    #[instrument(name = "user::select_locale", skip_all)]
+    pub async fn select_locale<'c, C>(id: &str, conn: C) -> Result<Option<String>>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("user::select_locale");
+        query!(r#"SELECT locale FROM users WHERE id = $1"#, id)
+            .fetch_optional(conn)
+            .await
+            .map(|res| res.and_then(|rec| rec.locale))
+            .map_err(|err| anyhow!("Failed to get locale: {}", err))
+    }
+    #[instrument(name = "user::update_locale", skip_all)]
+    pub async fn update_locale<'c, C>(id: &str, locale: Option<&str>, conn: C) -> Result<()>
+    where
+        C: Executor<'c, Database = Postgres>,
+    {
+        let _timer = metrics::time_query("user::update_locale");
+        query!(r#"UPDATE users SET locale = $2 WHERE id = $1"#, id, locale)
+            .execute(conn)
+            .await
+            .map(|_| ())
+            .map_err(|err| anyhow!("Failed to update locale: {}", err))
+    }
+    /// Delete a user and everything that is theirs. Events that already ended and cars that
+    /// already left are kept, and moved to `DELETED_USER_ID`. An upcoming event they created is
+    /// handed to the driver of the first other car in it, or deleted if nobody else drives to it.
+    /// Their upcoming cars, rides, ride requests and transfer offers are deleted, and their
+    /// identities, sessions and tokens go with the user. Returns `None` if there is no such user.
+    #[instrument(name = "user::delete_account", skip_all)]
+    pub async fn delete_account(
+        id: &str,
+        conn: &mut PgConnection,
+    ) -> Result<Option<DeletedAccount>> {
+        let _timer = metrics::time_query("user::delete_account");
+        let Some(user) = UserData::select_one(id.to_string(), &mut *conn).await? else {
+            return Ok(None);
+        };
+        let left = query_as!(
+            AffectedRide,
+            r#"
+            SELECT event.name AS event_name, event.location, car.departure_time,
+            ARRAY[car.driver] AS "recipients!"
+            FROM rider
+            JOIN car ON car.id = rider.car_id
+            JOIN event ON event.id = car.event_id
+            WHERE rider.rider = $1 AND car.departure_time > NOW()
+            "#,
+            id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get rides: {}", err))?;
+        let cancelled = query_as!(
+            AffectedRide,
+            r#"
+            SELECT event.name AS event_name, event.location, car.departure_time,
+            ARRAY_AGG(rider.rider) AS "recipients!"
+            FROM car
+            JOIN event ON event.id = car.event_id
+            JOIN rider ON rider.car_id = car.id
+            WHERE car.driver = $1 AND car.departure_time > NOW()
+            GROUP BY car.id, event.id
+            "#,
+            id
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get cars: {}", err))?;
+        let events = query_as!(
+            AffectedRide,
+            r#"
+            SELECT event.name AS event_name, event.location, event.start_time AS departure_time,
+            ARRAY_AGG(ride_request.rider) AS "recipients!"
+            FROM event
+            JOIN ride_request ON ride_request.event_id = event.id
+            WHERE event.creator = $1 AND event.end_time >= NOW() AND ride_request.rider <> $1
+            AND NOT EXISTS (
+                SELECT 1 FROM car WHERE car.event_id = event.id AND car.driver NOT IN ($1, $2)
+            )
+            GROUP BY event.id
+            "#,
+            id,
+            DELETED_USER_ID
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to get events: {}", err))?;
+
+        query!(
+            r#"UPDATE event SET creator = $2 WHERE creator = $1 AND end_time < NOW()"#,
+            id,
+            DELETED_USER_ID
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to keep past events: {}", err))?;
+        query!(
+            r#"
+            UPDATE event SET creator = (
+                SELECT car.driver FROM car
+                WHERE car.event_id = event.id AND car.driver NOT IN ($1, $2)
+                ORDER BY car.id LIMIT 1
+            )
+            WHERE creator = $1
+            AND EXISTS (
+                SELECT 1 FROM car WHERE car.event_id = event.id AND car.driver NOT IN ($1, $2)
+            )
+            "#,
+            id,
+            DELETED_USER_ID
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to hand over events: {}", err))?;
+        query!(r#"DELETE FROM event WHERE creator = $1"#, id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| anyhow!("Failed to delete events: {}", err))?;
+        query!(
+            r#"DELETE FROM car WHERE driver = $1 AND departure_time > NOW()"#,
+            id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to delete cars: {}", err))?;
+        query!(
+            r#"
+            DELETE FROM car_transfer USING car
+            WHERE car_transfer.car_id = car.id AND car.driver = $1
+            "#,
+            id
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to delete transfers of past cars: {}", err))?;
+        query!(
+            r#"UPDATE car SET driver = $2 WHERE driver = $1"#,
+            id,
+            DELETED_USER_ID
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|err| anyhow!("Failed to keep past cars: {}", err))?;
+        query!(r#"DELETE FROM rider WHERE rider = $1"#, id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| anyhow!("Failed to delete rides: {}", err))?;
+        query!(r#"DELETE FROM ride_request WHERE rider = $1"#, id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| anyhow!("Failed to delete ride requests: {}", err))?;
+        query!(r#"DELETE FROM car_transfer WHERE new_driver = $1"#, id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| anyhow!("Failed to delete transfer offers: {}", err))?;
+        query!(r#"DELETE FROM users WHERE id = $1"#, id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|err| anyhow!("Failed to delete user: {}", err))?;
+        Ok(Some(DeletedAccount {
+            name: user.name,
+            left,
+            cancelled,
+            events,
+        }))
+    }
 }
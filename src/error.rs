@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use log::error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// Stable, machine readable error codes. Clients should match on these rather than on the
+/// message, which is meant for people and may change.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    ValidationFailed,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    InternalError,
+}
+
+/// A problem with one part of a request body.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct FieldError {
+    /// Path to the field the problem is with, e.g. `name` or `riders[1]`, if it's about a
+    /// single field.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    /// Machine readable reason, e.g. `required`, `too_long` or `in_past`.
+    pub code: String,
+    pub message: String,
+    /// Values the check was made against, such as the `max` length.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, Value>,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &str, message: impl Into<String>) -> Self {
+        FieldError {
+            field: Some(field.to_string()),
+            code: code.to_string(),
+            message: message.into(),
+            params: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.params.insert(name.to_string(), value.into());
+        self
+    }
+}
+
+/// The body of every error response.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub error: String,
+    /// Everything that was wrong with the request, for `validation_failed` errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
+}
+
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Validation(Vec<FieldError>),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    /// Something went wrong on our end. The source is logged, and only the message is shown.
+    Internal {
+        message: String,
+        source: anyhow::Error,
+    },
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn internal(message: &str, source: impl Into<anyhow::Error>) -> Self {
+        AppError::Internal {
+            message: message.to_string(),
+            source: source.into(),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::BadRequest(_) => ErrorCode::BadRequest,
+            AppError::Validation(_) => ErrorCode::ValidationFailed,
+            AppError::Unauthorized(_) => ErrorCode::Unauthorized,
+            AppError::Forbidden(_) => ErrorCode::Forbidden,
+            AppError::NotFound(_) => ErrorCode::NotFound,
+            AppError::Conflict(_) => ErrorCode::Conflict,
+            AppError::Internal { .. } => ErrorCode::InternalError,
+        }
+    }
+
+    pub fn body(&self) -> ApiError {
+        ApiError {
+            code: self.code(),
+            error: self.to_string(),
+            errors: match self {
+                AppError::Validation(errs) => Some(errs.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Validation(_) => write!(f, "Invalid request"),
+            AppError::BadRequest(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::Internal { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) | AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let AppError::Internal { message, source } = self {
+            error!("{}: {:#}", message, source);
+        }
+        HttpResponse::build(self.status_code()).json(self.body())
+    }
+}
+
+/// Errors from `db::*` and friends are already described by their own message, so callers only
+/// need to say what they were doing.
+impl From<anyhow::Error> for AppError {
+    fn from(source: anyhow::Error) -> Self {
+        AppError::internal("Internal server error", source)
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(source: sqlx::Error) -> Self {
+        AppError::internal("Database error", source)
+    }
+}
+
+impl From<Vec<String>> for AppError {
+    fn from(errs: Vec<String>) -> Self {
+        AppError::Validation(
+            errs.into_iter()
+                .map(|message| FieldError {
+                    field: None,
+                    code: "invalid".to_string(),
+                    message,
+                    params: BTreeMap::new(),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl From<Vec<FieldError>> for AppError {
+    fn from(errs: Vec<FieldError>) -> Self {
+        AppError::Validation(errs)
+    }
+}
+
+/// Attach a user facing message to an internal error, e.g.
+/// `Car::select_one(..).await.or_internal("Failed to get Car")?`.
+pub trait OrInternal<T> {
+    fn or_internal(self, message: &str) -> AppResult<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> OrInternal<T> for Result<T, E> {
+    fn or_internal(self, message: &str) -> AppResult<T> {
+        self.map_err(|err| AppError::internal(message, err))
+    }
+}
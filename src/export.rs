@@ -0,0 +1,112 @@
+use std::borrow::Cow;
+
+use actix_web::web::Bytes;
+use csv::{Terminator, WriterBuilder};
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::Deserialize;
+
+use crate::db::car::Car;
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// Spreadsheets run cells starting with these as formulas, so they're escaped with a quote.
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+fn neutralize(field: &str) -> Cow<'_, str> {
+    if field.starts_with(FORMULA_PREFIXES) {
+        Cow::Owned(format!("'{}", field))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+fn csv_rows(rows: &[Vec<&str>]) -> Result<Bytes, actix_web::Error> {
+    let mut writer = WriterBuilder::new()
+        .terminator(Terminator::CRLF)
+        .from_writer(Vec::new());
+    for row in rows {
+        writer
+            .write_record(row.iter().map(|field| neutralize(field).into_owned()))
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+    writer
+        .into_inner()
+        .map(Bytes::from)
+        .map_err(actix_web::error::ErrorInternalServerError)
+}
+
+/// One row per seat so the roster can be checked off person by person. Cars without riders
+/// still get a row for their driver.
+fn car_to_csv(car: &Car) -> Result<Bytes, actix_web::Error> {
+    let departure = car.departure_time.to_rfc3339();
+    let return_time = car.return_time.to_rfc3339();
+    let car_id = car.id.to_string();
+    let car_fields: [&str; 6] = [
+        &car_id,
+        &car.driver.name,
+        &car.driver.email,
+        &departure,
+        &return_time,
+        &car.comment,
+    ];
+    let riders = car.riders.as_deref().unwrap_or_default();
+    if riders.is_empty() {
+        return csv_rows(&[[&car_fields[..], &["", ""][..]].concat()]);
+    }
+    let rows: Vec<Vec<&str>> = riders
+        .iter()
+        .map(|rider| {
+            let rider_fields: [&str; 2] = [&rider.name, &rider.email];
+            [&car_fields[..], &rider_fields[..]].concat()
+        })
+        .collect();
+    csv_rows(&rows)
+}
+
+pub fn csv_stream(cars: Vec<Car>) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let header = csv_rows(&[vec![
+        "car_id",
+        "driver_name",
+        "driver_email",
+        "departure_time",
+        "return_time",
+        "comment",
+        "rider_name",
+        "rider_email",
+    ]]);
+    stream::once(async move { header }).chain(stream::iter(cars).map(|car| car_to_csv(&car)))
+}
+
+pub fn json_stream(cars: Vec<Car>) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    stream::once(async { Ok(Bytes::from_static(b"[")) })
+        .chain(
+            stream::iter(cars.into_iter().enumerate()).map(|(index, car)| {
+                let mut chunk = if index == 0 { Vec::new() } else { vec![b','] };
+                serde_json::to_writer(&mut chunk, &car)
+                    .map_err(actix_web::error::ErrorInternalServerError)?;
+                Ok(Bytes::from(chunk))
+            }),
+        )
+        .chain(stream::once(async { Ok(Bytes::from_static(b"]")) }))
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use actix_web::{web, HttpResponse, Responder};
+use log::error;
+use redis::aio::MultiplexedConnection;
+use redis_work_queue::WorkQueue;
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::metrics;
+
+/// What `/readyz` checks and `/metrics` reads the queue depth from. The server and the worker
+/// each serve these routes.
+#[derive(Clone)]
+pub struct Probes {
+    db: PgPool,
+    redis: Option<(MultiplexedConnection, Arc<WorkQueue>)>,
+}
+
+impl Probes {
+    /// `redis` should be left out when the queue isn't in Redis. It shouldn't be a connection
+    /// that blocks waiting for jobs, or the checks would wait behind it.
+    pub fn new(db: PgPool, redis: Option<(MultiplexedConnection, WorkQueue)>) -> Self {
+        Probes {
+            db,
+            redis: redis.map(|(redis, work_queue)| (redis, Arc::new(work_queue))),
+        }
+    }
+
+    async fn check_postgres(&self) -> Result<(), String> {
+        sqlx::query("SELECT 1")
+            .execute(&self.db)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    async fn check_redis(&self) -> Option<Result<(), String>> {
+        let (mut redis, _) = self.redis.clone()?;
+        Some(
+            redis::cmd("PING")
+                .query_async::<String>(&mut redis)
+                .await
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+        )
+    }
+
+    async fn update_queue_depth(&self) {
+        let Some((mut redis, work_queue)) = self.redis.clone() else {
+            return;
+        };
+        let queued = work_queue.queue_len(&mut redis).await;
+        let processing = work_queue.processing(&mut redis).await;
+        match (queued, processing) {
+            (Ok(queued), Ok(processing)) => metrics::set_queue_depth(queued, processing),
+            (Err(err), _) | (_, Err(err)) => error!("Failed to read queue depth: {}", err),
+        }
+    }
+}
+
+/// The process is up. This doesn't check Postgres or Redis, so an outage there doesn't get the
+/// process restarted.
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Postgres, and Redis when it's used, can be reached.
+async fn readyz(probes: web::Data<Probes>) -> impl Responder {
+    let mut ready = true;
+    let mut status = |result: Option<Result<(), String>>| match result {
+        Some(Ok(())) => "ok".to_string(),
+        Some(Err(err)) => {
+            ready = false;
+            err
+        }
+        None => "disabled".to_string(),
+    };
+    let postgres = status(Some(probes.check_postgres().await));
+    let redis = status(probes.check_redis().await);
+    let body = json!({
+        "status": if ready { "ok" } else { "unavailable" },
+        "checks": { "postgres": postgres, "redis": redis },
+    });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+async fn metrics_text(probes: web::Data<Probes>) -> impl Responder {
+    probes.update_queue_depth().await;
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render())
+}
+
+/// Add `/healthz`, `/readyz` and `/metrics`. Expects `Probes` in the app data.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(readyz))
+        .route("/metrics", web::get().to(metrics_text));
+}
@@ -0,0 +1,650 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgPoolOptions, query, PgPool};
+use utoipa::ToSchema;
+
+use crate::{
+    app::{MultipleRiderChange, RedisJob},
+    config::Config,
+    db::{
+        car::{Car, CarData},
+        event::{Event, EventData},
+        ride_request::RideRequest,
+        user::UserData,
+    },
+    error::{AppError, FieldError},
+    redis::RedisQueue,
+    validation::ValidationRules,
+};
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl ImportFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => Ok(ImportFormat::Csv),
+            Some("json") => Ok(ImportFormat::Json),
+            _ => Err(anyhow!("Import file must end in .csv or .json")),
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportCar {
+    /// Email address or CSH username of the driver.
+    pub driver: String,
+    pub max_capacity: i32,
+    pub departure_time: DateTime<Utc>,
+    pub return_time: DateTime<Utc>,
+    #[serde(default)]
+    pub comment: String,
+    /// Email addresses or CSH usernames of the riders.
+    #[serde(default)]
+    pub riders: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportEvent {
+    pub name: String,
+    pub location: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    #[serde(default)]
+    pub cars: Vec<ImportCar>,
+}
+
+/// A spreadsheet row. Event columns are ignored when importing into an existing event, and a
+/// row without a driver only creates its event.
+#[derive(Deserialize)]
+struct CsvRow {
+    #[serde(default)]
+    event_name: String,
+    #[serde(default)]
+    location: String,
+    start_time: Option<DateTime<Utc>>,
+    end_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    driver: String,
+    max_capacity: Option<i32>,
+    departure_time: Option<DateTime<Utc>>,
+    return_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    comment: String,
+    /// Riders separated by semicolons.
+    #[serde(default)]
+    riders: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResult {
+    pub events: Vec<i32>,
+    pub cars: Vec<i32>,
+}
+
+pub enum ImportError {
+    /// Rows that failed validation, nothing was written.
+    Invalid(Vec<FieldError>),
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ImportError {
+    fn from(value: anyhow::Error) -> Self {
+        ImportError::Failed(value)
+    }
+}
+
+impl From<ImportError> for AppError {
+    fn from(value: ImportError) -> Self {
+        match value {
+            ImportError::Invalid(errs) => errs.into(),
+            ImportError::Failed(err) => AppError::internal("Failed to import", err),
+        }
+    }
+}
+
+/// Where an event or car is in the import, so its errors can point at it.
+#[derive(Clone)]
+struct Source {
+    /// For people, e.g. `Row 4` or `Event 1, car 2`.
+    label: String,
+    /// For clients, e.g. `rows[2]` or `[0].cars[1]`. CSV rows are counted from 0, without the
+    /// header.
+    path: String,
+    /// CSV columns are snake_case, and an event's name is its `event_name`.
+    csv: bool,
+}
+
+impl Source {
+    fn field(&self, field: &str) -> String {
+        let field = if !self.csv {
+            field.to_string()
+        } else if field == "name" {
+            "event_name".to_string()
+        } else {
+            field.chars().fold(String::new(), |mut column, c| {
+                if c.is_ascii_uppercase() {
+                    column.push('_');
+                }
+                column.push(c.to_ascii_lowercase());
+                column
+            })
+        };
+        match (self.path.is_empty(), field.is_empty()) {
+            (true, _) => field,
+            (false, true) => self.path.clone(),
+            (false, false) => format!("{}.{}", self.path, field),
+        }
+    }
+
+    fn error(&self, field: &str, code: &str, message: impl fmt::Display) -> FieldError {
+        FieldError::new(
+            &self.field(field),
+            code,
+            format!("{}: {}", self.label, message),
+        )
+    }
+
+    /// Point an error from validating the event or car at where it came from.
+    fn locate(&self, err: FieldError) -> FieldError {
+        FieldError {
+            field: Some(self.field(err.field.as_deref().unwrap_or_default())),
+            message: format!("{}: {}", self.label, err.message),
+            ..err
+        }
+    }
+}
+
+/// An error with the body as a whole, e.g. JSON that doesn't parse.
+fn body_error(message: impl fmt::Display) -> FieldError {
+    FieldError {
+        field: None,
+        code: "invalid".to_string(),
+        message: message.to_string(),
+        params: BTreeMap::new(),
+    }
+}
+
+struct PendingCar {
+    source: Source,
+    car: ImportCar,
+}
+
+enum EventTarget {
+    New(EventData),
+    Existing(i32),
+}
+
+pub struct PendingEvent {
+    source: Source,
+    target: EventTarget,
+    cars: Vec<PendingCar>,
+}
+
+impl CsvRow {
+    fn into_car(self, source: &Source) -> Result<Option<ImportCar>, FieldError> {
+        if self.driver.is_empty() {
+            return Ok(None);
+        }
+        match (self.max_capacity, self.departure_time, self.return_time) {
+            (Some(max_capacity), Some(departure_time), Some(return_time)) => Ok(Some(ImportCar {
+                driver: self.driver,
+                max_capacity,
+                departure_time,
+                return_time,
+                comment: self.comment,
+                riders: self
+                    .riders
+                    .split(';')
+                    .map(|rider| rider.trim().to_string())
+                    .filter(|rider| !rider.is_empty())
+                    .collect(),
+            })),
+            (max_capacity, departure_time, _) => {
+                let missing = if max_capacity.is_none() {
+                    "maxCapacity"
+                } else if departure_time.is_none() {
+                    "departureTime"
+                } else {
+                    "returnTime"
+                };
+                Err(source.error(
+                    missing,
+                    "required",
+                    "max_capacity, departure_time and return_time are required for a car.",
+                ))
+            }
+        }
+    }
+}
+
+fn read_csv(body: &[u8]) -> Result<Vec<(Source, CsvRow)>, Vec<FieldError>> {
+    let mut reader = csv::Reader::from_reader(body);
+    let mut rows = Vec::new();
+    let mut errs = Vec::new();
+    for (index, row) in reader.deserialize::<CsvRow>().enumerate() {
+        let source = Source {
+            // Row 1 is the header.
+            label: format!("Row {}", index + 2),
+            path: format!("rows[{}]", index),
+            csv: true,
+        };
+        match row {
+            Ok(row) => rows.push((source, row)),
+            Err(err) => errs.push(source.error("", "invalid", err)),
+        }
+    }
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+    Ok(rows)
+}
+
+/// Parse a batch of new events with their cars.
+pub fn parse_events(
+    format: ImportFormat,
+    body: &[u8],
+) -> Result<Vec<PendingEvent>, Vec<FieldError>> {
+    match format {
+        ImportFormat::Json => {
+            let events: Vec<ImportEvent> =
+                serde_json::from_slice(body).map_err(|err| vec![body_error(err)])?;
+            Ok(events
+                .into_iter()
+                .enumerate()
+                .map(|(index, event)| PendingEvent {
+                    source: Source {
+                        label: format!("Event {}", index + 1),
+                        path: format!("[{}]", index),
+                        csv: false,
+                    },
+                    target: EventTarget::New(EventData {
+                        name: event.name,
+                        location: event.location,
+                        start_time: event.start_time,
+                        end_time: event.end_time,
+                    }),
+                    cars: event
+                        .cars
+                        .into_iter()
+                        .enumerate()
+                        .map(|(car_index, car)| PendingCar {
+                            source: Source {
+                                label: format!("Event {}, car {}", index + 1, car_index + 1),
+                                path: format!("[{}].cars[{}]", index, car_index),
+                                csv: false,
+                            },
+                            car,
+                        })
+                        .collect(),
+                })
+                .collect())
+        }
+        ImportFormat::Csv => {
+            let mut events: Vec<PendingEvent> = Vec::new();
+            let mut errs = Vec::new();
+            for (source, mut row) in read_csv(body)? {
+                let (start_time, end_time) = match (row.start_time, row.end_time) {
+                    (Some(start_time), Some(end_time)) => (start_time, end_time),
+                    (start_time, _) => {
+                        errs.push(source.error(
+                            if start_time.is_none() {
+                                "startTime"
+                            } else {
+                                "endTime"
+                            },
+                            "required",
+                            "start_time and end_time are required.",
+                        ));
+                        continue;
+                    }
+                };
+                let data = EventData {
+                    name: std::mem::take(&mut row.event_name),
+                    location: std::mem::take(&mut row.location),
+                    start_time,
+                    end_time,
+                };
+                let car = match row.into_car(&source) {
+                    Ok(car) => car,
+                    Err(err) => {
+                        errs.push(err);
+                        continue;
+                    }
+                };
+                // Consecutive or not, rows describing the same event share it.
+                let existing = events.iter_mut().find(|pending| match &pending.target {
+                    EventTarget::New(other) => {
+                        other.name == data.name
+                            && other.location == data.location
+                            && other.start_time == data.start_time
+                            && other.end_time == data.end_time
+                    }
+                    EventTarget::Existing(_) => false,
+                });
+                let pending = match existing {
+                    Some(pending) => pending,
+                    None => {
+                        events.push(PendingEvent {
+                            source: source.clone(),
+                            target: EventTarget::New(data),
+                            cars: Vec::new(),
+                        });
+                        events.last_mut().unwrap()
+                    }
+                };
+                if let Some(car) = car {
+                    pending.cars.push(PendingCar { source, car });
+                }
+            }
+            if !errs.is_empty() {
+                return Err(errs);
+            }
+            Ok(events)
+        }
+    }
+}
+
+/// Parse a batch of cars for an event that already exists.
+pub fn parse_cars(
+    format: ImportFormat,
+    event_id: i32,
+    body: &[u8],
+) -> Result<PendingEvent, Vec<FieldError>> {
+    let cars = match format {
+        ImportFormat::Json => {
+            let cars: Vec<ImportCar> =
+                serde_json::from_slice(body).map_err(|err| vec![body_error(err)])?;
+            cars.into_iter()
+                .enumerate()
+                .map(|(index, car)| PendingCar {
+                    source: Source {
+                        label: format!("Car {}", index + 1),
+                        path: format!("[{}]", index),
+                        csv: false,
+                    },
+                    car,
+                })
+                .collect()
+        }
+        ImportFormat::Csv => {
+            let mut cars = Vec::new();
+            let mut errs = Vec::new();
+            for (source, row) in read_csv(body)? {
+                match row.into_car(&source) {
+                    Ok(Some(car)) => cars.push(PendingCar { source, car }),
+                    Ok(None) => {
+                        errs.push(source.error("driver", "required", "driver is required."))
+                    }
+                    Err(err) => errs.push(err),
+                }
+            }
+            if !errs.is_empty() {
+                return Err(errs);
+            }
+            cars
+        }
+    };
+    Ok(PendingEvent {
+        source: Source {
+            label: format!("Event {}", event_id),
+            path: String::new(),
+            csv: matches!(format, ImportFormat::Csv),
+        },
+        target: EventTarget::Existing(event_id),
+        cars,
+    })
+}
+
+struct ResolvedCar {
+    driver: UserData,
+    data: CarData,
+}
+
+struct ResolvedEvent {
+    target: EventTarget,
+    cars: Vec<ResolvedCar>,
+}
+
+/// Validate the whole batch and write it in a single transaction. Returns the rider changes
+/// so the caller can queue notifications.
+pub async fn import(
+    events: Vec<PendingEvent>,
+    creator_id: String,
+    rules: &ValidationRules,
+    db: &PgPool,
+) -> Result<(ImportResult, Vec<MultipleRiderChange>), ImportError> {
+    // Users and cars are read in the same transaction the batch is written in.
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|err| anyhow!("Failed to make SQL Transaction: {}", err))?;
+    let identifiers: Vec<String> = events
+        .iter()
+        .flat_map(|event| event.cars.iter())
+        .flat_map(|pending| {
+            std::iter::once(pending.car.driver.clone()).chain(pending.car.riders.iter().cloned())
+        })
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect();
+    let users = UserData::select_by_identifiers(identifiers, &mut *tx).await?;
+    let resolve = |source: &Source, field: &str, identifier: &String| match users
+        .get(&identifier.to_lowercase())
+        .map(|found| found.as_slice())
+    {
+        Some([user]) => Ok(user.clone()),
+        Some([]) | None => Err(source.error(
+            field,
+            "not_found",
+            format!("No user found for {}.", identifier),
+        )),
+        Some(_) => Err(source.error(
+            field,
+            "ambiguous",
+            format!("{} matches more than one user.", identifier),
+        )),
+    };
+
+    let mut errs = Vec::new();
+    let mut resolved = Vec::new();
+    for event in events {
+        let mut other_cars = match &event.target {
+            EventTarget::New(data) => {
+                if let Err(event_errs) = data.validate(rules) {
+                    errs.extend(event_errs.into_iter().map(|err| event.source.locate(err)));
+                }
+                Vec::new()
+            }
+            EventTarget::Existing(event_id) => Car::select_all(*event_id, &mut *tx).await?,
+        };
+
+        let mut cars = Vec::new();
+        for pending in event.cars {
+            let source = &pending.source;
+            let driver = match resolve(source, "driver", &pending.car.driver) {
+                Ok(driver) => driver,
+                Err(err) => {
+                    errs.push(err);
+                    continue;
+                }
+            };
+            let mut riders = Vec::new();
+            for (index, rider) in pending.car.riders.iter().enumerate() {
+                match resolve(source, &format!("riders[{}]", index), rider) {
+                    Ok(rider) => riders.push(rider),
+                    Err(err) => errs.push(err),
+                }
+            }
+            let data = CarData {
+                max_capacity: pending.car.max_capacity,
+                departure_time: pending.car.departure_time,
+                return_time: pending.car.return_time,
+                comment: pending.car.comment,
+                riders: riders.iter().map(|rider| rider.id.clone()).collect(),
+            };
+            let driver_taken = other_cars.iter().any(|car| {
+                car.driver.id == driver.id
+                    || car
+                        .riders
+                        .iter()
+                        .flatten()
+                        .any(|rider| rider.id == driver.id)
+            });
+            if driver_taken {
+                errs.push(source.error(
+                    "driver",
+                    "already_in_car",
+                    format!(
+                        "{} is already in another car or is a driver.",
+                        pending.car.driver
+                    ),
+                ));
+            }
+            if let Err(car_errs) = data.validate(&driver.id, other_cars.clone(), rules) {
+                errs.extend(car_errs.into_iter().map(|err| source.locate(err)));
+            }
+            other_cars.push(Car {
+                id: 0,
+                event_id: None,
+                driver: driver.clone(),
+                riders: Some(riders),
+                max_capacity: data.max_capacity,
+                departure_time: data.departure_time,
+                return_time: data.return_time,
+                comment: data.comment.clone(),
+            });
+            cars.push(ResolvedCar { driver, data });
+        }
+        resolved.push(ResolvedEvent {
+            target: event.target,
+            cars,
+        });
+    }
+    if !errs.is_empty() {
+        return Err(ImportError::Invalid(errs));
+    }
+
+    let mut result = ImportResult {
+        events: Vec::new(),
+        cars: Vec::new(),
+    };
+    let mut changes = Vec::new();
+    for event in resolved {
+        let event_id = match &event.target {
+            EventTarget::New(data) => {
+                let record = Event::insert_new(data, creator_id.clone(), &mut *tx).await?;
+                result.events.push(record.id);
+                record.id
+            }
+            EventTarget::Existing(event_id) => *event_id,
+        };
+        for car in event.cars {
+            let record =
+                Car::insert_new(event_id, car.driver.id.clone(), &car.data, &mut *tx).await?;
+            query!(
+                r#"
+                INSERT INTO rider (car_id, rider) SELECT $1, * FROM UNNEST($2::VARCHAR[])
+                "#,
+                record.id,
+                &car.data.riders
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|err| anyhow!("Failed to add riders to car: {}", err))?;
+            let mut placed = car.data.riders.clone();
+            placed.push(car.driver.id);
+            RideRequest::delete_many(event_id, &placed, &mut *tx).await?;
+            result.cars.push(record.id);
+            changes.push(MultipleRiderChange {
+                event_id,
+                car_id: record.id,
+                old_riders: Vec::new(),
+                new_riders: car.data.riders,
+            });
+        }
+    }
+    tx.commit()
+        .await
+        .map_err(|err| anyhow!("Failed to commit transaction: {}", err))?;
+    Ok((result, changes))
+}
+
+fn messages(errs: Vec<FieldError>) -> String {
+    errs.into_iter()
+        .map(|err| err.message)
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Import events and cars from a file on disk, as `creator`, and queue the same rider
+/// notifications as an import over HTTP.
+pub async fn import_file(
+    file: &Path,
+    creator: String,
+    rules: &ValidationRules,
+    db: &PgPool,
+    queue: &mut RedisQueue,
+) -> Result<ImportResult> {
+    if UserData::select_one(creator.clone(), db).await?.is_none() {
+        return Err(anyhow!("Creator {} does not exist", creator));
+    }
+
+    let body = std::fs::read(file)?;
+    let events = match parse_events(ImportFormat::from_path(file)?, &body) {
+        Ok(events) => events,
+        Err(errs) => return Err(anyhow!("Failed to parse import:\n{}", messages(errs))),
+    };
+    let (result, changes) = match import(events, creator, rules, db).await {
+        Ok(imported) => imported,
+        Err(ImportError::Invalid(errs)) => {
+            return Err(anyhow!(
+                "Import is invalid, nothing was written:\n{}",
+                messages(errs)
+            ))
+        }
+        Err(ImportError::Failed(err)) => return Err(err),
+    };
+    for change in changes {
+        if let Err(err) = queue.insert_job(RedisJob::RiderUpdate(change)).await {
+            error!("{}", err);
+        }
+    }
+    Ok(result)
+}
+
+pub async fn main(config: Config, file: &Path, creator: String) -> Result<()> {
+    let db_pool = PgPoolOptions::new()
+        .max_connections(config.database.max_connections)
+        .connect(&config.database.url)
+        .await?;
+    let mut queue = if config.features.notifications {
+        RedisQueue::connect(&config.redis).await?
+    } else {
+        RedisQueue::Disabled
+    };
+
+    let result = import_file(file, creator, &config.validation, &db_pool, &mut queue).await?;
+    println!(
+        "Imported {} events and {} cars.",
+        result.events.len(),
+        result.cars.len()
+    );
+    Ok(())
+}
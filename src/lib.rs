@@ -0,0 +1,22 @@
+pub mod admin;
+pub mod api;
+pub mod app;
+mod auth;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod export;
+pub mod health;
+pub mod import;
+pub mod matching;
+pub mod metrics;
+pub mod notifications;
+pub mod pings;
+pub mod providers;
+pub mod redis;
+pub mod retention;
+pub mod server;
+mod session;
+pub mod telemetry;
+pub mod validation;
+pub mod worker;
@@ -1,19 +1,21 @@
-pub mod api;
-pub mod app;
-mod auth;
-pub mod db;
-pub mod pings;
-pub mod redis;
-mod server;
-mod worker;
-
-use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
+use rideboard_v2::admin::Admin;
+use rideboard_v2::config::{self, Config};
+use rideboard_v2::{import, server, telemetry, worker};
 
 #[derive(Parser)]
 #[command(name = "App")]
 #[command(about = "An application with async server and worker subcommands", long_about = None)]
 struct Cli {
+    /// File to read unset environment variables from [default: .env]
+    #[arg(long, global = true)]
+    env_file: Option<PathBuf>,
+    /// TOML config file [default: $CONFIG_FILE, or rideboard.toml if it exists]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -24,17 +26,269 @@ enum Commands {
     Server,
     /// Start the async worker
     Worker,
+    /// Import events and cars from a CSV or JSON file
+    Import {
+        /// Path to a .csv or .json file
+        file: PathBuf,
+        /// ID of the user who will own the imported events
+        #[arg(long)]
+        creator: String,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Fix data by hand
+    Admin {
+        /// Report what would change, then roll it back
+        #[arg(long, global = true)]
+        dry_run: bool,
+        #[command(subcommand)]
+        command: AdminCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Check that the server and worker could start with the current configuration
+    Check,
+}
+
+#[derive(Subcommand)]
+enum AdminCommand {
+    /// List, show or delete events
+    Event {
+        #[command(subcommand)]
+        command: EventCommand,
+    },
+    /// Remove or move riders
+    Rider {
+        #[command(subcommand)]
+        command: RiderCommand,
+    },
+    /// Merge duplicate users
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Queue notifications to every rider of a car again
+    Notify { event: i32, car: i32 },
+}
+
+#[derive(Subcommand)]
+enum EventCommand {
+    /// List upcoming events
+    List {
+        /// List events that have ended instead
+        #[arg(long)]
+        past: bool,
+    },
+    /// Show an event with its cars and riders
+    Show { id: i32 },
+    /// Delete an event with its cars and ride requests
+    Delete { id: i32 },
+    /// Delete every event that ended more than some days ago
+    Purge {
+        #[arg(long)]
+        older_than_days: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum RiderCommand {
+    /// Take a rider out of a car
+    Remove { event: i32, car: i32, rider: String },
+    /// Move a rider to another car in the same event
+    Move {
+        event: i32,
+        rider: String,
+        #[arg(long)]
+        from: i32,
+        #[arg(long)]
+        to: i32,
+    },
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Move everything a user has to another user, and delete them
+    Merge {
+        user: String,
+        #[arg(long)]
+        into: String,
+    },
+}
+
+/// Run an admin command and print what it did.
+async fn admin(config: &Config, dry_run: bool, command: &AdminCommand) -> Result<()> {
+    let mut admin = Admin::connect(config, dry_run).await?;
+    let verb = |done: &'static str, would: &'static str| if dry_run { would } else { done };
+    match command {
+        AdminCommand::Event {
+            command: EventCommand::List { past },
+        } => {
+            for event in admin.list_events(*past).await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    event.id,
+                    event.start_time.format("%Y-%m-%d %H:%M"),
+                    event.name,
+                    event.location
+                );
+            }
+        }
+        AdminCommand::Event {
+            command: EventCommand::Show { id },
+        } => {
+            let (event, cars) = admin.show_event(*id).await?;
+            println!("{} at {}", event.name, event.location);
+            println!("{} to {}", event.start_time, event.end_time);
+            println!("Created by {} ({})", event.creator.name, event.creator.id);
+            for car in cars {
+                let riders = car.riders.unwrap_or_default();
+                println!(
+                    "Car {}: {} ({}), {}/{} riders",
+                    car.id,
+                    car.driver.name,
+                    car.driver.id,
+                    riders.len(),
+                    car.max_capacity
+                );
+                for rider in riders {
+                    println!("  {} ({})", rider.name, rider.id);
+                }
+            }
+        }
+        AdminCommand::Event {
+            command: EventCommand::Delete { id },
+        } => {
+            let event = admin.delete_event(*id).await?;
+            println!(
+                "{} event {} ({})",
+                verb("Deleted", "Would delete"),
+                event.id,
+                event.name
+            );
+        }
+        AdminCommand::Event {
+            command: EventCommand::Purge { older_than_days },
+        } => {
+            let ids = admin.purge_events(*older_than_days).await?;
+            println!(
+                "{} {} events: {:?}",
+                verb("Deleted", "Would delete"),
+                ids.len(),
+                ids
+            );
+        }
+        AdminCommand::Rider {
+            command: RiderCommand::Remove { event, car, rider },
+        } => {
+            admin.remove_rider(*event, *car, rider).await?;
+            println!(
+                "{} {} from car {}",
+                verb("Removed", "Would remove"),
+                rider,
+                car
+            );
+        }
+        AdminCommand::Rider {
+            command:
+                RiderCommand::Move {
+                    event,
+                    rider,
+                    from,
+                    to,
+                },
+        } => {
+            admin.move_rider(*event, rider, *from, *to).await?;
+            println!(
+                "{} {} from car {} to car {}",
+                verb("Moved", "Would move"),
+                rider,
+                from,
+                to
+            );
+        }
+        AdminCommand::User {
+            command: UserCommand::Merge { user, into },
+        } => {
+            admin.merge_users(user, into).await?;
+            println!("{} {} into {}", verb("Merged", "Would merge"), user, into);
+        }
+        AdminCommand::Notify { event, car } => {
+            let riders = admin.notify_car(*event, *car).await?;
+            println!(
+                "{} notifications for {} riders",
+                verb("Queued", "Would queue"),
+                riders
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Report every problem with the configuration for both the server and the worker.
+fn check_config(config: &Config, path: Option<&Path>) -> Result<()> {
+    match path {
+        Some(path) => println!("Loaded {}", path.display()),
+        None => println!("No config file found, using defaults and the environment"),
+    }
+    let mut valid = true;
+    for (name, result) in [
+        ("server", config.check_server()),
+        ("worker", config.check_worker()),
+    ] {
+        match result {
+            Ok(()) => println!("{}: OK", name),
+            Err(err) => {
+                valid = false;
+                println!("{}: {}", name, err);
+            }
+        }
+    }
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Configuration is invalid"))
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
-    dotenv::dotenv().ok();
-
     let cli = Cli::parse();
+    config::load_env_file(cli.env_file.as_deref())?;
+    let (config, path) = Config::load(cli.config.as_deref())?;
+
+    let component = match &cli.command {
+        Commands::Server => "server",
+        Commands::Worker => "worker",
+        Commands::Import { .. } => "import",
+        Commands::Config { .. } => "config",
+        Commands::Admin { .. } => "admin",
+    };
+    let _telemetry = telemetry::init(&config.telemetry, component)?;
 
     match &cli.command {
-        Commands::Server => server::main().await,
-        Commands::Worker => worker::main().await,
+        Commands::Server => {
+            config.check_server()?;
+            server::main(config).await
+        }
+        Commands::Worker => {
+            config.check_worker()?;
+            worker::main(config).await
+        }
+        Commands::Import { file, creator } => {
+            config.check_import()?;
+            import::main(config, file, creator.clone()).await
+        }
+        Commands::Config {
+            command: ConfigCommand::Check,
+        } => check_config(&config, path.as_deref()),
+        Commands::Admin { dry_run, command } => {
+            config.check_admin()?;
+            admin(&config, *dry_run, command).await
+        }
     }
 }
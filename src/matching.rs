@@ -0,0 +1,117 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::db::{car::Car, ride_request::RideRequest};
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CarAssignment {
+    pub car_id: i32,
+    pub riders: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchProposal {
+    pub assignments: Vec<CarAssignment>,
+    pub unmatched: Vec<String>,
+}
+
+/// Whether a car leaving at `departure` suits a rider's window. Either end may be open.
+pub fn in_window(
+    departure: &DateTime<Utc>,
+    earliest: Option<DateTime<Utc>>,
+    latest: Option<DateTime<Utc>>,
+) -> bool {
+    earliest.iter().all(|earliest| departure >= earliest)
+        && latest.iter().all(|latest| departure <= latest)
+}
+
+fn departs_in_window(car: &Car, request: &RideRequest) -> bool {
+    in_window(
+        &car.departure_time,
+        request.earliest_departure,
+        request.latest_departure,
+    )
+}
+
+/// Greedily place riders who need a ride into cars with open seats.
+///
+/// The most constrained riders (fewest cars leaving in their window) are placed first, each
+/// into the compatible car with the most seats left so riders are spread across drivers.
+pub fn propose(cars: &[Car], requests: &[RideRequest]) -> MatchProposal {
+    let occupied: HashSet<&String> = cars
+        .iter()
+        .flat_map(|car| {
+            car.riders
+                .iter()
+                .flatten()
+                .map(|rider| &rider.id)
+                .chain(std::iter::once(&car.driver.id))
+        })
+        .collect();
+
+    let mut open_seats: HashMap<i32, i32> = cars
+        .iter()
+        .map(|car| {
+            let riders = car.riders.as_ref().map(|riders| riders.len()).unwrap_or(0) as i32;
+            (car.id, car.max_capacity - riders)
+        })
+        .collect();
+
+    let mut pending: Vec<(&RideRequest, Vec<&Car>)> = requests
+        .iter()
+        .filter(|request| !occupied.contains(&request.rider.id))
+        .map(|request| {
+            let compatible = cars
+                .iter()
+                .filter(|car| departs_in_window(car, request))
+                .collect();
+            (request, compatible)
+        })
+        .collect();
+    pending.sort_by(|(a, a_cars), (b, b_cars)| {
+        a_cars
+            .len()
+            .cmp(&b_cars.len())
+            .then_with(|| a.rider.id.cmp(&b.rider.id))
+    });
+
+    let mut assigned: HashMap<i32, Vec<String>> = HashMap::new();
+    let mut unmatched = Vec::new();
+    for (request, compatible) in pending {
+        let best = compatible
+            .iter()
+            .filter(|car| open_seats.get(&car.id).copied().unwrap_or(0) > 0)
+            .max_by(|a, b| {
+                open_seats[&a.id]
+                    .cmp(&open_seats[&b.id])
+                    .then_with(|| b.departure_time.cmp(&a.departure_time))
+                    .then_with(|| b.id.cmp(&a.id))
+            });
+        match best {
+            Some(car) => {
+                *open_seats.get_mut(&car.id).unwrap() -= 1;
+                assigned
+                    .entry(car.id)
+                    .or_default()
+                    .push(request.rider.id.clone());
+            }
+            None => unmatched.push(request.rider.id.clone()),
+        }
+    }
+
+    let mut assignments: Vec<CarAssignment> = assigned
+        .into_iter()
+        .map(|(car_id, riders)| CarAssignment { car_id, riders })
+        .collect();
+    assignments.sort_by_key(|assignment| assignment.car_id);
+
+    MatchProposal {
+        assignments,
+        unmatched,
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::LazyLock;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use prometheus::{
+    histogram_opts, opts, Encoder, HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec,
+    Registry, TextEncoder,
+};
+
+/// Every metric, served in the Prometheus text format at `/metrics`.
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+static REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        histogram_opts!(
+            "rideboard_http_request_duration_seconds",
+            "Time taken to respond to HTTP requests"
+        ),
+        &["method", "route", "status"],
+    ))
+});
+
+static QUERY_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(HistogramVec::new(
+        histogram_opts!(
+            "rideboard_db_query_duration_seconds",
+            "Time taken by database queries",
+            vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+        ),
+        &["query"],
+    ))
+});
+
+static QUEUE_DEPTH: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(IntGaugeVec::new(
+        opts!(
+            "rideboard_queue_depth",
+            "Jobs waiting in the work queue, or leased by a worker"
+        ),
+        &["state"],
+    ))
+});
+
+static JOBS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        opts!("rideboard_jobs_total", "Jobs run by the worker"),
+        &["job", "outcome"],
+    ))
+});
+
+static PINGS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(IntCounterVec::new(
+        opts!("rideboard_pings_total", "Pings sent to users"),
+        &["kind", "outcome"],
+    ))
+});
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("Invalid metric");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric registered twice");
+    metric
+}
+
+/// Start timing a database query. The time is recorded when the timer is dropped.
+pub fn time_query(query: &str) -> HistogramTimer {
+    QUERY_DURATION.with_label_values(&[query]).start_timer()
+}
+
+pub fn set_queue_depth(queued: usize, processing: usize) {
+    QUEUE_DEPTH
+        .with_label_values(&["queued"])
+        .set(queued as i64);
+    QUEUE_DEPTH
+        .with_label_values(&["processing"])
+        .set(processing as i64);
+}
+
+/// Count a job the worker finished, by its `RedisJob` kind. Jobs that will be retried count as
+/// failed each time.
+pub fn job_finished(job: &str, succeeded: bool) {
+    let outcome = if succeeded { "processed" } else { "failed" };
+    JOBS.with_label_values(&[job, outcome]).inc();
+}
+
+pub fn ping_sent(kind: &str, succeeded: bool) {
+    let outcome = if succeeded { "sent" } else { "failed" };
+    PINGS.with_label_values(&[kind, outcome]).inc();
+}
+
+/// Middleware recording how long each request took, by the route pattern it matched so paths
+/// with IDs in them share a series.
+pub async fn record_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let timer = std::time::Instant::now();
+    let res = next.call(req).await?;
+    let route = res
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| "unmatched".to_string());
+    REQUEST_DURATION
+        .with_label_values(&[&method, &route, res.status().as_str()])
+        .observe(timer.elapsed().as_secs_f64());
+    Ok(res)
+}
+
+/// Every metric in the Prometheus text format.
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    if let Err(err) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        log::error!("Failed to encode metrics: {}", err);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
@@ -0,0 +1,4 @@
+CREATE TABLE car_transfer (
+    car_id INT PRIMARY KEY REFERENCES car(id) ON DELETE CASCADE,
+    new_driver VARCHAR NOT NULL REFERENCES users(id)
+);
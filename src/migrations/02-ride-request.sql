@@ -0,0 +1,7 @@
+CREATE TABLE ride_request (
+    event_id INT REFERENCES event(id) ON DELETE CASCADE,
+    rider VARCHAR REFERENCES users(id),
+    earliest_departure TIMESTAMP WITH TIME ZONE,
+    latest_departure TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (event_id, rider)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE ride_request ADD COLUMN note VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE ride_request ADD COLUMN created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
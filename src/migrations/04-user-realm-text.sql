@@ -0,0 +1,3 @@
+-- Realms are the names of configured auth providers, so they can't be a fixed enum.
+ALTER TABLE users ALTER COLUMN realm TYPE VARCHAR USING realm::text;
+DROP TYPE user_realm;
@@ -0,0 +1,89 @@
+CREATE TABLE user_identity (
+    realm VARCHAR NOT NULL,
+    subject VARCHAR NOT NULL,
+    user_id VARCHAR NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    email VARCHAR NOT NULL,
+    PRIMARY KEY (realm, subject)
+);
+
+INSERT INTO user_identity (realm, subject, user_id, email) SELECT realm, id, id, email FROM users;
+
+-- Merge accounts that share an email. CSH and Google only hand out verified addresses.
+-- CSH accounts are kept over the others, since pings are only sent to CSH users.
+CREATE TEMPORARY TABLE user_merge AS
+SELECT id AS old_id,
+FIRST_VALUE(id) OVER (PARTITION BY LOWER(email) ORDER BY realm = 'csh' DESC, id) AS new_id
+FROM users;
+DELETE FROM user_merge WHERE old_id = new_id;
+
+-- Nobody can drive two cars to one event, so users that both drive in the same event are left
+-- as separate accounts.
+DELETE FROM user_merge WHERE old_id IN (
+    SELECT m.old_id FROM user_merge m
+    JOIN car mine ON mine.driver = m.old_id
+    JOIN car theirs ON theirs.event_id = mine.event_id AND theirs.driver != m.old_id
+    WHERE theirs.driver = m.new_id
+    OR theirs.driver IN (SELECT old_id FROM user_merge other WHERE other.new_id = m.new_id)
+);
+CREATE TEMPORARY TABLE user_group AS
+SELECT old_id AS user_id, new_id FROM user_merge
+UNION SELECT new_id, new_id FROM user_merge;
+
+-- Each merged user keeps one place per event: their car if one of them drove, else the kept
+-- user's seat, else the seat of the first merged user.
+DELETE FROM rider USING car, user_group
+WHERE car.id = rider.car_id AND rider.rider = user_group.user_id
+AND car.event_id IN (
+    SELECT driven.event_id FROM car driven JOIN user_group g ON g.user_id = driven.driver
+    WHERE g.new_id = user_group.new_id
+);
+DELETE FROM rider USING car, user_merge
+WHERE car.id = rider.car_id AND rider.rider = user_merge.old_id
+AND EXISTS (
+    SELECT 1 FROM rider kept JOIN car seat ON seat.id = kept.car_id
+    WHERE seat.event_id = car.event_id AND (
+        kept.rider = user_merge.new_id
+        OR kept.rider IN (
+            SELECT old_id FROM user_merge other
+            WHERE other.new_id = user_merge.new_id AND other.old_id < user_merge.old_id
+        )
+    )
+);
+UPDATE rider SET rider = new_id FROM user_merge WHERE rider = old_id;
+
+DELETE FROM ride_request USING user_group
+WHERE ride_request.rider = user_group.user_id
+AND ride_request.event_id IN (
+    SELECT car.event_id FROM car JOIN user_group g ON g.user_id = car.driver
+    WHERE g.new_id = user_group.new_id
+    UNION
+    SELECT car.event_id FROM rider JOIN car ON car.id = rider.car_id
+    JOIN user_group g ON g.user_id = rider.rider
+    WHERE g.new_id = user_group.new_id
+);
+DELETE FROM ride_request USING user_merge
+WHERE ride_request.rider = user_merge.old_id
+AND EXISTS (
+    SELECT 1 FROM ride_request kept
+    WHERE kept.event_id = ride_request.event_id AND (
+        kept.rider = user_merge.new_id
+        OR kept.rider IN (
+            SELECT old_id FROM user_merge other
+            WHERE other.new_id = user_merge.new_id AND other.old_id < user_merge.old_id
+        )
+    )
+);
+UPDATE ride_request SET rider = new_id FROM user_merge WHERE rider = old_id;
+
+DELETE FROM car_transfer USING car, user_group driver, user_group recipient
+WHERE car.id = car_transfer.car_id AND car.driver = driver.user_id
+AND car_transfer.new_driver = recipient.user_id AND driver.new_id = recipient.new_id;
+
+UPDATE event SET creator = new_id FROM user_merge WHERE creator = old_id;
+UPDATE car SET driver = new_id FROM user_merge WHERE driver = old_id;
+UPDATE car_transfer SET new_driver = new_id FROM user_merge WHERE new_driver = old_id;
+UPDATE user_identity SET user_id = new_id FROM user_merge WHERE user_id = old_id;
+
+DELETE FROM users USING user_merge WHERE users.id = user_merge.old_id;
+DROP TABLE user_group;
+DROP TABLE user_merge;
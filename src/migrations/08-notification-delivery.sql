@@ -0,0 +1,6 @@
+CREATE TABLE notification_delivery (
+    job_id VARCHAR NOT NULL,
+    recipient VARCHAR NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    delivered_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (job_id, recipient)
+);
@@ -0,0 +1,5 @@
+-- Drives the past cars and owns the past events of deleted accounts, and of everyone once
+-- retention anonymizes an event. No login can get this ID: CSH IDs are LDAP entry UUIDs, Google
+-- IDs are the numeric `sub`, and other providers' IDs are `<provider name>:<subject>`, where
+-- `config check` makes sure the name isn't empty. None of them start with ':'.
+INSERT INTO users (id, realm, name, email) VALUES (':deleted', 'deleted', 'Deleted User', '');
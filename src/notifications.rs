@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+/// How `{time}` is shown when no locale sets `time_format`.
+const DEFAULT_TIME_FORMAT: &str = "%a %b %-d at %-I:%M %p";
+
+/// A notification the worker sends. Each has a template, which locales can override.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// A rider joined the driver's car.
+    Join,
+    /// A rider left the driver's car.
+    Leave,
+    /// The driver added the rider to their car.
+    Add,
+    /// The driver removed the rider from their car.
+    Remove,
+    /// The driver asked the recipient to take over their car.
+    TransferOffer,
+    /// A rider's car has a new driver.
+    DriverChange,
+    /// The rider's driver deleted their account, and their car with it.
+    Cancel,
+    /// The creator of an event the recipient asked for a ride to deleted their account, and the
+    /// event with it.
+    EventCancel,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::Join => "join",
+            NotificationKind::Leave => "leave",
+            NotificationKind::Add => "add",
+            NotificationKind::Remove => "remove",
+            NotificationKind::TransferOffer => "transfer_offer",
+            NotificationKind::DriverChange => "driver_change",
+            NotificationKind::Cancel => "cancel",
+            NotificationKind::EventCancel => "event_cancel",
+        }
+    }
+
+    /// The placeholders this kind's templates can use. For `add`, `remove` and `cancel`, the
+    /// rider is the recipient.
+    pub fn placeholders(&self) -> &'static [&'static str] {
+        match self {
+            NotificationKind::Join
+            | NotificationKind::Leave
+            | NotificationKind::Add
+            | NotificationKind::Remove
+            | NotificationKind::Cancel => &["rider", "driver", "event", "location", "time"],
+            NotificationKind::TransferOffer => &["driver", "event", "location", "time"],
+            NotificationKind::EventCancel => &["event", "location", "time"],
+            NotificationKind::DriverChange => {
+                &["old_driver", "driver", "event", "location", "time"]
+            }
+        }
+    }
+
+    /// The built-in English text, used when no locale has a template for this kind.
+    fn default_template(&self) -> &'static str {
+        match self {
+            NotificationKind::Join => "{rider} joined your ride to \"{event}\".",
+            NotificationKind::Leave => "{rider} left your ride \"{event}\".",
+            NotificationKind::Add => {
+                "You have been added to {driver}'s ride to \"{event}\" by the driver."
+            }
+            NotificationKind::Remove => {
+                "You have been removed from {driver}'s ride to \"{event}\" by the driver."
+            }
+            NotificationKind::TransferOffer => {
+                "{driver} has asked you to take over their ride to \"{event}\"."
+            }
+            NotificationKind::DriverChange => {
+                "{driver} is now driving your ride to \"{event}\" instead of {old_driver}."
+            }
+            NotificationKind::Cancel => {
+                "{driver} deleted their account, so their ride to \"{event}\" is cancelled."
+            }
+            NotificationKind::EventCancel => {
+                "\"{event}\" was cancelled because its organizer deleted their account."
+            }
+        }
+    }
+}
+
+/// The values a notification's placeholders are filled in with. Values a kind doesn't use are
+/// left empty.
+#[derive(Clone, Copy)]
+pub struct Placeholders<'a> {
+    pub rider: &'a str,
+    pub driver: &'a str,
+    pub old_driver: &'a str,
+    pub event: &'a str,
+    pub location: &'a str,
+    /// When the car leaves.
+    pub time: DateTime<Utc>,
+}
+
+impl Placeholders<'_> {
+    fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "rider" => Some(self.rider),
+            "driver" => Some(self.driver),
+            "old_driver" => Some(self.old_driver),
+            "event" => Some(self.event),
+            "location" => Some(self.location),
+            _ => None,
+        }
+    }
+}
+
+/// Overrides for one locale. Anything left out falls back to the default locale, then to the
+/// built-in English text.
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocaleTemplates {
+    /// A `strftime` format for `{time}`.
+    pub time_format: Option<String>,
+    pub templates: HashMap<NotificationKind, String>,
+}
+
+/// The text of every notification, by locale. The worker renders a notification once and
+/// hands the text to each channel, so Pings and any other channel share these.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationTemplates {
+    /// Used for users who haven't picked a locale, or picked one without a template.
+    pub default_locale: String,
+    /// Times are shown in this zone, e.g. `America/New_York`.
+    pub timezone: Tz,
+    pub locales: HashMap<String, LocaleTemplates>,
+}
+
+impl Default for NotificationTemplates {
+    fn default() -> Self {
+        NotificationTemplates {
+            default_locale: "en".to_string(),
+            timezone: Tz::America__New_York,
+            locales: HashMap::new(),
+        }
+    }
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Split a template into text and `{placeholder}`s. `{{` and `}}` stand for literal braces.
+fn parse(template: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        segments.push(Segment::Text(&rest[..start]));
+        let brace = &rest[start..start + 1];
+        rest = &rest[start + 1..];
+        if rest.starts_with(brace) {
+            segments.push(Segment::Text(brace));
+            rest = &rest[1..];
+        } else if brace == "}" {
+            return Err("has a } without a matching {".to_string());
+        } else {
+            let end = rest
+                .find('}')
+                .ok_or("has a { without a matching }".to_string())?;
+            segments.push(Segment::Placeholder(&rest[..end]));
+            rest = &rest[end + 1..];
+        }
+    }
+    segments.push(Segment::Text(rest));
+    Ok(segments)
+}
+
+fn valid_time_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+}
+
+/// Locales are matched ignoring case, with `_` and `-` treated the same.
+fn normalize(locale: &str) -> String {
+    locale.to_lowercase().replace('_', "-")
+}
+
+impl NotificationTemplates {
+    /// List every template that can't be rendered: unbalanced braces, placeholders the kind
+    /// doesn't have and invalid time formats.
+    pub fn check(&self) -> Vec<String> {
+        let mut errs = Vec::new();
+        if normalize(&self.default_locale) != "en" && self.locale(&self.default_locale).is_none() {
+            errs.push(format!(
+                "notifications.default_locale is {}, which has no templates",
+                self.default_locale
+            ));
+        }
+        let mut names: Vec<&String> = self.locales.keys().collect();
+        names.sort();
+        for name in names {
+            let locale = &self.locales[name];
+            if let Some(format) = &locale.time_format {
+                if !valid_time_format(format) {
+                    errs.push(format!(
+                        "notifications.locales.{}.time_format is not a valid time format",
+                        name
+                    ));
+                }
+            }
+            let mut templates: Vec<_> = locale.templates.iter().collect();
+            templates.sort_by_key(|(kind, _)| kind.as_str());
+            for (kind, template) in templates {
+                let field = format!("notifications.locales.{}.templates.{}", name, kind.as_str());
+                match parse(template) {
+                    Ok(segments) => {
+                        for segment in segments {
+                            if let Segment::Placeholder(placeholder) = segment {
+                                if !kind.placeholders().contains(&placeholder) {
+                                    errs.push(format!(
+                                        "{} uses {{{}}}, which isn't one of: {}",
+                                        field,
+                                        placeholder,
+                                        kind.placeholders().join(", ")
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => errs.push(format!("{} {}", field, err)),
+                }
+            }
+        }
+        errs
+    }
+
+    fn locale(&self, name: &str) -> Option<&LocaleTemplates> {
+        let name = normalize(name);
+        self.locales
+            .iter()
+            .find(|(locale, _)| normalize(locale) == name)
+            .map(|(_, templates)| templates)
+    }
+
+    /// The locales to look for templates in, in order: `locale` itself, its language without
+    /// the region (`pt` for `pt-BR`), then the default locale.
+    fn fallbacks(&self, locale: Option<&str>) -> Vec<&LocaleTemplates> {
+        let mut names = Vec::new();
+        if let Some(locale) = locale.map(normalize) {
+            if let Some((language, _)) = locale.split_once('-') {
+                names.push(language.to_string());
+            }
+            names.insert(0, locale);
+        }
+        names.push(self.default_locale.clone());
+        names.iter().filter_map(|name| self.locale(name)).collect()
+    }
+
+    /// The text of a `kind` notification in `locale`, or in the default locale when the user
+    /// hasn't picked one.
+    pub fn render(
+        &self,
+        kind: NotificationKind,
+        locale: Option<&str>,
+        values: &Placeholders,
+    ) -> String {
+        let fallbacks = self.fallbacks(locale);
+        let template = fallbacks
+            .iter()
+            .find_map(|locale| locale.templates.get(&kind))
+            .map(String::as_str)
+            .unwrap_or(kind.default_template());
+        let time_format = fallbacks
+            .iter()
+            .find_map(|locale| locale.time_format.as_deref())
+            .filter(|format| valid_time_format(format))
+            .unwrap_or(DEFAULT_TIME_FORMAT);
+        let segments = parse(template).unwrap_or_else(|_| vec![Segment::Text(template)]);
+
+        let mut out = String::new();
+        for segment in segments {
+            match segment {
+                Segment::Text(text) => out.push_str(text),
+                Segment::Placeholder("time") => out.push_str(
+                    &values
+                        .time
+                        .with_timezone(&self.timezone)
+                        .format(time_format)
+                        .to_string(),
+                ),
+                Segment::Placeholder(name) => match values.get(name) {
+                    Some(value) => out.push_str(value),
+                    None => out.push_str(&format!("{{{}}}", name)),
+                },
+            }
+        }
+        out
+    }
+}
@@ -4,22 +4,30 @@ use reqwest::{
     Client,
 };
 use serde_json::json;
+use tracing::instrument;
+
+use crate::metrics;
+use crate::notifications::NotificationKind;
 
 pub struct PingClient {
     client: Client,
+    base_url: String,
     join_route: String,
     leave_route: String,
     add_route: String,
     remove_route: String,
+    transfer_route: String,
 }
 
 impl PingClient {
     pub fn new(
+        base_url: String,
         token: String,
         join_route: String,
         leave_route: String,
         add_route: String,
         remove_route: String,
+        transfer_route: String,
     ) -> Result<PingClient> {
         let mut headers = HeaderMap::new();
         headers.insert(
@@ -31,70 +39,49 @@ impl PingClient {
 
         Ok(PingClient {
             client,
+            base_url: base_url.trim_end_matches('/').to_string(),
             join_route,
             leave_route,
             add_route,
             remove_route,
+            transfer_route,
         })
     }
 
-    pub async fn send_join(&self, to: &str, join: &str, event: &str) -> Result<()> {
-        self.client
-            .post(format!(
-                "https://pings.csh.rit.edu/service/route/{}/ping",
-                self.join_route
-            ))
-            .json(&json!({
-                "username": to,
-                "body": format!("{join} joined your ride to \"{event}\".")
-            }))
-            .send()
-            .await?;
-        Ok(())
-    }
-
-    pub async fn send_leave(&self, to: &str, leave: &str, event: &str) -> Result<()> {
-        self.client
-            .post(format!(
-                "https://pings.csh.rit.edu/service/route/{}/ping",
-                self.leave_route
-            ))
-            .json(&json!({
-                "username": to,
-                "body": format!("{leave} left your ride \"{event}\".")
-            }))
-            .send()
-            .await?;
-        Ok(())
-    }
-
-    pub async fn send_add(&self, to: &str, driver: &str, event: &str) -> Result<()> {
-        self.client
-            .post(format!(
-                "https://pings.csh.rit.edu/service/route/{}/ping",
-                self.add_route
-            ))
-            .json(&json!({
-                "username": to,
-                "body": format!("You have been added to {driver}'s ride to \"{event}\" by the driver.")
-            }))
-            .send()
-            .await?;
-        Ok(())
+    fn route(&self, kind: NotificationKind) -> &str {
+        match kind {
+            NotificationKind::Join => &self.join_route,
+            NotificationKind::Leave => &self.leave_route,
+            NotificationKind::Add => &self.add_route,
+            NotificationKind::Remove | NotificationKind::Cancel | NotificationKind::EventCancel => {
+                &self.remove_route
+            }
+            NotificationKind::TransferOffer | NotificationKind::DriverChange => {
+                &self.transfer_route
+            }
+        }
     }
 
-    pub async fn send_remove(&self, to: &str, driver: &str, event: &str) -> Result<()> {
-        self.client
+    /// Send `body`, rendered from the `kind` template, to `to` through that kind's route. Pings
+    /// only counts as delivered when it responds with a success status.
+    #[instrument(name = "pings::send", skip_all, fields(kind = kind.as_str()))]
+    pub async fn send(&self, kind: NotificationKind, to: &str, body: &str) -> Result<()> {
+        let result = self
+            .client
             .post(format!(
-                "https://pings.csh.rit.edu/service/route/{}/ping",
-                self.remove_route
+                "{}/service/route/{}/ping",
+                self.base_url,
+                self.route(kind)
             ))
             .json(&json!({
                 "username": to,
-                "body": format!("You have been removed from {driver}'s ride to \"{event}\" by the driver.")
+                "body": body,
             }))
             .send()
-            .await?;
+            .await
+            .and_then(|res| res.error_for_status());
+        metrics::ping_sent(kind.as_str(), result.is_ok());
+        result?;
         Ok(())
     }
 }
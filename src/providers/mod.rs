@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use futures_util::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use crate::api::v1::auth::models::UserInfo;
+use crate::error::AppResult;
+
+pub mod oauth;
+pub mod oidc;
+
+/// Secrets from the login redirect that the provider's callback has to match.
+#[derive(Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub provider: String,
+    pub state: String,
+    pub nonce: Option<String>,
+    pub pkce_verifier: String,
+    /// Whether to link this identity to the logged in user rather than log in with it.
+    #[serde(default)]
+    pub link: bool,
+}
+
+/// An identity provider users can log in with.
+pub trait AuthProvider: Send + Sync {
+    /// Used in the login URLs and stored as the realm of every user from this provider.
+    fn name(&self) -> &str;
+    fn display_name(&self) -> &str;
+    /// The URL to send the user to, along with what to keep in their session until they return.
+    fn login_url(&self) -> (String, PendingLogin);
+    /// Exchange the code from the callback and map the user's profile to `UserInfo`. Errors are
+    /// returned as the response to send.
+    fn authenticate<'a>(
+        &'a self,
+        code: String,
+        pending: PendingLogin,
+    ) -> LocalBoxFuture<'a, AppResult<UserInfo>>;
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderSummary {
+    pub name: String,
+    pub display_name: String,
+}
+
+/// Which claims of a generic provider's userinfo response to read. Groups are only trusted when
+/// `groups` is set, since they decide who is an admin.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ClaimMapping {
+    pub id: String,
+    pub email: String,
+    pub name: String,
+    pub given_name: String,
+    pub family_name: String,
+    pub username: String,
+    pub picture: String,
+    pub groups: Option<String>,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            id: "sub".to_string(),
+            email: "email".to_string(),
+            name: "name".to_string(),
+            given_name: "given_name".to_string(),
+            family_name: "family_name".to_string(),
+            username: "preferred_username".to_string(),
+            picture: "picture".to_string(),
+            groups: None,
+        }
+    }
+}
+
+impl ClaimMapping {
+    fn claim(claims: &Map<String, Value>, key: &str) -> Option<String> {
+        match claims.get(key) {
+            Some(Value::String(value)) => Some(value.clone()),
+            Some(Value::Number(value)) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Ids are prefixed with the provider name so subjects from different providers can't clash.
+    pub fn user_info(&self, provider: &str, claims: &Map<String, Value>) -> Result<UserInfo> {
+        let subject = Self::claim(claims, &self.id).ok_or(anyhow!(
+            "{} did not return the {} claim",
+            provider,
+            self.id
+        ))?;
+        let email = Self::claim(claims, &self.email).ok_or(anyhow!(
+            "{} did not return the {} claim",
+            provider,
+            self.email
+        ))?;
+        let (given_name, family_name) = match (
+            Self::claim(claims, &self.given_name),
+            Self::claim(claims, &self.family_name),
+        ) {
+            (Some(given_name), family_name) => (given_name, family_name.unwrap_or_default()),
+            (None, _) => (
+                Self::claim(claims, &self.name).unwrap_or(email.clone()),
+                String::new(),
+            ),
+        };
+        let groups = match self.groups.as_ref().and_then(|key| claims.get(key)) {
+            Some(Value::Array(groups)) => groups
+                .iter()
+                .filter_map(|group| group.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(UserInfo {
+            id: format!("{}:{}", provider, subject),
+            username: Self::claim(claims, &self.username),
+            email,
+            given_name,
+            family_name,
+            picture: Self::claim(claims, &self.picture).unwrap_or_default(),
+            groups,
+        })
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ProviderKind {
+    Csh {
+        issuer_url: Option<String>,
+    },
+    Google {
+        issuer_url: Option<String>,
+    },
+    Oidc {
+        issuer_url: String,
+        #[serde(default)]
+        claims: ClaimMapping,
+    },
+    Oauth2 {
+        auth_url: String,
+        token_url: String,
+        userinfo_url: String,
+        #[serde(default)]
+        claims: ClaimMapping,
+    },
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ProviderConfig {
+    pub name: String,
+    pub display_name: Option<String>,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(flatten)]
+    pub kind: ProviderKind,
+}
+
+impl ProviderConfig {
+    /// Read a provider from `{NAME}_*` environment variables, e.g. `GITHUB_KIND=oauth2`,
+    /// `GITHUB_CLIENT_ID`, `GITHUB_SCOPES="read:user user:email"` and `GITHUB_CLAIM_ID=id`.
+    /// `csh` and `google` default to their own kind.
+    fn from_env(name: &str) -> Result<Self> {
+        let prefix = format!("{}_", name.to_uppercase());
+        let mut config = Map::new();
+        let mut claims = Map::new();
+        for (key, value) in env::vars().filter(|(_, value)| !value.is_empty()) {
+            let Some(key) = key.strip_prefix(&prefix).map(str::to_lowercase) else {
+                continue;
+            };
+            if let Some(claim) = key.strip_prefix("claim_") {
+                claims.insert(claim.to_string(), Value::String(value));
+            } else if key == "scopes" {
+                let scopes = value
+                    .split_whitespace()
+                    .map(|scope| Value::String(scope.to_string()))
+                    .collect();
+                config.insert(key, Value::Array(scopes));
+            } else {
+                config.insert(key, Value::String(value));
+            }
+        }
+        if !config.contains_key("kind") {
+            config.insert("kind".to_string(), Value::String(name.to_string()));
+        }
+        config.insert("name".to_string(), Value::String(name.to_string()));
+        config.insert("claims".to_string(), Value::Object(claims));
+        serde_json::from_value(Value::Object(config))
+            .map_err(|err| anyhow!("Invalid configuration for auth provider {}: {}", name, err))
+    }
+}
+
+pub struct ProviderRegistry {
+    providers: Vec<Arc<dyn AuthProvider>>,
+}
+
+impl ProviderRegistry {
+    /// Providers come from the JSON list in `AUTH_PROVIDERS_FILE` if it is set, otherwise from
+    /// the comma separated names in `AUTH_PROVIDERS` (default `csh,google`).
+    pub fn configs_from_env() -> Result<Vec<ProviderConfig>> {
+        if let Some(file) = env::var("AUTH_PROVIDERS_FILE")
+            .ok()
+            .filter(|file| !file.is_empty())
+        {
+            let path = PathBuf::from(file);
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|err| anyhow!("Failed to read {}: {}", path.display(), err))?;
+            return serde_json::from_str(&contents)
+                .map_err(|err| anyhow!("Failed to parse {}: {}", path.display(), err));
+        }
+        env::var("AUTH_PROVIDERS")
+            .ok()
+            .filter(|names| !names.is_empty())
+            .unwrap_or("csh,google".to_string())
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(ProviderConfig::from_env)
+            .collect()
+    }
+
+    /// Build every configured provider. OpenID providers are discovered here, so this should
+    /// only be run once at startup.
+    pub async fn new(configs: Vec<ProviderConfig>, redirect_domain: &str) -> Result<Self> {
+        let mut seen = HashSet::new();
+        let mut providers: Vec<Arc<dyn AuthProvider>> = Vec::new();
+        for config in configs {
+            if !seen.insert(config.name.clone()) {
+                return Err(anyhow!("Auth provider {} is configured twice", config.name));
+            }
+            let redirect_url = format!("{}/api/v1/auth/{}/redirect", redirect_domain, config.name);
+            providers.push(match config.kind {
+                ProviderKind::Oauth2 { .. } => {
+                    Arc::new(oauth::OAuthProvider::new(config, redirect_url)?)
+                }
+                _ => Arc::new(oidc::OidcProvider::discover(config, redirect_url).await?),
+            });
+        }
+        Ok(Self { providers })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn AuthProvider>> {
+        self.providers
+            .iter()
+            .find(|provider| provider.name() == name)
+            .cloned()
+    }
+
+    pub fn list(&self) -> Vec<ProviderSummary> {
+        self.providers
+            .iter()
+            .map(|provider| ProviderSummary {
+                name: provider.name().to_string(),
+                display_name: provider.display_name().to_string(),
+            })
+            .collect()
+    }
+}
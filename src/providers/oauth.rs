@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use futures_util::future::LocalBoxFuture;
+use oauth2::basic::BasicClient;
+use oauth2::reqwest::async_http_client;
+use oauth2::{
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope as OAuthScope, TokenResponse, TokenUrl,
+};
+use reqwest::{header, Client};
+use serde_json::{Map, Value};
+
+use super::{AuthProvider, ClaimMapping, PendingLogin, ProviderConfig, ProviderKind};
+use crate::api::v1::auth::models::UserInfo;
+use crate::error::{AppResult, OrInternal};
+
+/// A plain OAuth2 provider without ID tokens, such as GitHub. The user's profile is read from
+/// `userinfo_url` with the access token.
+pub struct OAuthProvider {
+    name: String,
+    display_name: String,
+    scopes: Vec<String>,
+    client: BasicClient,
+    userinfo_url: String,
+    claims: ClaimMapping,
+}
+
+impl OAuthProvider {
+    pub fn new(config: ProviderConfig, redirect_url: String) -> Result<Self> {
+        let ProviderKind::Oauth2 {
+            auth_url,
+            token_url,
+            userinfo_url,
+            claims,
+        } = config.kind
+        else {
+            return Err(anyhow!("{} is not an OAuth2 provider", config.name));
+        };
+
+        let client = BasicClient::new(
+            ClientId::new(config.client_id),
+            Some(ClientSecret::new(config.client_secret)),
+            AuthUrl::new(auth_url).map_err(|err| anyhow!("Invalid auth URL: {}", err))?,
+            Some(TokenUrl::new(token_url).map_err(|err| anyhow!("Invalid token URL: {}", err))?),
+        )
+        .set_redirect_uri(
+            RedirectUrl::new(redirect_url)
+                .map_err(|err| anyhow!("Invalid redirect URL: {}", err))?,
+        );
+
+        Ok(Self {
+            display_name: config.display_name.unwrap_or(config.name.clone()),
+            name: config.name,
+            scopes: config.scopes,
+            client,
+            userinfo_url,
+            claims,
+        })
+    }
+}
+
+impl AuthProvider for OAuthProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    fn login_url(&self) -> (String, PendingLogin) {
+        let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let mut request = self.client.authorize_url(CsrfToken::new_random);
+        for scope in self.scopes.iter() {
+            request = request.add_scope(OAuthScope::new(scope.clone()));
+        }
+        let (authorize_url, csrf_state) = request.set_pkce_challenge(pkce_code_challenge).url();
+
+        (
+            authorize_url.to_string(),
+            PendingLogin {
+                provider: self.name.clone(),
+                state: csrf_state.secret().clone(),
+                nonce: None,
+                pkce_verifier: pkce_code_verifier.secret().clone(),
+                link: false,
+            },
+        )
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        code: String,
+        pending: PendingLogin,
+    ) -> LocalBoxFuture<'a, AppResult<UserInfo>> {
+        Box::pin(async move {
+            let token = self
+                .client
+                .exchange_code(AuthorizationCode::new(code))
+                .set_pkce_verifier(PkceCodeVerifier::new(pending.pkce_verifier))
+                .request_async(async_http_client)
+                .await
+                .or_internal("Failed to get OAuth Token")?;
+
+            // Some APIs, like GitHub's, reject requests without a user agent.
+            let claims: Map<String, Value> = Client::new()
+                .get(&self.userinfo_url)
+                .header(header::USER_AGENT, "rideboard")
+                .header(header::ACCEPT, "application/json")
+                .bearer_auth(token.access_token().secret())
+                .send()
+                .await
+                .or_internal("Failed to get UserInfo Token")?
+                .json()
+                .await
+                .or_internal("Failed to deserialize UserInfo token")?;
+
+            self.claims
+                .user_info(&self.name, &claims)
+                .or_internal("Failed to read UserInfo")
+        })
+    }
+}
@@ -0,0 +1,294 @@
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use futures_util::future::LocalBoxFuture;
+use log::error;
+use openidconnect::core::{
+    CoreAuthenticationFlow, CoreClient, CoreJsonWebKeySet, CoreProviderMetadata,
+};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::{
+    AccessTokenHash, AuthorizationCode, ClaimsVerificationError, ClientId, ClientSecret, CsrfToken,
+    IssuerUrl, Nonce, OAuth2TokenResponse, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl,
+    Scope as OAuthScope, SignatureVerificationError, TokenResponse,
+};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use super::{AuthProvider, ClaimMapping, PendingLogin, ProviderConfig, ProviderKind};
+use crate::api::v1::auth::models::{CSHUserInfo, GoogleUserInfo, ProviderUserInfo, UserInfo};
+use crate::error::{AppError, AppResult, OrInternal};
+
+/// How to turn the userinfo response into a `UserInfo`.
+enum Profile {
+    Csh,
+    Google,
+    Claims(ClaimMapping),
+}
+
+/// How often an ID token signed with a key we don't have can make us refetch the issuer's
+/// keys, so made up key IDs can't be used to hammer it.
+const KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An OpenID Connect provider set up from its issuer's discovery document.
+pub struct OidcProvider {
+    name: String,
+    display_name: String,
+    scopes: Vec<String>,
+    metadata: CoreProviderMetadata,
+    client_id: ClientId,
+    client_secret: ClientSecret,
+    redirect_url: RedirectUrl,
+    /// Rebuilt with the issuer's current keys when it starts signing with a new one.
+    client: RwLock<CoreClient>,
+    /// When the keys were last refetched after discovery.
+    keys_refreshed: Mutex<Option<Instant>>,
+    userinfo_url: String,
+    profile: Profile,
+}
+
+fn make_client(
+    metadata: CoreProviderMetadata,
+    client_id: &ClientId,
+    client_secret: &ClientSecret,
+    redirect_url: &RedirectUrl,
+) -> CoreClient {
+    CoreClient::from_provider_metadata(metadata, client_id.clone(), Some(client_secret.clone()))
+        .set_redirect_uri(redirect_url.clone())
+}
+
+impl OidcProvider {
+    pub async fn discover(config: ProviderConfig, redirect_url: String) -> Result<Self> {
+        let (issuer_url, profile, display_name, default_scopes) = match config.kind {
+            ProviderKind::Csh { issuer_url } => (
+                issuer_url.unwrap_or("https://sso.csh.rit.edu/auth/realms/csh".to_string()),
+                Profile::Csh,
+                "CSH",
+                vec!["house-service-oidc"],
+            ),
+            ProviderKind::Google { issuer_url } => (
+                issuer_url.unwrap_or("https://accounts.google.com".to_string()),
+                Profile::Google,
+                "Google",
+                vec!["profile", "email"],
+            ),
+            ProviderKind::Oidc { issuer_url, claims } => (
+                issuer_url,
+                Profile::Claims(claims),
+                config.name.as_str(),
+                vec!["profile", "email"],
+            ),
+            ProviderKind::Oauth2 { .. } => {
+                return Err(anyhow!("{} is not an OpenID provider", config.name))
+            }
+        };
+
+        let issuer = IssuerUrl::new(issuer_url.clone())
+            .map_err(|err| anyhow!("Invalid issuer URL {}: {}", issuer_url, err))?;
+        let metadata = CoreProviderMetadata::discover_async(issuer, async_http_client)
+            .await
+            .map_err(|err| anyhow!("Failed to discover OpenID provider {}: {}", issuer_url, err))?;
+        let userinfo_url = metadata
+            .userinfo_endpoint()
+            .map(|url| url.url().to_string())
+            .ok_or(anyhow!("{} does not have a userinfo endpoint", issuer_url))?;
+
+        let client_id = ClientId::new(config.client_id);
+        let client_secret = ClientSecret::new(config.client_secret);
+        let redirect_url = RedirectUrl::new(redirect_url)
+            .map_err(|err| anyhow!("Invalid redirect URL: {}", err))?;
+        let client = make_client(metadata.clone(), &client_id, &client_secret, &redirect_url);
+
+        let scopes = if config.scopes.is_empty() {
+            default_scopes.into_iter().map(str::to_string).collect()
+        } else {
+            config.scopes
+        };
+        Ok(Self {
+            display_name: config.display_name.unwrap_or(display_name.to_string()),
+            name: config.name,
+            scopes,
+            metadata,
+            client_id,
+            client_secret,
+            redirect_url,
+            client: RwLock::new(client),
+            keys_refreshed: Mutex::new(None),
+            userinfo_url,
+            profile,
+        })
+    }
+
+    fn client(&self) -> CoreClient {
+        self.client
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Refetch the issuer's signing keys, unless that was done less than
+    /// `KEY_REFRESH_INTERVAL` ago. Returns whether there are new keys to try.
+    async fn refresh_keys(&self) -> bool {
+        {
+            let mut refreshed = self
+                .keys_refreshed
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if refreshed.is_some_and(|at| at.elapsed() < KEY_REFRESH_INTERVAL) {
+                return false;
+            }
+            *refreshed = Some(Instant::now());
+        }
+
+        match CoreJsonWebKeySet::fetch_async(self.metadata.jwks_uri(), async_http_client).await {
+            Ok(jwks) => {
+                let client = make_client(
+                    self.metadata.clone().set_jwks(jwks),
+                    &self.client_id,
+                    &self.client_secret,
+                    &self.redirect_url,
+                );
+                *self.client.write().unwrap_or_else(PoisonError::into_inner) = client;
+                true
+            }
+            Err(err) => {
+                error!("Failed to refetch signing keys for {}: {}", self.name, err);
+                false
+            }
+        }
+    }
+
+    /// Exchange an authorization code, verify the ID token against the issuer's keys and the
+    /// nonce from the session, then fetch the user's profile from the userinfo endpoint. The
+    /// keys are refetched if the token is signed with one we haven't seen.
+    async fn fetch_user_info<T: DeserializeOwned + ProviderUserInfo>(
+        &self,
+        code: String,
+        pending: PendingLogin,
+    ) -> AppResult<T> {
+        let nonce = pending.nonce.map(Nonce::new).ok_or(AppError::BadRequest(
+            "Login expired, please log in again".to_string(),
+        ))?;
+
+        let token = self
+            .client()
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(PkceCodeVerifier::new(pending.pkce_verifier))
+            .request_async(async_http_client)
+            .await
+            .or_internal("Failed to get OAuth Token")?;
+
+        let id_token = token.id_token().ok_or(AppError::internal(
+            "Provider did not return an ID Token",
+            anyhow!("Token response from {} has no ID token", self.name),
+        ))?;
+        let mut claims = id_token.claims(&self.client().id_token_verifier(), &nonce);
+        if let Err(ClaimsVerificationError::SignatureVerification(
+            SignatureVerificationError::NoMatchingKey,
+        )) = claims
+        {
+            if self.refresh_keys().await {
+                claims = id_token.claims(&self.client().id_token_verifier(), &nonce);
+            }
+        }
+        let claims = claims.map_err(|err| {
+            error!("{}", err);
+            AppError::Unauthorized("Failed to verify ID Token".to_string())
+        })?;
+        if let Some(expected_hash) = claims.access_token_hash() {
+            let matches = id_token
+                .signing_alg()
+                .and_then(|alg| AccessTokenHash::from_token(token.access_token(), &alg))
+                .is_ok_and(|actual_hash| &actual_hash == expected_hash);
+            if !matches {
+                return Err(AppError::Unauthorized(
+                    "Access Token does not match ID Token".to_string(),
+                ));
+            }
+        }
+
+        let user_info: T = Client::new()
+            .get(&self.userinfo_url)
+            .bearer_auth(token.access_token().secret())
+            .send()
+            .await
+            .or_internal("Failed to get UserInfo Token")?
+            .json()
+            .await
+            .or_internal("Failed to deserialize UserInfo token")?;
+
+        if user_info.subject() != claims.subject().as_str() {
+            return Err(AppError::Unauthorized(
+                "UserInfo does not match ID Token".to_string(),
+            ));
+        }
+
+        Ok(user_info)
+    }
+}
+
+impl AuthProvider for OidcProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    fn login_url(&self) -> (String, PendingLogin) {
+        let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let client = self.client();
+        let mut request = client.authorize_url(
+            CoreAuthenticationFlow::AuthorizationCode,
+            CsrfToken::new_random,
+            Nonce::new_random,
+        );
+        for scope in self.scopes.iter() {
+            request = request.add_scope(OAuthScope::new(scope.clone()));
+        }
+        let (authorize_url, csrf_state, nonce) =
+            request.set_pkce_challenge(pkce_code_challenge).url();
+
+        (
+            authorize_url.to_string(),
+            PendingLogin {
+                provider: self.name.clone(),
+                state: csrf_state.secret().clone(),
+                nonce: Some(nonce.secret().clone()),
+                pkce_verifier: pkce_code_verifier.secret().clone(),
+                link: false,
+            },
+        )
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        code: String,
+        pending: PendingLogin,
+    ) -> LocalBoxFuture<'a, AppResult<UserInfo>> {
+        Box::pin(async move {
+            match &self.profile {
+                Profile::Csh => self
+                    .fetch_user_info::<CSHUserInfo>(code, pending)
+                    .await
+                    .map(UserInfo::from),
+                Profile::Google => self
+                    .fetch_user_info::<GoogleUserInfo>(code, pending)
+                    .await
+                    .map(UserInfo::from),
+                Profile::Claims(mapping) => {
+                    let claims = self
+                        .fetch_user_info::<Map<String, Value>>(code, pending)
+                        .await?;
+                    mapping
+                        .user_info(&self.name, &claims)
+                        .or_internal("Failed to read UserInfo")
+                }
+            }
+        })
+    }
+}
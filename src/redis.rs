@@ -1,20 +1,64 @@
-use crate::app::RedisJob;
+use crate::app::{QueuedJob, RedisJob};
+use crate::config::RedisConfig;
+use crate::telemetry;
 use anyhow::{anyhow, Result};
 use redis::aio::MultiplexedConnection;
-use redis_work_queue::{Item, WorkQueue};
+use redis_work_queue::{Item, KeyPrefix, WorkQueue};
+use tracing::instrument;
 
-pub struct RedisQueue {
-    pub redis: MultiplexedConnection,
-    pub work_queue: WorkQueue,
+pub enum RedisQueue {
+    Redis {
+        redis: MultiplexedConnection,
+        work_queue: WorkQueue,
+    },
+    /// Holds jobs in memory instead, for running without a Redis server (e.g. in tests).
+    Memory(Vec<Item>),
+    /// Drops every job, for when notifications are turned off.
+    Disabled,
 }
 
 impl RedisQueue {
+    /// Connect to the queue the worker takes jobs from.
+    pub async fn connect(config: &RedisConfig) -> Result<Self> {
+        let redis = redis::Client::open(config.url.as_str())
+            .map_err(|err| anyhow!("Failed to create Redis client: {}", err))?
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|err| anyhow!("Failed to connect to Redis: {}", err))?;
+        Ok(RedisQueue::Redis {
+            redis,
+            work_queue: WorkQueue::new(KeyPrefix::from(config.key_prefix.as_str())),
+        })
+    }
+
+    #[instrument(skip_all, fields(job = job.kind()))]
     pub async fn insert_job(&mut self, job: RedisJob) -> Result<()> {
-        let item = Item::from_json_data(&job)?;
-        match self.work_queue.add_item(&mut self.redis, &item).await {
-            Ok(true) => Ok(()),
-            Ok(false) => Err(anyhow!("Job ID Already Exists in Queue")),
-            Err(err) => Err(anyhow!("{}", err)),
+        let item = Item::from_json_data(&QueuedJob {
+            job,
+            trace_context: telemetry::current_context(),
+        })?;
+        match self {
+            RedisQueue::Redis { redis, work_queue } => {
+                match work_queue.add_item(redis, &item).await {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(anyhow!("Job ID Already Exists in Queue")),
+                    Err(err) => Err(anyhow!("{}", err)),
+                }
+            }
+            RedisQueue::Memory(items) => {
+                items.push(item);
+                Ok(())
+            }
+            RedisQueue::Disabled => Ok(()),
+        }
+    }
+
+    /// Remove and return every job added to an in-memory queue. Other queues return nothing,
+    /// since a Redis queue's jobs are for the worker.
+    pub fn take_jobs(&mut self) -> Vec<Item> {
+        match self {
+            RedisQueue::Redis { .. } | RedisQueue::Disabled => Vec::new(),
+            RedisQueue::Memory(items) => std::mem::take(items),
         }
     }
 }
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{Months, TimeDelta, Utc};
+use log::{error, info};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::config::{RetentionConfig, RetentionMode};
+use crate::db::{delivery::Delivery, event::Event};
+
+/// Deliveries are only looked at while their job might be retried, which is well within this.
+const DELIVERY_RETENTION: TimeDelta = TimeDelta::days(7);
+
+/// What one run of the retention policy removed.
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct RetentionReport {
+    /// Events deleted in `purge` mode.
+    pub events_purged: usize,
+    /// Riders, ride requests, transfer offers and comments removed, and drivers and creators
+    /// replaced, in `anonymize` mode.
+    pub rows_anonymized: u64,
+    pub deliveries_deleted: u64,
+}
+
+/// Apply the retention policy once, in one transaction. Old notification deliveries are deleted
+/// even when events are kept forever.
+#[instrument(name = "retention::run", skip_all)]
+pub async fn run(config: &RetentionConfig, db: &PgPool) -> Result<RetentionReport> {
+    let mut report = RetentionReport::default();
+    let mut tx = db
+        .begin()
+        .await
+        .map_err(|err| anyhow!("Failed to make SQL Transaction: {}", err))?;
+    let cutoff = Some(config.months)
+        .filter(|months| *months > 0)
+        .and_then(|months| Utc::now().checked_sub_months(Months::new(months)));
+    if let Some(cutoff) = cutoff {
+        match config.mode {
+            RetentionMode::Anonymize => {
+                report.rows_anonymized = Event::anonymize_ended_before(cutoff, &mut tx).await?
+            }
+            RetentionMode::Purge => {
+                report.events_purged = Event::delete_ended_before(cutoff, &mut *tx).await?.len()
+            }
+        }
+    }
+    report.deliveries_deleted =
+        Delivery::delete_before(Utc::now() - DELIVERY_RETENTION, &mut *tx).await?;
+    tx.commit()
+        .await
+        .map_err(|err| anyhow!("Failed to commit transaction: {}", err))?;
+    Ok(report)
+}
+
+/// Apply the retention policy now and every `interval_hours` after, until the task is dropped.
+/// Every worker runs it, which is harmless since a second run finds nothing left to remove.
+pub async fn run_periodically(config: RetentionConfig, db: PgPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(
+        config.interval_hours.saturating_mul(60 * 60),
+    ));
+    loop {
+        interval.tick().await;
+        match run(&config, &db).await {
+            Ok(report) => info!(
+                "Retention purged {} events, anonymized {} rows and deleted {} deliveries",
+                report.events_purged, report.rows_anonymized, report.deliveries_deleted
+            ),
+            Err(err) => error!("Failed to apply retention policy: {}", err),
+        }
+    }
+}
@@ -1,19 +1,21 @@
-use actix_session::storage::CookieSessionStore;
+use actix_session::config::{PersistentSession, TtlExtensionPolicy};
 use actix_session::SessionMiddleware;
-use actix_web::cookie::Key;
-use actix_web::{middleware::Logger, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::middleware::{from_fn, Logger};
+use actix_web::{web, App, HttpResponse, HttpServer, Responder, ResponseError};
 use anyhow::{anyhow, Result};
-use base64::prelude::*;
 use include_dir::{include_dir, Dir};
 use log::info;
-use redis_work_queue::{KeyPrefix, WorkQueue};
-use sqlx::postgres::PgPoolOptions;
-use std::env;
-use std::sync::{Arc, Mutex};
 
-use crate::app::{ApiError, AppState};
-use crate::redis::RedisQueue;
-use crate::{api, auth};
+use crate::api;
+use crate::app::AppState;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::health;
+use crate::metrics;
+use crate::session::{self, PgSessionStore};
+use crate::telemetry;
 
 //mod pings; // Undo this when developing it
 
@@ -27,7 +29,7 @@ async fn serve_file(path: web::Path<String>) -> impl Responder {
         let mime = mime_guess::from_path(&file_path).first_or_octet_stream();
         HttpResponse::Ok().content_type(mime.as_ref()).body(content)
     } else {
-        HttpResponse::NotFound().json(ApiError::from("File not found".to_string()))
+        AppError::NotFound("File not found".to_string()).error_response()
     }
 }
 
@@ -37,72 +39,62 @@ async fn serve_index() -> impl Responder {
         let mime = mime_guess::from_path("index.html").first_or_octet_stream();
         HttpResponse::Ok().content_type(mime.as_ref()).body(content)
     } else {
-        HttpResponse::NotFound().json(ApiError::from("File not found".to_string()))
+        AppError::NotFound("File not found".to_string()).error_response()
     }
 }
 
-pub async fn main() -> Result<()> {
-    let host = env::var("HOST").unwrap_or("127.0.0.1".to_string());
-    let host_inner = host.clone();
-    let port: i32 = match &env::var("PORT").map(|port| port.parse()) {
-        Ok(Ok(p)) => *p,
-        Ok(Err(_)) => 8080,
-        Err(_) => 8080,
-    };
-
-    let db_pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&env::var("DATABASE_URL").expect("DATABASE_URL must be set"))
-        .await
-        .expect("Failed to create pool");
+/// The whole service, API and frontend, around the given state.
+pub fn build_app(
+    state: AppState,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let store = PgSessionStore::new(state.db.clone());
+    let session = state.session.clone();
+    let docs = state.features.api_docs;
+    let probes = state.probes.clone();
+    App::new()
+        .app_data(web::Data::new(state))
+        .app_data(web::Data::new(probes))
+        .wrap(
+            SessionMiddleware::builder(store, session.key)
+                .cookie_secure(session.secure)
+                .session_lifecycle(
+                    PersistentSession::default()
+                        .session_ttl(session.ttl)
+                        .session_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest),
+                )
+                .build(),
+        )
+        .wrap(Logger::default())
+        .wrap(from_fn(metrics::record_request))
+        .wrap(from_fn(telemetry::trace_request))
+        .configure(health::configure)
+        .service(api::scope(docs))
+        .route("/", web::get().to(serve_index))
+        .route("/history", web::get().to(serve_index))
+        .route("/login", web::get().to(serve_index))
+        .route("/{filename:.*}", web::get().to(serve_file))
+}
 
-    let session_key = env::var("SESSION_KEY")
-        .map_err(|e| anyhow!("Failed to get Env Var: {}", e))
-        .and_then(|key64| {
-            BASE64_STANDARD
-                .decode(key64)
-                .map_err(|e| anyhow!("Failed to decode session key: {}", e))
-        })
-        .map(|key| Key::from(&key))
-        .unwrap_or(Key::generate());
+pub async fn main(config: Config) -> Result<()> {
+    let state = AppState::connect(&config).await?;
 
-    let redis_conn = redis::Client::open(env::var("REDIS_URL").expect("REDIS_URL must be set"))
-        .expect("Failed to create Redis Client")
-        .get_multiplexed_async_connection()
-        .await
-        .expect("Failed to create Redis Connection");
+    let cleanup = tokio::spawn(session::delete_expired_periodically(state.db.clone()));
 
+    let (host, port) = (config.server.host, config.server.port);
     info!("Starting server at http://{host}:{port}");
-    HttpServer::new(move || {
-        let (google_client, csh_client) = auth::get_clients(&host_inner, port);
-
-        App::new()
-            .app_data(web::Data::new(AppState {
-                db: db_pool.clone(),
-                redis: Arc::new(Mutex::new(RedisQueue {
-                    redis: redis_conn.clone(),
-                    work_queue: WorkQueue::new(KeyPrefix::from("rideboard")),
-                })),
-                google_oauth: google_client,
-                google_userinfo_url: "https://openidconnect.googleapis.com/v1/userinfo".to_string(),
-                csh_oauth: csh_client,
-                csh_userinfo_url: env::var("CSH_USERINFO_URL")
-                    .expect("Missing Userinfo URL for CSH Auth"),
-            }))
-            .wrap(
-                SessionMiddleware::builder(CookieSessionStore::default(), session_key.clone())
-                    .cookie_secure(env::var("DEVELOPMENT").is_err())
-                    .build(),
-            )
-            .wrap(Logger::default())
-            .service(api::scope())
-            .route("/", web::get().to(serve_index))
-            .route("/history", web::get().to(serve_index))
-            .route("/login", web::get().to(serve_index))
-            .route("/{filename:.*}", web::get().to(serve_file))
-    })
-    .bind(format!("{host}:{port}"))?
-    .run()
-    .await
-    .map_err(|err| anyhow!("Failed to run server: {}", err))
+    let result = HttpServer::new(move || build_app(state.clone()))
+        .bind((host.as_str(), port))?
+        .run()
+        .await
+        .map_err(|err| anyhow!("Failed to run server: {}", err));
+    cleanup.abort();
+    result
 }
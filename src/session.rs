@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::time::Duration as StdDuration;
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::anyhow;
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{error, info};
+use rand::distributions::{Alphanumeric, DistString};
+use sqlx::PgPool;
+
+use crate::api::v1::auth::models::UserInfo;
+use crate::auth::hash_secret;
+use crate::db::session::SessionInfo;
+
+type SessionState = HashMap<String, String>;
+
+/// Expired sessions are never loaded, so deleting them only keeps the table small.
+const CLEANUP_INTERVAL: StdDuration = StdDuration::from_secs(60 * 60);
+
+/// Keeps sessions in Postgres so they survive restarts and can be listed and revoked. Only a
+/// hash of the session key is stored.
+#[derive(Clone)]
+pub struct PgSessionStore {
+    db: PgPool,
+}
+
+impl PgSessionStore {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+fn expires_at(ttl: &Duration) -> DateTime<Utc> {
+    Utc::now() + TimeDelta::seconds(ttl.whole_seconds())
+}
+
+/// Pull out the values that sessions are looked up by.
+fn owner(state: &SessionState) -> (Option<String>, Option<String>) {
+    let user_id = state
+        .get("userinfo")
+        .and_then(|info| serde_json::from_str::<UserInfo>(info).ok())
+        .map(|info| info.id);
+    let session_id = state
+        .get("session_id")
+        .and_then(|id| serde_json::from_str::<String>(id).ok());
+    (user_id, session_id)
+}
+
+impl SessionStore for PgSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let state = SessionInfo::select_state(&hash_secret(session_key.as_ref()), &self.db)
+            .await
+            .map_err(LoadError::Other)?;
+        state
+            .map(|state| serde_json::from_str(&state))
+            .transpose()
+            .map_err(|err| LoadError::Deserialization(anyhow!(err)))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|err| SaveError::Serialization(anyhow!(err)))?;
+        let key = Alphanumeric.sample_string(&mut rand::thread_rng(), 64);
+        let (user_id, session_id) = owner(&session_state);
+
+        SessionInfo::insert_new(
+            &hash_secret(&key),
+            user_id,
+            session_id,
+            &state,
+            expires_at(ttl),
+            &self.db,
+        )
+        .await
+        .map_err(SaveError::Other)?;
+
+        SessionKey::try_from(key).map_err(|err| SaveError::Other(anyhow!(err)))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|err| UpdateError::Serialization(anyhow!(err)))?;
+        let (user_id, session_id) = owner(&session_state);
+
+        let updated = SessionInfo::update(
+            &hash_secret(session_key.as_ref()),
+            user_id,
+            session_id,
+            &state,
+            expires_at(ttl),
+            &self.db,
+        )
+        .await
+        .map_err(UpdateError::Other)?;
+        if updated {
+            return Ok(session_key);
+        }
+
+        // The session expired or was revoked while the request was running. Start over with an
+        // empty session rather than bringing the login back.
+        self.save(SessionState::new(), ttl)
+            .await
+            .map_err(|err| match err {
+                SaveError::Serialization(err) => UpdateError::Serialization(err),
+                SaveError::Other(err) => UpdateError::Other(err),
+            })
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> anyhow::Result<()> {
+        SessionInfo::update_expiry(
+            &hash_secret(session_key.as_ref()),
+            expires_at(ttl),
+            &self.db,
+        )
+        .await
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+        SessionInfo::delete(&hash_secret(session_key.as_ref()), &self.db).await
+    }
+}
+
+/// Delete expired sessions now and every hour after, until the task is dropped.
+pub async fn delete_expired_periodically(db: PgPool) {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match SessionInfo::delete_expired(&db).await {
+            Ok(count) => info!("Deleted {} expired sessions", count),
+            Err(err) => error!("{}", err),
+        }
+    }
+}
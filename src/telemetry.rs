@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::HeaderMap;
+use actix_web::middleware::Next;
+use anyhow::{anyhow, Result};
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracerProvider, SpanExporter};
+use opentelemetry_sdk::Resource;
+use tracing::{field, info_span, Instrument, Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::TelemetryConfig;
+
+/// Keeps the tracer running. Dropping it flushes any spans that haven't been exported yet, so
+/// hold on to it until the process exits.
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("Failed to flush traces: {}", err);
+            }
+        }
+    }
+}
+
+/// Set up logging, and export spans over OTLP if an endpoint is configured. `component` tells
+/// the server's spans from the worker's.
+pub fn init(config: &TelemetryConfig, component: &str) -> Result<Telemetry> {
+    let provider = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                .build()
+                .map_err(|err| anyhow!("Failed to create OTLP exporter: {}", err))?;
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(resource(&config.service_name, component))
+                    .build(),
+            )
+        }
+        None => None,
+    };
+    install(provider)
+}
+
+/// Set up logging and hand every span to `exporter` as soon as it ends. Tests use this with an
+/// in-memory exporter to look at the spans.
+pub fn init_with_exporter<E: SpanExporter + 'static>(
+    exporter: E,
+    component: &str,
+) -> Result<Telemetry> {
+    install(Some(
+        SdkTracerProvider::builder()
+            .with_simple_exporter(exporter)
+            .with_resource(resource("rideboard", component))
+            .build(),
+    ))
+}
+
+fn resource(service_name: &str, component: &str) -> Resource {
+    Resource::builder()
+        .with_service_name(service_name.to_string())
+        .with_attribute(KeyValue::new("rideboard.component", component.to_string()))
+        .build()
+}
+
+fn install(provider: Option<SdkTracerProvider>) -> Result<Telemetry> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    // Logs are filtered by RUST_LOG as before. Spans from this crate are always traced, so a
+    // quiet log doesn't leave gaps in the traces.
+    let log_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error"));
+    let otel = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("rideboard"))
+            .with_filter(Targets::new().with_target("rideboard_v2", Level::INFO))
+    });
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(log_filter))
+        .with(otel)
+        .try_init()
+        .map_err(|err| anyhow!("Failed to set up logging: {}", err))?;
+    Ok(Telemetry { provider })
+}
+
+/// The current trace context, to send along with a job so the worker's spans join the trace.
+pub fn current_context() -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    carrier
+}
+
+/// The trace context sent by `current_context`.
+pub fn context_from(carrier: &HashMap<String, String>) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(carrier))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Middleware wrapping each request in a span, continuing the caller's trace if it sent a
+/// `traceparent` header.
+pub async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    let span = info_span!(
+        "HTTP request",
+        http.request.method = %req.method(),
+        url.path = %req.path(),
+        http.route = field::Empty,
+        http.response.status_code = field::Empty,
+    );
+    let _ = span.set_parent(parent);
+    let res = next.call(req).instrument(span.clone()).await?;
+    if let Some(route) = res.request().match_pattern() {
+        span.record("http.route", route);
+    }
+    span.record("http.response.status_code", res.status().as_u16());
+    Ok(res)
+}
@@ -0,0 +1,74 @@
+use chrono::TimeDelta;
+use serde::{Deserialize, Deserializer};
+
+use crate::error::FieldError;
+
+/// Limits applied to events, cars and ride requests on top of the checks that always hold (e.g. an event
+/// can't end before it starts).
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationRules {
+    pub max_name_length: usize,
+    pub max_location_length: usize,
+    pub max_comment_length: usize,
+    pub max_note_length: usize,
+    pub max_capacity: i32,
+    #[serde(rename = "max_event_duration_hours", deserialize_with = "hours")]
+    pub max_event_duration: TimeDelta,
+}
+
+impl Default for ValidationRules {
+    fn default() -> Self {
+        ValidationRules {
+            max_name_length: 100,
+            max_location_length: 200,
+            max_comment_length: 500,
+            max_note_length: 500,
+            max_capacity: 20,
+            max_event_duration: TimeDelta::days(14),
+        }
+    }
+}
+
+fn hours<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TimeDelta, D::Error> {
+    i64::deserialize(deserializer).map(TimeDelta::hours)
+}
+
+/// Collects the problems with a request body so they can all be reported at once.
+#[derive(Default)]
+pub struct Validator {
+    errs: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn add(&mut self, err: FieldError) {
+        self.errs.push(err);
+    }
+
+    pub fn required(&mut self, field: &str, value: &str, message: &str) {
+        if value.trim().is_empty() {
+            self.add(FieldError::new(field, "required", message));
+        }
+    }
+
+    pub fn max_length(&mut self, field: &str, label: &str, value: &str, max: usize) {
+        if value.chars().count() > max {
+            self.add(
+                FieldError::new(
+                    field,
+                    "too_long",
+                    format!("{} cannot be longer than {} characters.", label, max),
+                )
+                .with_param("max", max),
+            );
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Vec<FieldError>> {
+        if self.errs.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errs)
+        }
+    }
+}
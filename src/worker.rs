@@ -1,141 +1,291 @@
-use std::{collections::HashSet, env};
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
 
+use actix_web::{web, App, HttpServer};
 use anyhow::{anyhow, Result};
-use log::error;
-use redis::{aio::MultiplexedConnection, RedisResult};
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
+use log::{error, info};
+use redis::{aio::MultiplexedConnection, AsyncCommands, RedisResult, Script};
 use redis_work_queue::{Item, KeyPrefix, WorkQueue};
 use sqlx::{postgres::PgPoolOptions, query, query_as, Pool, Postgres};
 use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tracing::{info_span, instrument, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 use crate::{
-    app::{RedisJob, SimpleRiderChange},
-    db::user::UserData,
+    app::{QueuedJob, RedisJob, SimpleRiderChange},
+    config::{Config, WorkerConfig},
+    db::{delivery::Delivery, user::UserData},
+    health::{self, Probes},
+    metrics,
+    notifications::{NotificationKind, NotificationTemplates, Placeholders},
     pings::PingClient,
+    retention, telemetry,
 };
 
-struct RedisError {
+/// How long leasing waits for a job before checking for a shutdown.
+const LEASE_POLL: Duration = Duration::from_secs(1);
+
+pub struct RedisError {
     pub msg: String,
     pub should_retry: bool,
 }
 
-pub async fn main() -> Result<()> {
-    let db = redis::Client::open(env::var("REDIS_URL").expect("REDIS_URL must be set"))?
-        .get_multiplexed_async_connection()
-        .await?;
-
-    let work_queue = WorkQueue::new(KeyPrefix::from("rideboard"));
+pub async fn main(config: Config) -> Result<()> {
+    let client = redis::Client::open(config.redis.url.as_str())?;
+    let probe = client.get_multiplexed_async_connection().await?;
 
     let db_pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&env::var("DATABASE_URL").expect("DATABASE_URL must be set"))
+        .max_connections(config.database.max_connections)
+        .min_connections(config.database.min_connections)
+        .connect(&config.database.url)
         .await?;
 
+    let routes = config.pings.routes;
     let pings = PingClient::new(
-        env::var("PINGS_TOKEN").expect("PINGS_TOKEN must be set"),
-        env::var("PINGS_JOIN_ROUTE").expect("PINGS_JOIN_ROUTE must be set"),
-        env::var("PINGS_LEAVE_ROUTE").expect("PINGS_LEAVE_ROUTE must be set"),
-        env::var("PINGS_ADD_ROUTE").expect("PINGS_ADD_ROUTE must be set"),
-        env::var("PINGS_REMOVE_ROUTE").expect("PINGS_REMOVE_ROUTE must be set"),
+        config.pings.base_url,
+        config.pings.token,
+        routes.join,
+        routes.leave,
+        routes.add,
+        routes.remove,
+        routes.transfer,
     )?;
 
-    work_loop(db, work_queue, db_pool, pings).await?;
-    Ok(())
+    let key_prefix = config.redis.key_prefix.as_str();
+    let work_queue = WorkQueue::new(KeyPrefix::from(key_prefix));
+    let probes = Probes::new(db_pool.clone(), Some((probe, work_queue)));
+    let (host, port) = (config.worker.host.as_str(), config.worker.port);
+    info!("Serving health checks at http://{host}:{port}");
+    // The listener is stopped once running jobs are done, rather than on the signal.
+    let listener = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(probes.clone()))
+            .configure(health::configure)
+    })
+    .workers(1)
+    .disable_signals()
+    .bind((host, port))?
+    .run();
+    let listener_handle = listener.handle();
+    let listener = tokio::spawn(listener);
+    // Stopping it partway through rolls back the run, which the next worker to start repeats.
+    let retention = tokio::spawn(retention::run_periodically(
+        config.retention,
+        db_pool.clone(),
+    ));
+
+    let result = work_loop(
+        &client,
+        key_prefix,
+        &config.worker,
+        db_pool,
+        pings,
+        config.notifications,
+        shutdown_signal(),
+    )
+    .await;
+    retention.abort();
+    listener_handle.stop(true).await;
+    listener
+        .await?
+        .map_err(|err| anyhow!("Failed to run health listener: {}", err))?;
+    Ok(result?)
 }
 
-async fn get_event_name(event_id: i32, db_pool: &Pool<Postgres>) -> Result<String> {
-    match query!(r#"SELECT name FROM event WHERE id = $1"#, event_id)
-        .fetch_one(db_pool)
-        .await
-    {
-        Ok(event) => Ok(event.name),
-        Err(err) => Err(anyhow!("Failed to get event name: {}", err)),
-    }
+/// What the templates say about an event.
+struct EventInfo {
+    name: String,
+    location: String,
 }
 
-async fn get_driver(car_id: i32, db_pool: &Pool<Postgres>) -> Result<UserData> {
+#[instrument(name = "worker::get_event", skip_all)]
+async fn get_event(event_id: i32, db_pool: &Pool<Postgres>) -> Result<EventInfo> {
+    let _timer = metrics::time_query("worker::get_event");
     query_as!(
-        UserData,
+        EventInfo,
+        r#"SELECT name, location FROM event WHERE id = $1"#,
+        event_id
+    )
+    .fetch_one(db_pool)
+    .await
+    .map_err(|err| anyhow!("Failed to get event: {}", err))
+}
+
+/// The car's driver and when it leaves.
+#[instrument(name = "worker::get_car", skip_all)]
+async fn get_car(car_id: i32, db_pool: &Pool<Postgres>) -> Result<(UserData, DateTime<Utc>)> {
+    let _timer = metrics::time_query("worker::get_car");
+    let car = query!(
         r#"
-        SELECT users.id AS "id!", users.realm::text AS "realm!", users.name AS "name!", users.email AS "email!"
+        SELECT users.id AS "id!", users.realm::text AS "realm!", users.name AS "name!", users.email AS "email!",
+        car.departure_time
         FROM car JOIN users ON car.driver = users.id WHERE car.id = $1;
         "#, car_id
-    ).fetch_one(db_pool).await.map_err(|err| anyhow!("Failed to get driver: {}", err))
+    ).fetch_one(db_pool).await.map_err(|err| anyhow!("Failed to get driver: {}", err))?;
+    Ok((
+        UserData {
+            id: car.id,
+            realm: car.realm,
+            name: car.name,
+            email: car.email,
+        },
+        car.departure_time,
+    ))
 }
 
 async fn get_simple_data(
     data: SimpleRiderChange,
     db_pool: &Pool<Postgres>,
-) -> Result<(String, UserData, UserData)> {
+) -> Result<(EventInfo, (UserData, DateTime<Utc>), UserData)> {
     let rider = UserData::select_one(data.rider_id, db_pool)
         .await?
         .ok_or(anyhow!("Rider does not exist"))?;
     Ok((
-        get_event_name(data.event_id, db_pool).await?,
-        get_driver(data.car_id, db_pool).await?,
+        get_event(data.event_id, db_pool).await?,
+        get_car(data.car_id, db_pool).await?,
         rider,
     ))
 }
 
-async fn work(job: &Item, db_pool: &Pool<Postgres>, pings: &PingClient) -> Result<(), RedisError> {
-    let job_data: RedisJob = job.data_json().map_err(|_err| RedisError {
-        msg: "Failed to Parse into Job".to_string(),
-        should_retry: false,
+/// Send the notifications for one job.
+pub async fn work(
+    job: &Item,
+    db_pool: &Pool<Postgres>,
+    pings: &PingClient,
+    templates: &NotificationTemplates,
+) -> Result<(), RedisError> {
+    let queued: QueuedJob = job.data_json().map_err(|_err| {
+        metrics::job_finished("Unknown", false);
+        RedisError {
+            msg: "Failed to Parse into Job".to_string(),
+            should_retry: false,
+        }
     })?;
+    let kind = queued.job.kind();
+    // Continue the trace of the request that queued the job.
+    let span = info_span!("worker::work", job = kind, job_id = %job.id);
+    let _ = span.set_parent(telemetry::context_from(&queued.trace_context));
+    let notifier = Notifier {
+        job_id: &job.id,
+        db_pool,
+        pings,
+        templates,
+    };
+    let result = run(queued.job, db_pool, &notifier).instrument(span).await;
+    metrics::job_finished(kind, result.is_ok());
+    result
+}
+
+/// Sends a job's notifications, each recipient at most once.
+struct Notifier<'a> {
+    job_id: &'a str,
+    db_pool: &'a Pool<Postgres>,
+    pings: &'a PingClient,
+    templates: &'a NotificationTemplates,
+}
+
+impl Notifier<'_> {
+    /// Render the `kind` template in `to`'s locale and send it, unless this job already has,
+    /// so a retried job doesn't notify again the users it reached before failing. Pings is the
+    /// only channel, and it only reaches CSH members.
+    async fn notify(
+        &self,
+        kind: NotificationKind,
+        to: &UserData,
+        values: &Placeholders<'_>,
+    ) -> Result<(), RedisError> {
+        if to.realm != "csh" {
+            return Ok(());
+        }
+        let retry = |err: anyhow::Error| RedisError {
+            msg: err.to_string(),
+            should_retry: true,
+        };
+        if Delivery::exists(self.job_id, &to.id, self.db_pool)
+            .await
+            .map_err(retry)?
+        {
+            return Ok(());
+        }
+        let locale = UserData::select_locale(&to.id, self.db_pool)
+            .await
+            .map_err(retry)?;
+        let body = self.templates.render(kind, locale.as_deref(), values);
+        self.pings
+            .send(kind, to.email.trim_end_matches("@csh.rit.edu"), &body)
+            .await
+            .map_err(|err| RedisError {
+                msg: format!("Failed to send message: {}", err),
+                should_retry: true,
+            })?;
+        // The ping is out, so carry on either way. Failing here would only send it again.
+        if let Err(err) = Delivery::insert(self.job_id, &to.id, self.db_pool).await {
+            error!("{}", err);
+        }
+        Ok(())
+    }
+}
+
+async fn run(
+    job_data: RedisJob,
+    db_pool: &Pool<Postgres>,
+    notifier: &Notifier<'_>,
+) -> Result<(), RedisError> {
     match job_data {
         RedisJob::Join(data) => {
-            let (event_name, driver, rider) =
+            let (event, (driver, time), rider) =
                 get_simple_data(data, db_pool)
                     .await
                     .map_err(|err| RedisError {
                         msg: err.to_string(),
                         should_retry: false,
                     })?;
-            if driver.realm != "csh" {
-                return Ok(());
-            }
-            pings
-                .send_join(
-                    driver.email.trim_end_matches("@csh.rit.edu"),
-                    &rider.name,
-                    &event_name,
-                )
-                .await
-                .map_err(|err| RedisError {
-                    msg: err.to_string(),
-                    should_retry: true,
-                })?
+            let values = Placeholders {
+                rider: &rider.name,
+                driver: &driver.name,
+                old_driver: "",
+                event: &event.name,
+                location: &event.location,
+                time,
+            };
+            notifier
+                .notify(NotificationKind::Join, &driver, &values)
+                .await?;
         }
         RedisJob::Leave(data) => {
-            let (event_name, driver, rider) =
+            let (event, (driver, time), rider) =
                 get_simple_data(data, db_pool)
                     .await
                     .map_err(|err| RedisError {
                         msg: err.to_string(),
                         should_retry: false,
                     })?;
-            if driver.realm != "csh" {
-                return Ok(());
-            }
-            pings
-                .send_leave(
-                    driver.email.trim_end_matches("@csh.rit.edu"),
-                    &rider.name,
-                    &event_name,
-                )
-                .await
-                .map_err(|err| RedisError {
-                    msg: err.to_string(),
-                    should_retry: true,
-                })?;
+            let values = Placeholders {
+                rider: &rider.name,
+                driver: &driver.name,
+                old_driver: "",
+                event: &event.name,
+                location: &event.location,
+                time,
+            };
+            notifier
+                .notify(NotificationKind::Leave, &driver, &values)
+                .await?;
         }
         RedisJob::RiderUpdate(data) => {
-            let event_name = get_event_name(data.event_id, db_pool)
+            let event = get_event(data.event_id, db_pool)
                 .await
                 .map_err(|err| RedisError {
                     msg: err.to_string(),
                     should_retry: true,
                 })?;
-            let driver = get_driver(data.car_id, db_pool)
+            let (driver, time) = get_car(data.car_id, db_pool)
                 .await
                 .map_err(|err| RedisError {
                     msg: err.to_string(),
@@ -156,83 +306,387 @@ async fn work(job: &Item, db_pool: &Pool<Postgres>, pings: &PingClient) -> Resul
                 msg: err.to_string(),
                 should_retry: true,
             })?;
-            for removed in old_set.difference(&new_set) {
-                let user = user_map.get(removed).ok_or(RedisError {
+            let changes = old_set
+                .difference(&new_set)
+                .map(|id| (NotificationKind::Remove, id))
+                .chain(
+                    new_set
+                        .difference(&old_set)
+                        .map(|id| (NotificationKind::Add, id)),
+                );
+            for (kind, id) in changes {
+                let user = user_map.get(id).ok_or(RedisError {
                     msg: "User was missing from map.".to_string(),
                     should_retry: false,
                 })?;
-                if user.realm != "csh" {
-                    continue;
-                }
-                pings
-                    .send_remove(
-                        user.email.trim_end_matches("@csh.rit.edu"),
-                        &driver.name,
-                        &event_name,
-                    )
-                    .await
-                    .map_err(|err| RedisError {
-                        msg: format!("Failed to send message: {}", err),
-                        should_retry: true,
-                    })?;
+                let values = Placeholders {
+                    rider: &user.name,
+                    driver: &driver.name,
+                    old_driver: "",
+                    event: &event.name,
+                    location: &event.location,
+                    time,
+                };
+                notifier.notify(kind, user, &values).await?;
             }
-            for added in new_set.difference(&old_set) {
-                let user = user_map.get(added).ok_or(RedisError {
+        }
+        RedisJob::TransferOffer(data) => {
+            let (event, (driver, time), new_driver) = get_simple_data(data, db_pool)
+                .await
+                .map_err(|err| RedisError {
+                    msg: err.to_string(),
+                    should_retry: false,
+                })?;
+            let values = Placeholders {
+                rider: "",
+                driver: &driver.name,
+                old_driver: "",
+                event: &event.name,
+                location: &event.location,
+                time,
+            };
+            notifier
+                .notify(NotificationKind::TransferOffer, &new_driver, &values)
+                .await?;
+        }
+        RedisJob::DriverChange(data) => {
+            let event = get_event(data.event_id, db_pool)
+                .await
+                .map_err(|err| RedisError {
+                    msg: err.to_string(),
+                    should_retry: true,
+                })?;
+            let (driver, time) = get_car(data.car_id, db_pool)
+                .await
+                .map_err(|err| RedisError {
+                    msg: err.to_string(),
+                    should_retry: true,
+                })?;
+            let old_driver = UserData::select_one(data.old_driver, db_pool)
+                .await
+                .map_err(|err| RedisError {
+                    msg: err.to_string(),
+                    should_retry: true,
+                })?
+                .ok_or(RedisError {
+                    msg: "Old driver does not exist".to_string(),
+                    should_retry: false,
+                })?;
+            let user_map = UserData::select_map(data.riders.clone(), db_pool)
+                .await
+                .map_err(|err| RedisError {
+                    msg: err.to_string(),
+                    should_retry: true,
+                })?;
+            for rider in data.riders.iter() {
+                let user = user_map.get(rider).ok_or(RedisError {
                     msg: "User was missing from map.".to_string(),
                     should_retry: false,
                 })?;
-                if user.realm != "csh" {
-                    continue;
-                }
-                pings
-                    .send_add(
-                        user.email.trim_end_matches("@csh.rit.edu"),
-                        &driver.name,
-                        &event_name,
-                    )
-                    .await
-                    .map_err(|err| RedisError {
-                        msg: format!("Failed to send message: {}", err),
-                        should_retry: true,
-                    })?;
+                let values = Placeholders {
+                    rider: &user.name,
+                    driver: &driver.name,
+                    old_driver: &old_driver.name,
+                    event: &event.name,
+                    location: &event.location,
+                    time,
+                };
+                notifier
+                    .notify(NotificationKind::DriverChange, user, &values)
+                    .await?;
+            }
+        }
+        RedisJob::RiderDeleted(data) => {
+            let drivers = UserData::select_map(data.ride.recipients.clone(), db_pool)
+                .await
+                .map_err(|err| RedisError {
+                    msg: err.to_string(),
+                    should_retry: true,
+                })?;
+            // A driver who has since deleted their account too is skipped.
+            for driver in data.ride.recipients.iter().filter_map(|id| drivers.get(id)) {
+                let values = Placeholders {
+                    rider: &data.name,
+                    driver: &driver.name,
+                    old_driver: "",
+                    event: &data.ride.event_name,
+                    location: &data.ride.location,
+                    time: data.ride.departure_time,
+                };
+                notifier
+                    .notify(NotificationKind::Leave, driver, &values)
+                    .await?;
+            }
+        }
+        RedisJob::DriverDeleted(data) => {
+            let riders = UserData::select_map(data.ride.recipients.clone(), db_pool)
+                .await
+                .map_err(|err| RedisError {
+                    msg: err.to_string(),
+                    should_retry: true,
+                })?;
+            for rider in data.ride.recipients.iter().filter_map(|id| riders.get(id)) {
+                let values = Placeholders {
+                    rider: &rider.name,
+                    driver: &data.name,
+                    old_driver: "",
+                    event: &data.ride.event_name,
+                    location: &data.ride.location,
+                    time: data.ride.departure_time,
+                };
+                notifier
+                    .notify(NotificationKind::Cancel, rider, &values)
+                    .await?;
+            }
+        }
+        RedisJob::CreatorDeleted(data) => {
+            let riders = UserData::select_map(data.ride.recipients.clone(), db_pool)
+                .await
+                .map_err(|err| RedisError {
+                    msg: err.to_string(),
+                    should_retry: true,
+                })?;
+            for rider in data.ride.recipients.iter().filter_map(|id| riders.get(id)) {
+                let values = Placeholders {
+                    rider: &rider.name,
+                    driver: "",
+                    old_driver: "",
+                    event: &data.ride.event_name,
+                    location: &data.ride.location,
+                    time: data.ride.departure_time,
+                };
+                notifier
+                    .notify(NotificationKind::EventCancel, rider, &values)
+                    .await?;
             }
         }
     }
     Ok(())
 }
 
-pub async fn work_loop(
-    mut db: MultiplexedConnection,
-    work_queue: WorkQueue,
-    db_pool: Pool<Postgres>,
-    pings: PingClient,
-) -> RedisResult<()> {
-    loop {
-        // Wait for a job with no timeout and a lease time of 5 seconds.
-        let job: Item = match work_queue
-            .lease(&mut db, None, Duration::from_secs(5))
-            .await?
-        {
-            Some(job) => job,
-            None => {
-                error!("Failed to get job.");
+/// The lease bookkeeping redis-work-queue 0.3 leaves out: renewing a job's lease while it runs,
+/// and returning jobs whose lease ran out to the queue. This is the only code that relies on the
+/// keys `WorkQueue` uses.
+#[derive(Clone)]
+struct Leases {
+    queue_key: String,
+    processing_key: String,
+    lease_key: KeyPrefix,
+    duration: Duration,
+    requeue: Script,
+}
+
+impl Leases {
+    fn new(name: KeyPrefix, duration: Duration) -> Self {
+        Leases {
+            queue_key: name.of(":queue"),
+            processing_key: name.of(":processing"),
+            lease_key: name.and(":lease:"),
+            duration,
+            // Checked and moved in one step, so a job another worker just renewed or requeued is
+            // left alone.
+            requeue: Script::new(
+                r#"
+                if redis.call('EXISTS', KEYS[3]) == 0 and redis.call('LREM', KEYS[1], 0, ARGV[1]) > 0 then
+                    redis.call('LPUSH', KEYS[2], ARGV[1])
+                    return 1
+                end
+                return 0
+                "#,
+            ),
+        }
+    }
+
+    /// Keep pushing back the lease's expiry while the job runs, so a long job isn't handed to
+    /// another worker. Only returns if the lease is gone, since the job may be running elsewhere.
+    async fn renew(&self, mut db: MultiplexedConnection, job_id: &str) -> RedisError {
+        let key = self.lease_key.of(job_id);
+        loop {
+            tokio::time::sleep(self.duration / 3).await;
+            match db.expire(&key, self.duration.as_secs() as i64).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return RedisError {
+                        msg: format!("Lost the lease on job {}", job_id),
+                        should_retry: true,
+                    }
+                }
+                Err(err) => error!("Failed to renew lease on job {}: {}", job_id, err),
+            }
+        }
+    }
+
+    /// Return the jobs being processed without a lease to the queue, and the number returned.
+    /// `WorkQueue::lease` takes a job before setting its lease, so a job is only returned once
+    /// it was also missing one on the previous pass, which `suspects` keeps track of.
+    async fn requeue_expired(
+        &self,
+        db: &mut MultiplexedConnection,
+        suspects: &mut HashSet<String>,
+    ) -> RedisResult<usize> {
+        let processing: Vec<String> = db.lrange(&self.processing_key, 0, -1).await?;
+        let mut leaseless = HashSet::new();
+        let mut requeued = 0;
+        for job_id in processing {
+            let leased: bool = db.exists(self.lease_key.of(&job_id)).await?;
+            if leased {
+                continue;
+            }
+            if !suspects.contains(&job_id) {
+                leaseless.insert(job_id);
                 continue;
             }
+            let moved: bool = self
+                .requeue
+                .key(&self.processing_key)
+                .key(&self.queue_key)
+                .key(self.lease_key.of(&job_id))
+                .arg(&job_id)
+                .invoke_async(db)
+                .await?;
+            if moved {
+                requeued += 1;
+            }
+        }
+        *suspects = leaseless;
+        Ok(requeued)
+    }
+
+    /// Run `requeue_expired` once per lease duration, forever.
+    async fn requeue_expired_periodically(self, mut db: MultiplexedConnection) {
+        let mut suspects = HashSet::new();
+        let mut interval = tokio::time::interval(self.duration);
+        loop {
+            interval.tick().await;
+            match self.requeue_expired(&mut db, &mut suspects).await {
+                Ok(0) => {}
+                Ok(count) => info!("Returned {} jobs with expired leases to the queue", count),
+                Err(err) => error!("Failed to return expired jobs to the queue: {}", err),
+            }
+        }
+    }
+}
+
+/// What a running job needs, shared by every job task.
+#[derive(Clone)]
+struct JobContext {
+    db: MultiplexedConnection,
+    work_queue: Arc<WorkQueue>,
+    leases: Leases,
+    db_pool: Pool<Postgres>,
+    pings: Arc<PingClient>,
+    templates: Arc<NotificationTemplates>,
+}
+
+impl JobContext {
+    async fn run(mut self, job: Item, _slot: OwnedSemaphorePermit) {
+        let result = tokio::select! {
+            result = work(&job, &self.db_pool, &self.pings, &self.templates) => result,
+            err = self.leases.renew(self.db.clone(), &job.id) => Err(err),
         };
-        match work(&job, &db_pool, &pings).await {
+        let complete = match result {
             // Mark successful jobs as complete
-            Ok(()) => {
-                work_queue.complete(&mut db, &job).await?;
-            }
+            Ok(()) => true,
             // Drop a job that should be retried - it will be returned to the work queue after
-            // the (5 second) lease expires.
-            Err(err) if err.should_retry => error!("{}", err.msg),
+            // its lease expires.
+            Err(err) if err.should_retry => {
+                error!("{}", err.msg);
+                false
+            }
             // Errors that shouldn't cause a retry should mark the job as complete so it isn't
             // tried again.
             Err(err) => {
                 error!("{}", err.msg);
-                work_queue.complete(&mut db, &job).await?;
+                true
+            }
+        };
+        if complete {
+            if let Err(err) = self.work_queue.complete(&mut self.db, &job).await {
+                error!("Failed to complete job {}: {}", job.id, err);
             }
         }
     }
 }
+
+/// Lease jobs and run up to `config.concurrency` of them at once until `shutdown` finishes,
+/// then wait for the running jobs before returning.
+pub async fn work_loop(
+    client: &redis::Client,
+    key_prefix: &str,
+    config: &WorkerConfig,
+    db_pool: Pool<Postgres>,
+    pings: PingClient,
+    templates: NotificationTemplates,
+    shutdown: impl Future<Output = ()>,
+) -> RedisResult<()> {
+    // Leasing blocks its connection while it waits, so running jobs get their own.
+    let mut leaser = client.get_multiplexed_async_connection().await?;
+    let leases = Leases::new(
+        KeyPrefix::from(key_prefix),
+        Duration::from_secs(config.lease_seconds),
+    );
+    let context = JobContext {
+        db: client.get_multiplexed_async_connection().await?,
+        work_queue: Arc::new(WorkQueue::new(KeyPrefix::from(key_prefix))),
+        leases: leases.clone(),
+        db_pool,
+        pings: Arc::new(pings),
+        templates: Arc::new(templates),
+    };
+    let cleaner = tokio::spawn(leases.requeue_expired_periodically(context.db.clone()));
+    let slots = Arc::new(Semaphore::new(config.concurrency));
+    let mut running = JoinSet::new();
+    let mut result = Ok(());
+    tokio::pin!(shutdown);
+
+    'leasing: loop {
+        let slot = tokio::select! {
+            _ = &mut shutdown => break,
+            slot = slots.clone().acquire_owned() => slot.expect("Job slots were closed"),
+        };
+        let job = loop {
+            if (&mut shutdown).now_or_never().is_some() {
+                break 'leasing;
+            }
+            // Only wait a second at a time, so a shutdown is noticed promptly. Cancelling the
+            // lease instead could lose the job.
+            match context
+                .work_queue
+                .lease(&mut leaser, Some(LEASE_POLL), context.leases.duration)
+                .await
+            {
+                Ok(Some(job)) => break job,
+                Ok(None) => {}
+                Err(err) => {
+                    result = Err(err);
+                    break 'leasing;
+                }
+            }
+        };
+        running.spawn(context.clone().run(job, slot));
+        while let Some(finished) = running.try_join_next() {
+            if let Err(err) = finished {
+                error!("Job task failed: {}", err);
+            }
+        }
+    }
+
+    cleaner.abort();
+    info!("Waiting for {} running jobs to finish", running.len());
+    while let Some(finished) = running.join_next().await {
+        if let Err(err) = finished {
+            error!("Job task failed: {}", err);
+        }
+    }
+    result
+}
+
+/// Finishes on SIGTERM or Ctrl-C.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    info!("Shutting down, no more jobs will be leased");
+}
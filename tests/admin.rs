@@ -0,0 +1,213 @@
+mod common;
+
+use rideboard_v2::admin::Admin;
+use rideboard_v2::db::car::Car;
+use rideboard_v2::db::event::Event;
+use rideboard_v2::db::user::UserData;
+use rideboard_v2::redis::RedisQueue;
+
+use common::{car_riders, insert_car, insert_event, insert_user, TestApp};
+
+fn admin(harness: &TestApp, dry_run: bool) -> Admin {
+    Admin {
+        db: harness.state.db.clone(),
+        queue: RedisQueue::Memory(Vec::new()),
+        dry_run,
+    }
+}
+
+/// The cars `user_id` rides in at `event_id`, which should never be more than one.
+async fn places(harness: &TestApp, event_id: i32, user_id: &str) -> Vec<i32> {
+    Car::select_all(event_id, &harness.state.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|car| car.riders.iter().flatten().any(|rider| rider.id == user_id))
+        .map(|car| car.id)
+        .collect()
+}
+
+#[actix_web::test]
+async fn dry_runs_change_nothing() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let alice = insert_user(&harness.state.db, "alice").await;
+    let bob = insert_user(&harness.state.db, "bob").await;
+    let event = insert_event(&harness.state.db, &alice, 1).await;
+    let car = insert_car(&harness.state.db, event, &alice, &[&bob]).await;
+
+    let dry = admin(&harness, true);
+    dry.remove_rider(event, car, &bob).await.unwrap();
+    assert_eq!(
+        car_riders(&harness.state.db, event, car).await,
+        vec![bob.clone()]
+    );
+    assert_eq!(dry.delete_event(event).await.unwrap().id, event);
+    assert!(Event::select_one(event, &harness.state.db)
+        .await
+        .unwrap()
+        .is_some());
+    // Dry runs still fail like the real thing would.
+    assert!(dry.remove_rider(event, car, &alice).await.is_err());
+
+    admin(&harness, false).delete_event(event).await.unwrap();
+    assert!(Event::select_one(event, &harness.state.db)
+        .await
+        .unwrap()
+        .is_none());
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn only_events_that_ended_long_enough_ago_are_purged() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let alice = insert_user(&harness.state.db, "alice").await;
+    let old = insert_event(&harness.state.db, &alice, -40).await;
+    let recent = insert_event(&harness.state.db, &alice, -10).await;
+    let upcoming = insert_event(&harness.state.db, &alice, 1).await;
+
+    assert_eq!(
+        admin(&harness, true).purge_events(30).await.unwrap(),
+        vec![old]
+    );
+    assert_eq!(
+        admin(&harness, false).purge_events(30).await.unwrap(),
+        vec![old]
+    );
+    let left: Vec<i32> = Event::select_all(true, &harness.state.db)
+        .await
+        .unwrap()
+        .into_iter()
+        .chain(Event::select_all(false, &harness.state.db).await.unwrap())
+        .map(|event| event.id)
+        .collect();
+    assert!(!left.contains(&old));
+    assert!(left.contains(&recent));
+    assert!(left.contains(&upcoming));
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn riders_are_moved_only_into_cars_with_room() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let alice = insert_user(&harness.state.db, "alice").await;
+    let bob = insert_user(&harness.state.db, "bob").await;
+    let carol = insert_user(&harness.state.db, "carol").await;
+    let dave = insert_user(&harness.state.db, "dave").await;
+    let event = insert_event(&harness.state.db, &alice, 1).await;
+    let full = insert_car(&harness.state.db, event, &alice, &[&bob]).await;
+    let empty = insert_car(&harness.state.db, event, &carol, &[]).await;
+    let other = insert_car(&harness.state.db, event, &dave, &[]).await;
+
+    let admin = admin(&harness, false);
+    admin.move_rider(event, &bob, full, empty).await.unwrap();
+    assert!(car_riders(&harness.state.db, event, full).await.is_empty());
+    assert_eq!(
+        car_riders(&harness.state.db, event, empty).await,
+        vec![bob.clone()]
+    );
+
+    let erin = insert_user(&harness.state.db, "erin").await;
+    Car::add_rider(other, &erin, &harness.state.db)
+        .await
+        .unwrap();
+    let err = admin
+        .move_rider(event, &erin, other, empty)
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), format!("Car {} is full", empty));
+    assert_eq!(places(&harness, event, &erin).await, vec![other]);
+
+    admin.remove_rider(event, empty, &bob).await.unwrap();
+    assert!(car_riders(&harness.state.db, event, empty).await.is_empty());
+    let err = admin
+        .move_rider(event, &erin, full, empty)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("{} is not a rider in car {}", erin, full)
+    );
+    assert_eq!(places(&harness, event, &erin).await, vec![other]);
+    admin.move_rider(event, &erin, other, empty).await.unwrap();
+    assert_eq!(places(&harness, event, &erin).await, vec![empty]);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn merged_users_keep_their_rides() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let alice = insert_user(&harness.state.db, "alice").await;
+    let bob = insert_user(&harness.state.db, "bob").await;
+    let robert = insert_user(&harness.state.db, "robert").await;
+    let event = insert_event(&harness.state.db, &bob, 1).await;
+    let car = insert_car(&harness.state.db, event, &alice, &[&bob]).await;
+
+    let admin = admin(&harness, false);
+    assert!(admin.merge_users(&bob, &bob).await.is_err());
+    admin.merge_users(&bob, &robert).await.unwrap();
+    assert!(UserData::select_one(bob.clone(), &harness.state.db)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        car_riders(&harness.state.db, event, car).await,
+        vec![robert.clone()]
+    );
+    let event = Event::select_one(event, &harness.state.db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(event.creator.id, robert);
+    // Robert rides with Alice, so once they're merged he drives the car and gives up the seat.
+    admin.merge_users(&alice, &robert).await.unwrap();
+    let car = Car::select_one(event.id, car, &harness.state.db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(car.driver.id, robert);
+    assert!(car.riders.unwrap_or_default().is_empty());
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn riders_can_be_notified_again() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let alice = insert_user(&harness.state.db, "alice").await;
+    let bob = insert_user(&harness.state.db, "bob").await;
+    let event = insert_event(&harness.state.db, &alice, 1).await;
+    let car = insert_car(&harness.state.db, event, &alice, &[&bob]).await;
+
+    let mut dry = admin(&harness, true);
+    assert_eq!(dry.notify_car(event, car).await.unwrap(), 1);
+    assert!(dry.queue.take_jobs().is_empty());
+
+    let mut admin = admin(&harness, false);
+    assert_eq!(admin.notify_car(event, car).await.unwrap(), 1);
+    assert!(harness.retry_jobs(admin.queue.take_jobs()).await.is_empty());
+    let pings = harness.pings.take();
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].username, "bob");
+    assert_eq!(
+        pings[0].body,
+        "You have been added to alice Tester's ride to \"Ski Trip\" by the driver."
+    );
+
+    admin.queue = RedisQueue::Disabled;
+    assert!(admin.notify_car(event, car).await.is_err());
+
+    harness.stop().await;
+}
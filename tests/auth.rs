@@ -0,0 +1,163 @@
+mod common;
+
+use actix_web::body::MessageBody;
+use actix_web::cookie::Cookie;
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use serde_json::Value;
+
+use rideboard_v2::api::open_api_spec;
+
+use common::{
+    call, finish_login, logged_in, query_param, start_login, Grant, TestApp, OIDC_PROVIDER,
+    PROVIDER,
+};
+
+#[actix_web::test]
+async fn oidc_logins_check_the_id_token() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+
+    let (cookie, authorize_url) = start_login(&app, OIDC_PROVIDER).await;
+    let code = harness.oidc.grant(Grant {
+        username: "alice".to_string(),
+        nonce: query_param(&authorize_url, "nonce"),
+        forged: false,
+    });
+    let state = query_param(&authorize_url, "state");
+    let (status, cookie) = finish_login(&app, OIDC_PROVIDER, &code, &state, cookie).await;
+    assert_eq!(status, StatusCode::FOUND);
+    let (status, user) = call(&app, TestRequest::get().uri("/api/v1/auth/"), &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(user["id"], "oidc:alice");
+    assert_eq!(user["email"], "alice@example.com");
+
+    // Signed with a key the issuer doesn't publish.
+    let (cookie, authorize_url) = start_login(&app, OIDC_PROVIDER).await;
+    let code = harness.oidc.grant(Grant {
+        username: "mallory".to_string(),
+        nonce: query_param(&authorize_url, "nonce"),
+        forged: true,
+    });
+    let state = query_param(&authorize_url, "state");
+    let (status, cookie) = finish_login(&app, OIDC_PROVIDER, &code, &state, cookie).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(!logged_in(&app, &cookie).await);
+
+    // An ID token issued for somebody else's login.
+    let (cookie, authorize_url) = start_login(&app, OIDC_PROVIDER).await;
+    let code = harness.oidc.grant(Grant {
+        username: "mallory".to_string(),
+        nonce: "someone-elses-nonce".to_string(),
+        forged: false,
+    });
+    let state = query_param(&authorize_url, "state");
+    let (status, cookie) = finish_login(&app, OIDC_PROVIDER, &code, &state, cookie).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(!logged_in(&app, &cookie).await);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn oidc_keys_added_after_startup_are_fetched() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    let fetches = harness.oidc.key_fetches();
+
+    harness.oidc.rotate_keys();
+    for username in ["alice", "bob"] {
+        let (cookie, authorize_url) = start_login(&app, OIDC_PROVIDER).await;
+        let code = harness.oidc.grant(Grant {
+            username: username.to_string(),
+            nonce: query_param(&authorize_url, "nonce"),
+            forged: false,
+        });
+        let state = query_param(&authorize_url, "state");
+        let (status, cookie) = finish_login(&app, OIDC_PROVIDER, &code, &state, cookie).await;
+        assert_eq!(status, StatusCode::FOUND);
+        assert!(logged_in(&app, &cookie).await);
+    }
+    // Only the first login had a key we didn't know yet.
+    assert_eq!(harness.oidc.key_fetches(), fetches + 1);
+
+    // A forged token names a key we already have, so it doesn't fetch them again.
+    let (cookie, authorize_url) = start_login(&app, OIDC_PROVIDER).await;
+    let code = harness.oidc.grant(Grant {
+        username: "mallory".to_string(),
+        nonce: query_param(&authorize_url, "nonce"),
+        forged: true,
+    });
+    let state = query_param(&authorize_url, "state");
+    let (status, _) = finish_login(&app, OIDC_PROVIDER, &code, &state, cookie).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(harness.oidc.key_fetches(), fetches + 1);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn redirects_must_match_the_pending_login() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+
+    // Nothing was started in this session.
+    let (status, cookie) =
+        finish_login(&app, PROVIDER, "alice", "state", Cookie::new("id", "")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(!logged_in(&app, &cookie).await);
+
+    let (cookie, _) = start_login(&app, PROVIDER).await;
+    let (status, cookie) = finish_login(&app, PROVIDER, "alice", "forged-state", cookie).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(!logged_in(&app, &cookie).await);
+
+    // A login started with another provider can't be finished with this one.
+    let (cookie, authorize_url) = start_login(&app, OIDC_PROVIDER).await;
+    let state = query_param(&authorize_url, "state");
+    let (status, cookie) = finish_login(&app, PROVIDER, "alice", &state, cookie).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(!logged_in(&app, &cookie).await);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn every_login_route_is_documented() {
+    let body = open_api_spec().await.into_body().try_into_bytes().unwrap();
+    let spec: Value = serde_json::from_slice(&body).unwrap();
+    for path in ["/", "/link", "/redirect"] {
+        let path = format!("/api/v1/auth/{{provider}}{}", path);
+        assert!(spec["paths"].get(&path).is_some(), "{} is missing", path);
+    }
+}
+
+#[actix_web::test]
+async fn admin_groups_are_read_at_login() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("root", &["rtp"]);
+    let cookie = harness.login(&app, "root").await;
+    let (status, user) = call(&app, TestRequest::get().uri("/api/v1/auth/"), &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(user["groups"], serde_json::json!(["rtp"]));
+
+    // Leaving the group only counts once they log in again.
+    harness.oauth.add_user("root", &[]);
+    let (status, _) = call(&app, TestRequest::get().uri("/api/v1/stats/"), &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = harness.login(&app, "root").await;
+    let (status, _) = call(&app, TestRequest::get().uri("/api/v1/stats/"), &cookie).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    harness.stop().await;
+}
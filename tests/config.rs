@@ -0,0 +1,197 @@
+use std::path::PathBuf;
+
+use rideboard_v2::config::{Config, RetentionMode};
+
+/// Write `contents` to a temporary file and load it like `--config` would.
+fn load(name: &str, contents: &str) -> anyhow::Result<Config> {
+    let path: PathBuf = std::env::temp_dir().join(format!(
+        "rideboard-config-{}-{}.toml",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    let result = Config::load(Some(&path));
+    std::fs::remove_file(&path).unwrap();
+    result.map(|(config, loaded)| {
+        assert_eq!(loaded, Some(path));
+        config
+    })
+}
+
+const PROVIDER: &str = r#"
+[[auth.providers]]
+name = "csh"
+kind = "csh"
+client_id = "id"
+client_secret = "secret"
+"#;
+
+#[test]
+fn file_settings_are_read() {
+    let config = load(
+        "read",
+        &format!(
+            r#"
+[database]
+max_connections = 12
+min_connections = 2
+
+[redis]
+key_prefix = "staging"
+
+[pings.routes]
+join = "join-route"
+
+[validation]
+max_event_duration_hours = 48
+
+[features]
+api_docs = false
+{}"#,
+            PROVIDER
+        ),
+    )
+    .unwrap();
+    assert_eq!(config.database.max_connections, 12);
+    assert_eq!(config.database.min_connections, 2);
+    assert_eq!(config.redis.key_prefix, "staging");
+    assert_eq!(config.pings.routes.join, "join-route");
+    assert_eq!(config.validation.max_event_duration.num_hours(), 48);
+    assert!(!config.features.api_docs);
+    assert!(config.features.notifications);
+    assert_eq!(config.auth.providers.len(), 1);
+    assert_eq!(config.auth.providers[0].name, "csh");
+}
+
+#[test]
+fn unknown_settings_are_rejected() {
+    let err = load("unknown", "[database]\nmax_conections = 3\n")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("max_conections"), "{}", err);
+}
+
+#[test]
+fn every_server_problem_is_listed() {
+    let mut config = load(
+        "server",
+        &format!(
+            "[session]\nkey = \"c2hvcnQ=\"\nttl_hours = 0\n{}{}{}",
+            PROVIDER,
+            PROVIDER,
+            PROVIDER.replace("name = \"csh\"", "name = \"\"")
+        ),
+    )
+    .unwrap();
+    config.database.url = String::new();
+    let err = config.check_server().unwrap_err().to_string();
+    for problem in [
+        "database.url (DATABASE_URL) must be set",
+        "session.key (SESSION_KEY) must be at least 64 bytes",
+        "session.ttl_hours must be at least 1",
+        "Auth provider csh is configured twice",
+        "Auth provider names cannot be empty",
+    ] {
+        assert!(err.contains(problem), "{} is missing from {}", problem, err);
+    }
+}
+
+#[test]
+fn servers_need_a_session_key_unless_told_to_make_one() {
+    let mut config = load("session", PROVIDER).unwrap();
+    config.database.url = "postgresql://localhost/rideboard".to_string();
+    config.redis.url = "redis://localhost".to_string();
+    let err = config.check_server().unwrap_err().to_string();
+    assert!(
+        err.contains("session.key (SESSION_KEY) must be set"),
+        "{}",
+        err
+    );
+    assert!(config.session_settings().is_err());
+
+    config.session.generate_key = true;
+    assert!(config.check_server().is_ok());
+    assert!(config.session_settings().is_ok());
+}
+
+#[test]
+fn worker_needs_pings_only_for_notifications() {
+    let mut config = load("worker", PROVIDER).unwrap();
+    config.database.url = "postgresql://localhost/rideboard".to_string();
+    config.redis.url = "redis://localhost".to_string();
+    config.pings.token = String::new();
+    let err = config.check_worker().unwrap_err().to_string();
+    assert!(
+        err.contains("pings.token (PINGS_TOKEN) must be set"),
+        "{}",
+        err
+    );
+
+    config.features.notifications = false;
+    assert!(config.check_worker().is_ok());
+}
+
+#[test]
+fn worker_checks_notification_templates() {
+    let mut config = load(
+        "templates",
+        r#"
+[notifications]
+timezone = "Europe/Paris"
+
+[notifications.locales.fr.templates]
+join = "{cavalier} a rejoint votre trajet"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.notifications.timezone.name(), "Europe/Paris");
+    config.database.url = "postgresql://localhost/rideboard".to_string();
+    config.redis.url = "redis://localhost".to_string();
+    let err = config.check_worker().unwrap_err().to_string();
+    assert!(
+        err.contains("notifications.locales.fr.templates.join uses {cavalier}"),
+        "{}",
+        err
+    );
+
+    let err = load("timezone", "[notifications]\ntimezone = \"Mars/Olympus\"\n")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("Mars/Olympus"), "{}", err);
+}
+
+#[test]
+fn only_the_server_needs_auth_providers() {
+    // Without providers in the file, they're read from `CSH_*` and `GOOGLE_*`, which aren't set.
+    let mut config = load("providers", "[features]\nnotifications = false\n").unwrap();
+    config.database.url = "postgresql://localhost/rideboard".to_string();
+    config.redis.url = "redis://localhost".to_string();
+    assert!(config.check_worker().is_ok());
+    let err = config.check_server().unwrap_err().to_string();
+    assert!(err.contains("client_id"), "{}", err);
+}
+
+#[test]
+fn retention_settings_are_read_and_checked() {
+    let mut config = load(
+        "retention",
+        "[retention]\nmonths = 18\nmode = \"purge\"\ninterval_hours = 0\n",
+    )
+    .unwrap();
+    assert_eq!(config.retention.months, 18);
+    assert_eq!(config.retention.mode, RetentionMode::Purge);
+    config.database.url = "postgresql://localhost/rideboard".to_string();
+    config.redis.url = "redis://localhost".to_string();
+    config.features.notifications = false;
+    let err = config.check_worker().unwrap_err().to_string();
+    assert!(
+        err.contains("retention.interval_hours (RETENTION_INTERVAL_HOURS) must be at least 1"),
+        "{}",
+        err
+    );
+
+    let err = load("retention-mode", "[retention]\nmode = \"delete\"\n")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("delete"), "{}", err);
+}
@@ -0,0 +1,18 @@
+//! Settings from the environment. Kept apart from `config.rs`, since every config loaded in
+//! this process sees the variables set here.
+
+use rideboard_v2::config::Config;
+
+#[test]
+fn invalid_environment_settings_stop_startup() {
+    std::env::set_var("SESSION_TTL_HOURS", "a week");
+    let err = Config::load(Some(std::path::Path::new("rideboard.example.toml")))
+        .err()
+        .unwrap();
+    std::env::remove_var("SESSION_TTL_HOURS");
+    assert!(
+        err.to_string().contains("SESSION_TTL_HOURS is invalid"),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,185 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use chrono::{TimeDelta, Utc};
+use serde_json::json;
+
+use common::{call, insert_car, insert_event, insert_user, TestApp};
+
+#[actix_web::test]
+async fn create_and_get_event() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let cookie = harness.login(&app, "alice").await;
+
+    let start = Utc::now() + TimeDelta::days(1);
+    let (status, id) = call(
+        &app,
+        TestRequest::post().uri("/api/v1/event/").set_json(json!({
+            "name": "Ski Trip",
+            "location": "Bristol Mountain",
+            "startTime": start,
+            "endTime": start + TimeDelta::hours(8),
+        })),
+        &cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, event) = call(
+        &app,
+        TestRequest::get().uri(&format!("/api/v1/event/{}", id)),
+        &cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(event["name"], "Ski Trip");
+    assert_eq!(event["creator"]["id"], alice);
+    assert_eq!(event["creator"]["name"], "alice Tester");
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn invalid_event_reports_each_field() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    let cookie = harness.login(&app, "alice").await;
+
+    let start = Utc::now() + TimeDelta::days(1);
+    let (status, body) = call(
+        &app,
+        TestRequest::post().uri("/api/v1/event/").set_json(json!({
+            "name": "",
+            "location": "Bristol Mountain",
+            "startTime": start,
+            "endTime": start - TimeDelta::hours(1),
+        })),
+        &cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "validation_failed");
+    assert_eq!(
+        body["errors"],
+        json!([
+            {"field": "name", "code": "required", "message": "Missing Name."},
+            {
+                "field": "endTime",
+                "code": "before_start",
+                "message": "Start date cannot be after end date."
+            },
+        ])
+    );
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn requests_without_a_session_are_rejected() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+
+    let res = test::call_service(&app, TestRequest::get().uri("/api/v1/event/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let body: serde_json::Value = test::read_body_json(res).await;
+    assert_eq!(body["code"], "unauthorized");
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn only_the_creator_can_delete_an_event() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    harness.oauth.add_user("bob", &[]);
+    let alice = harness.login(&app, "alice").await;
+    let bob = harness.login(&app, "bob").await;
+
+    let start = Utc::now() + TimeDelta::days(1);
+    let (_, id) = call(
+        &app,
+        TestRequest::post().uri("/api/v1/event/").set_json(json!({
+            "name": "Ski Trip",
+            "location": "Bristol Mountain",
+            "startTime": start,
+            "endTime": start + TimeDelta::hours(8),
+        })),
+        &alice,
+    )
+    .await;
+
+    let uri = format!("/api/v1/event/{}", id);
+    let (status, _) = call(&app, TestRequest::delete().uri(&uri), &bob).await;
+    assert!(status.is_client_error(), "bob deleted alice's event");
+    let (status, _) = call(&app, TestRequest::get().uri(&uri), &alice).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = call(&app, TestRequest::delete().uri(&uri), &alice).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, TestRequest::get().uri(&uri), &alice).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn exported_rosters_cannot_run_formulas() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let cookie = harness.login(&app, "alice").await;
+    let bob = insert_user(&harness.state.db, "@bob").await;
+    let event = insert_event(&harness.state.db, &alice, 1).await;
+    let car = insert_car(&harness.state.db, event, &alice, &[&bob]).await;
+    sqlx::query("UPDATE car SET comment = $1")
+        .bind("=HYPERLINK(\"http://example.com\", \"Directions\")")
+        .execute(&harness.state.db)
+        .await
+        .unwrap();
+
+    let res = test::call_service(
+        &app,
+        TestRequest::get()
+            .uri(&format!("/api/v1/event/{}/export?format=csv", event))
+            .cookie(cookie)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+    let rows: Vec<&str> = body.split("\r\n").collect();
+    assert_eq!(
+        rows[0],
+        "car_id,driver_name,driver_email,departure_time,return_time,comment,rider_name,rider_email"
+    );
+    let cells: Vec<&str> = rows[1].split(',').collect();
+    assert_eq!(cells[0], car.to_string());
+    assert_eq!(cells[1], "alice Tester");
+    assert_eq!(
+        &cells[5..],
+        [
+            "\"'=HYPERLINK(\"\"http://example.com\"\"",
+            " \"\"Directions\"\")\"",
+            "'@bob Tester",
+            "'@bob@csh.rit.edu"
+        ]
+    );
+    assert_eq!(rows[2], "");
+
+    harness.stop().await;
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use serde_json::Value;
+
+use common::{call, TestApp};
+
+#[actix_web::test]
+async fn readiness_checks_postgres() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+
+    let res = test::call_service(&app, TestRequest::get().uri("/healthz").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = test::call_service(&app, TestRequest::get().uri("/readyz").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(res).await;
+    assert_eq!(body["checks"]["postgres"], "ok");
+    // The harness keeps jobs in memory.
+    assert_eq!(body["checks"]["redis"], "disabled");
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn metrics_cover_requests_and_queries() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    let cookie = harness.login(&app, "alice").await;
+
+    let (status, _) = call(&app, TestRequest::get().uri("/api/v1/event/"), &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let res = test::call_service(&app, TestRequest::get().uri("/metrics").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+    assert!(
+        body.contains(r#"rideboard_http_request_duration_seconds_count{method="GET",route="/api/v1/event/",status="200"}"#),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains(r#"rideboard_db_query_duration_seconds_count{query="event::select_all"}"#),
+        "{}",
+        body
+    );
+
+    harness.stop().await;
+}
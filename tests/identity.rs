@@ -0,0 +1,286 @@
+mod common;
+
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use reqwest::Url;
+use serde_json::Value;
+
+use rideboard_v2::db::car::Car;
+use rideboard_v2::db::identity::UserIdentity;
+use rideboard_v2::db::ride_request::{RideRequest, RideRequestData};
+use rideboard_v2::db::transfer::CarTransfer;
+use rideboard_v2::db::user::UserData;
+
+use common::{
+    call, car_riders, finish_login, insert_car, insert_event, insert_user, query_param,
+    start_login, Grant, MockOidc, TestApp, OIDC_PROVIDER,
+};
+
+/// Finish logging in to `MockOidc` as `username`, from `authorize_url`.
+async fn oidc_redirect<S, B>(
+    app: &S,
+    oidc: &MockOidc,
+    authorize_url: &Url,
+    username: &str,
+    cookie: Cookie<'static>,
+) -> Cookie<'static>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let code = oidc.grant(Grant {
+        username: username.to_string(),
+        nonce: query_param(authorize_url, "nonce"),
+        forged: false,
+    });
+    let state = query_param(authorize_url, "state");
+    let (status, cookie) = finish_login(app, OIDC_PROVIDER, &code, &state, cookie).await;
+    assert_eq!(status, StatusCode::FOUND);
+    cookie
+}
+
+async fn link<S, B>(app: &S, oidc: &MockOidc, username: &str, cookie: &Cookie<'static>)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let res = test::call_service(
+        app,
+        TestRequest::get()
+            .uri(&format!("/api/v1/auth/{}/link", OIDC_PROVIDER))
+            .cookie(cookie.clone())
+            .to_request(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let authorize_url = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+    let authorize_url = Url::parse(&authorize_url).unwrap();
+    oidc_redirect(app, oidc, &authorize_url, username, cookie.clone()).await;
+}
+
+fn realms(identities: &Value) -> Vec<&str> {
+    identities
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|identity| identity["realm"].as_str().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn linking_another_users_account_waits_for_confirmation() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+
+    // A new account is linked right away.
+    link(&app, &harness.oidc, "alice-phone", &alice_cookie).await;
+    let (_, identities) = call(
+        &app,
+        TestRequest::get().uri("/api/v1/user/identity"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(realms(&identities), vec!["csh", "oidc"]);
+
+    // One that was already used to log in has its own user, with a seat in Carol's car.
+    let (cookie, authorize_url) = start_login(&app, OIDC_PROVIDER).await;
+    oidc_redirect(&app, &harness.oidc, &authorize_url, "alice-laptop", cookie).await;
+    let other = "oidc:alice-laptop".to_string();
+    let carol = insert_user(db, "carol").await;
+    let event = insert_event(db, &carol, 1).await;
+    let car = insert_car(db, event, &carol, &[&other]).await;
+
+    link(&app, &harness.oidc, "alice-laptop", &alice_cookie).await;
+    assert!(UserData::select_one(other.clone(), db)
+        .await
+        .unwrap()
+        .is_some());
+    let (status, pending) = call(
+        &app,
+        TestRequest::get().uri("/api/v1/user/merge"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(pending["id"], other.as_str());
+
+    let (status, _) = call(
+        &app,
+        TestRequest::delete().uri("/api/v1/user/merge"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri("/api/v1/user/merge"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(UserData::select_one(other.clone(), db)
+        .await
+        .unwrap()
+        .is_some());
+
+    link(&app, &harness.oidc, "alice-laptop", &alice_cookie).await;
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri("/api/v1/user/merge"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(UserData::select_one(other.clone(), db)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(car_riders(db, event, car).await, vec![alice.clone()]);
+    let (_, identities) = call(
+        &app,
+        TestRequest::get().uri("/api/v1/user/identity"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(realms(&identities), vec!["csh", "oidc", "oidc"]);
+    let (status, _) = call(
+        &app,
+        TestRequest::get().uri("/api/v1/user/merge"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Both drive to the same event, so one of the cars has to go first.
+    let (cookie, authorize_url) = start_login(&app, OIDC_PROVIDER).await;
+    oidc_redirect(&app, &harness.oidc, &authorize_url, "alice-work", cookie).await;
+    let other = "oidc:alice-work".to_string();
+    insert_car(db, event, &other, &[]).await;
+    let own_car = insert_car(db, event, &alice, &[]).await;
+    Car::remove_rider(car, &alice, db).await.unwrap();
+
+    link(&app, &harness.oidc, "alice-work", &alice_cookie).await;
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri("/api/v1/user/merge"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(UserData::select_one(other.clone(), db)
+        .await
+        .unwrap()
+        .is_some());
+    Car::delete(own_car, event, alice.clone(), db)
+        .await
+        .unwrap();
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri("/api/v1/user/merge"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(UserData::select_one(other, db).await.unwrap().is_none());
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn merged_users_keep_one_place_per_event() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let kept = insert_user(db, "alice").await;
+    let old = insert_user(db, "alice2").await;
+    let dave = insert_user(db, "dave").await;
+    let erin = insert_user(db, "erin").await;
+
+    // The kept user drives, the old one rides with somebody else.
+    let driving = insert_event(db, &dave, 1).await;
+    let kept_car = insert_car(db, driving, &kept, &[]).await;
+    let dave_car = insert_car(db, driving, &dave, &[&old]).await;
+    CarTransfer::insert_new(driving, kept_car, kept.clone(), old.clone(), db)
+        .await
+        .unwrap();
+    // The kept user rides, the old one drives.
+    let riding = insert_event(db, &dave, 2).await;
+    let seat = insert_car(db, riding, &dave, &[&kept]).await;
+    let old_car = insert_car(db, riding, &old, &[]).await;
+    // The old user rides in the kept user's car.
+    let own = insert_event(db, &dave, 3).await;
+    let own_car = insert_car(db, own, &kept, &[&old]).await;
+    // Both ride, in different cars, and in the same car.
+    let apart = insert_event(db, &dave, 4).await;
+    let kept_seat = insert_car(db, apart, &dave, &[&kept]).await;
+    let old_seat = insert_car(db, apart, &erin, &[&old]).await;
+    let together = insert_event(db, &dave, 5).await;
+    let shared = insert_car(db, together, &dave, &[&kept, &old]).await;
+    // The old user asked for a ride the kept user already has.
+    let request = RideRequestData {
+        earliest_departure: None,
+        latest_departure: None,
+        note: String::new(),
+    };
+    RideRequest::insert_new(riding, old.clone(), &request, db)
+        .await
+        .unwrap();
+
+    let mut tx = db.begin().await.unwrap();
+    UserIdentity::merge(&old, &kept, &mut tx).await.unwrap();
+    tx.commit().await.unwrap();
+
+    assert!(car_riders(db, driving, dave_car).await.is_empty());
+    assert!(CarTransfer::select_one(driving, kept_car, db)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(car_riders(db, riding, seat).await.is_empty());
+    let car = Car::select_one(riding, old_car, db).await.unwrap().unwrap();
+    assert_eq!(car.driver.id, kept);
+    assert!(RideRequest::select_all(riding, db)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(car_riders(db, own, own_car).await.is_empty());
+    assert_eq!(car_riders(db, apart, kept_seat).await, vec![kept.clone()]);
+    assert!(car_riders(db, apart, old_seat).await.is_empty());
+    assert_eq!(car_riders(db, together, shared).await, vec![kept.clone()]);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn users_driving_to_the_same_event_are_not_merged() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let kept = insert_user(db, "alice").await;
+    let old = insert_user(db, "alice2").await;
+    let event = insert_event(db, &kept, 1).await;
+    insert_car(db, event, &kept, &[]).await;
+    let old_car = insert_car(db, event, &old, &[]).await;
+
+    assert_eq!(
+        UserIdentity::shared_event(&old, &kept, db).await.unwrap(),
+        Some(event)
+    );
+    let mut tx = db.begin().await.unwrap();
+    assert!(UserIdentity::merge(&old, &kept, &mut tx).await.is_err());
+    tx.rollback().await.unwrap();
+    let car = Car::select_one(event, old_car, db).await.unwrap().unwrap();
+    assert_eq!(car.driver.id, old);
+
+    harness.stop().await;
+}
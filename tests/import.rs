@@ -0,0 +1,235 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use chrono::{TimeDelta, Utc};
+use serde_json::{json, Value};
+
+use rideboard_v2::db::event::Event;
+use rideboard_v2::import::import_file;
+use rideboard_v2::redis::RedisQueue;
+use rideboard_v2::validation::ValidationRules;
+
+use common::{call, car_riders, insert_event, insert_user, TestApp};
+
+/// The fields and codes of a validation error response.
+fn field_errors(body: &Value) -> Vec<(String, String)> {
+    assert_eq!(body["code"], "validation_failed");
+    body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|err| {
+            (
+                err["field"].as_str().unwrap().to_string(),
+                err["code"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[actix_web::test]
+async fn command_line_imports_notify_riders() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let alice = insert_user(db, "alice").await;
+    let bob = insert_user(db, "bob").await;
+
+    let start = Utc::now() + TimeDelta::days(1);
+    let file = std::env::temp_dir().join(format!("rideboard-import-{}.json", std::process::id()));
+    std::fs::write(
+        &file,
+        json!([{
+            "name": "Ski Trip",
+            "location": "Bristol Mountain",
+            "startTime": start,
+            "endTime": start + TimeDelta::hours(8),
+            "cars": [{
+                "driver": "alice@csh.rit.edu",
+                "maxCapacity": 2,
+                "departureTime": start,
+                "returnTime": start + TimeDelta::hours(8),
+                "riders": ["bob@csh.rit.edu"],
+            }],
+        }])
+        .to_string(),
+    )
+    .unwrap();
+
+    let mut queue = RedisQueue::Memory(Vec::new());
+    let result = import_file(
+        &file,
+        alice.clone(),
+        &ValidationRules::default(),
+        db,
+        &mut queue,
+    )
+    .await;
+    std::fs::remove_file(&file).unwrap();
+    let result = result.unwrap();
+    assert_eq!(
+        car_riders(db, result.events[0], result.cars[0]).await,
+        vec![bob]
+    );
+
+    assert!(harness.retry_jobs(queue.take_jobs()).await.is_empty());
+    let pings = harness.pings.take();
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].route, "add");
+    assert_eq!(pings[0].username, "bob");
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn one_bad_row_rejects_the_whole_batch() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("root", &["rtp"]);
+    let cookie = harness.login(&app, "root").await;
+    insert_user(db, "alice").await;
+    insert_user(db, "bob").await;
+    let start = Utc::now() + TimeDelta::days(1);
+    let end = start + TimeDelta::hours(8);
+
+    let csv = format!(
+        "event_name,location,start_time,end_time,driver,max_capacity,departure_time,return_time,riders\n\
+         Ski Trip,Bristol Mountain,{start},{end},alice,2,{start},{end},bob\n\
+         Ski Trip,Bristol Mountain,,{end},,,,,\n",
+        start = start.to_rfc3339(),
+        end = end.to_rfc3339(),
+    );
+    let (status, body) = call(
+        &app,
+        TestRequest::post()
+            .uri("/api/v1/event/import?format=csv")
+            .set_payload(csv),
+        &cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        field_errors(&body),
+        vec![("rows[1].start_time".to_string(), "required".to_string())]
+    );
+
+    // The first event is fine, but nobody can ride in the second.
+    let car = json!({
+        "driver": "alice",
+        "maxCapacity": 2,
+        "departureTime": start,
+        "returnTime": end,
+    });
+    let mut events = json!([
+        {
+            "name": "Ski Trip",
+            "location": "Bristol Mountain",
+            "startTime": start,
+            "endTime": end,
+            "cars": [car],
+        },
+        {
+            "name": "",
+            "location": "Hunter Mountain",
+            "startTime": start,
+            "endTime": end,
+            "cars": [car],
+        },
+    ]);
+    events[1]["cars"][0]["riders"] = json!(["bob", "nobody"]);
+    let (status, body) = call(
+        &app,
+        TestRequest::post()
+            .uri("/api/v1/event/import?format=json")
+            .set_json(&events),
+        &cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        field_errors(&body),
+        vec![
+            ("[1].name".to_string(), "required".to_string()),
+            ("[1].cars[0].riders[1]".to_string(), "not_found".to_string()),
+        ]
+    );
+    assert!(body["errors"][1]["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("Event 2, car 1: "));
+    assert!(Event::select_all(false, db).await.unwrap().is_empty());
+
+    events[1]["name"] = json!("Snow Day");
+    events[1]["cars"][0]["riders"] = json!(["bob"]);
+    let (status, body) = call(
+        &app,
+        TestRequest::post()
+            .uri("/api/v1/event/import?format=json")
+            .set_json(&events),
+        &cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["events"].as_array().map(Vec::len), Some(2));
+    assert_eq!(Event::select_all(false, db).await.unwrap().len(), 2);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn only_admins_import_events() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    harness.oauth.add_user("bob", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+    let event_id = insert_event(db, &alice, 1).await;
+    let start = Utc::now() + TimeDelta::days(1);
+
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri("/api/v1/event/import?format=json")
+            .set_json(json!([{
+                "name": "Ski Trip",
+                "location": "Bristol Mountain",
+                "startTime": start,
+                "endTime": start + TimeDelta::hours(8),
+            }])),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(Event::select_all(false, db).await.unwrap().len(), 1);
+
+    // Creators can add cars to their own events, and nobody else can.
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&format!("/api/v1/event/{}/import?format=json", event_id))
+            .set_json(json!([])),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&format!("/api/v1/event/{}/import?format=json", event_id))
+            .set_json(json!([])),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    harness.stop().await;
+}
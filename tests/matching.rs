@@ -0,0 +1,260 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde_json::{json, Value};
+
+use rideboard_v2::db::car::Car;
+use rideboard_v2::db::ride_request::RideRequest;
+use rideboard_v2::db::user::UserData;
+use rideboard_v2::matching::{propose, MatchProposal};
+
+use common::{call, car_riders, insert_car, insert_event, TestApp};
+
+fn user(id: &str) -> UserData {
+    UserData {
+        id: id.to_string(),
+        realm: "csh".to_string(),
+        name: id.to_string(),
+        email: format!("{}@csh.rit.edu", id),
+    }
+}
+
+fn car(id: i32, driver: &str, riders: &[&str], max_capacity: i32, departure: DateTime<Utc>) -> Car {
+    Car {
+        id,
+        event_id: Some(1),
+        driver: user(driver),
+        riders: Some(riders.iter().map(|id| user(id)).collect()),
+        max_capacity,
+        departure_time: departure,
+        return_time: departure + TimeDelta::hours(8),
+        comment: String::new(),
+    }
+}
+
+fn request(
+    rider: &str,
+    earliest: Option<DateTime<Utc>>,
+    latest: Option<DateTime<Utc>>,
+) -> RideRequest {
+    RideRequest {
+        event_id: 1,
+        rider: user(rider),
+        earliest_departure: earliest,
+        latest_departure: latest,
+        note: String::new(),
+        created_at: Utc::now(),
+    }
+}
+
+/// (car, riders) pairs, to compare proposals in one assertion.
+fn assignments(proposal: &MatchProposal) -> Vec<(i32, Vec<&str>)> {
+    proposal
+        .assignments
+        .iter()
+        .map(|assignment| {
+            (
+                assignment.car_id,
+                assignment.riders.iter().map(String::as_str).collect(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn the_most_constrained_riders_are_placed_first() {
+    let morning = Utc::now() + TimeDelta::days(1);
+    let evening = morning + TimeDelta::hours(10);
+    let cars = [
+        car(1, "dave", &[], 1, morning),
+        car(2, "erin", &[], 1, evening),
+    ];
+    // Alice would take the morning car if riders were placed alphabetically, leaving Bob, who
+    // can only leave in the morning, without a seat.
+    let requests = [
+        request("alice", None, None),
+        request("bob", None, Some(morning + TimeDelta::hours(1))),
+    ];
+
+    let proposal = propose(&cars, &requests);
+    assert_eq!(
+        assignments(&proposal),
+        vec![(1, vec!["bob"]), (2, vec!["alice"])]
+    );
+    assert!(proposal.unmatched.is_empty());
+}
+
+#[test]
+fn riders_are_spread_across_the_emptiest_cars() {
+    let departure = Utc::now() + TimeDelta::days(1);
+    let cars = [
+        car(1, "dave", &["frank"], 3, departure),
+        car(2, "erin", &[], 3, departure),
+    ];
+    let requests = [
+        request("alice", None, None),
+        request("bob", None, None),
+        request("carol", None, None),
+    ];
+
+    let proposal = propose(&cars, &requests);
+    assert_eq!(
+        assignments(&proposal),
+        vec![(1, vec!["bob"]), (2, vec!["alice", "carol"])]
+    );
+}
+
+#[test]
+fn riders_without_a_suitable_car_are_unmatched() {
+    let departure = Utc::now() + TimeDelta::days(1);
+    let cars = [
+        car(1, "dave", &["frank"], 1, departure),
+        car(2, "erin", &[], 2, departure),
+    ];
+    let requests = [
+        // Too early for every car.
+        request("alice", None, Some(departure - TimeDelta::hours(1))),
+        // Already riding, and already driving.
+        request("frank", None, None),
+        request("erin", None, None),
+        // A window that is a single moment still fits.
+        request("bob", Some(departure), Some(departure)),
+        request("carol", None, None),
+        request("gina", None, None),
+        request("hank", None, None),
+    ];
+
+    let proposal = propose(&cars, &requests);
+    assert_eq!(assignments(&proposal), vec![(2, vec!["bob", "carol"])]);
+    assert_eq!(proposal.unmatched, vec!["alice", "gina", "hank"]);
+}
+
+#[actix_web::test]
+async fn stale_proposals_are_rejected() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let carol = harness.oauth.add_user("carol", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+    let carol_cookie = harness.login(&app, "carol").await;
+    let event_id = insert_event(&harness.state.db, &alice, 1).await;
+    let car_id = insert_car(&harness.state.db, event_id, &alice, &[]).await;
+
+    let request_uri = format!("/api/v1/event/{}/request/", event_id);
+    for cookie in [&bob_cookie, &carol_cookie] {
+        let (status, _) = call(
+            &app,
+            TestRequest::post().uri(&request_uri).set_json(json!({})),
+            cookie,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let match_uri = format!("/api/v1/event/{}/match/", event_id);
+    let (status, proposal) = call(&app, TestRequest::get().uri(&match_uri), &alice_cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        proposal["assignments"],
+        json!([{ "carId": car_id, "riders": [bob] }])
+    );
+
+    // Bob now has to leave before the car does.
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri(&request_uri).set_json(json!({
+            "latestDeparture": Utc::now() + TimeDelta::hours(1),
+        })),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = call(
+        &app,
+        TestRequest::post().uri(&match_uri).set_json(&proposal),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+
+    // Nothing was applied, so both requests are still there.
+    let (_, requests) = call(&app, TestRequest::get().uri(&request_uri), &alice_cookie).await;
+    assert_eq!(requests.as_array().map(Vec::len), Some(2));
+    assert!(car_riders(&harness.state.db, event_id, car_id)
+        .await
+        .is_empty());
+
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri(&request_uri).set_json(json!({})),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let mut overfull = proposal.clone();
+    overfull["assignments"][0]["riders"]
+        .as_array_mut()
+        .unwrap()
+        .push(Value::String(carol));
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri(&match_uri).set_json(&overfull),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri(&match_uri).set_json(&proposal),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        car_riders(&harness.state.db, event_id, car_id).await,
+        vec![bob]
+    );
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn only_the_creator_and_admins_can_match() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    harness.oauth.add_user("bob", &[]);
+    harness.oauth.add_user("root", &["rtp"]);
+    harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+    let root_cookie = harness.login(&app, "root").await;
+    let event_id = insert_event(&harness.state.db, &alice, 1).await;
+    let match_uri = format!("/api/v1/event/{}/match/", event_id);
+
+    let (status, body) = call(&app, TestRequest::get().uri(&match_uri), &bob_cookie).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        body["error"],
+        "Only the event creator or an admin can match riders."
+    );
+    let (status, proposal) = call(&app, TestRequest::get().uri(&match_uri), &root_cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri(&match_uri).set_json(&proposal),
+        &root_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    harness.stop().await;
+}
@@ -0,0 +1,125 @@
+use chrono::{TimeZone, Utc};
+
+use rideboard_v2::notifications::{NotificationKind, NotificationTemplates, Placeholders};
+
+fn templates(toml: &str) -> NotificationTemplates {
+    toml::from_str(toml).unwrap()
+}
+
+fn values() -> Placeholders<'static> {
+    Placeholders {
+        rider: "Bob",
+        driver: "Alice",
+        old_driver: "Carol",
+        event: "Ski Trip",
+        location: "Bristol Mountain",
+        // 9:30 AM in Rochester.
+        time: Utc.with_ymd_and_hms(2026, 1, 10, 14, 30, 0).unwrap(),
+    }
+}
+
+const SPANISH: &str = r#"
+[locales.es]
+time_format = "%d/%m %H:%M"
+
+[locales.es.templates]
+join = "{rider} se unió a tu viaje a \"{event}\" ({location}, {time})."
+"#;
+
+#[test]
+fn built_in_templates_are_used_by_default() {
+    let templates = NotificationTemplates::default();
+    assert_eq!(
+        templates.render(NotificationKind::Join, None, &values()),
+        "Bob joined your ride to \"Ski Trip\"."
+    );
+    assert_eq!(
+        templates.render(NotificationKind::DriverChange, Some("fr"), &values()),
+        "Alice is now driving your ride to \"Ski Trip\" instead of Carol."
+    );
+}
+
+#[test]
+fn templates_are_picked_by_locale() {
+    let templates = templates(SPANISH);
+    let expected = "Bob se unió a tu viaje a \"Ski Trip\" (Bristol Mountain, 10/01 09:30).";
+    assert_eq!(
+        templates.render(NotificationKind::Join, Some("es"), &values()),
+        expected
+    );
+    // Regional locales fall back to their language.
+    assert_eq!(
+        templates.render(NotificationKind::Join, Some("ES_mx"), &values()),
+        expected
+    );
+    // Kinds the locale leaves out use the built-in text.
+    assert_eq!(
+        templates.render(NotificationKind::Leave, Some("es"), &values()),
+        "Bob left your ride \"Ski Trip\"."
+    );
+}
+
+#[test]
+fn the_default_locale_and_timezone_can_be_changed() {
+    let templates = templates(&format!(
+        "default_locale = \"es\"\ntimezone = \"UTC\"\n{}",
+        SPANISH
+    ));
+    assert!(templates.check().is_empty());
+    assert_eq!(
+        templates.render(NotificationKind::Join, Some("de"), &values()),
+        "Bob se unió a tu viaje a \"Ski Trip\" (Bristol Mountain, 10/01 14:30)."
+    );
+}
+
+#[test]
+fn braces_can_be_escaped() {
+    let templates = templates(
+        r#"
+[locales.en.templates]
+add = "{{{driver}}} added you at {time}"
+"#,
+    );
+    assert!(templates.check().is_empty());
+    assert_eq!(
+        templates.render(NotificationKind::Add, None, &values()),
+        "{Alice} added you at Sat Jan 10 at 9:30 AM"
+    );
+}
+
+#[test]
+fn every_broken_template_is_listed() {
+    let templates = templates(
+        r#"
+default_locale = "de"
+
+[locales.es]
+time_format = "%Q"
+
+[locales.es.templates]
+join = "{rider} se unió a {evento}"
+leave = "{rider se fue"
+transfer_offer = "{rider} te pidió llevar su auto"
+"#,
+    );
+    assert_eq!(
+        templates.check(),
+        vec![
+            "notifications.default_locale is de, which has no templates",
+            "notifications.locales.es.time_format is not a valid time format",
+            "notifications.locales.es.templates.join uses {evento}, which isn't one of: rider, \
+             driver, event, location, time",
+            "notifications.locales.es.templates.leave has a { without a matching }",
+            "notifications.locales.es.templates.transfer_offer uses {rider}, which isn't one of: \
+             driver, event, location, time",
+        ]
+    );
+}
+
+#[test]
+fn unknown_kinds_are_rejected() {
+    let err = toml::from_str::<NotificationTemplates>("[locales.en.templates]\njoined = \"\"\n")
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("joined"), "{}", err);
+}
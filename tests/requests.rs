@@ -0,0 +1,171 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use chrono::{TimeDelta, Utc};
+use serde_json::json;
+
+use rideboard_v2::db::car::Car;
+
+use common::{call, car_riders, insert_car, insert_event, insert_user, TestApp};
+
+#[actix_web::test]
+async fn riders_post_requests_that_everyone_sees() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+    let event_id = insert_event(db, &alice, 1).await;
+    insert_car(db, event_id, &alice, &[]).await;
+    let uri = format!("/api/v1/event/{}/request/", event_id);
+
+    let latest = Utc::now() + TimeDelta::days(2);
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri(&uri).set_json(json!({
+            "latestDeparture": latest,
+            "note": "Bringing skis",
+        })),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    // Drivers are already going.
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri(&uri).set_json(json!({})),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, requests) = call(&app, TestRequest::get().uri(&uri), &alice_cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    let requests = requests.as_array().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["rider"]["id"], bob);
+    assert_eq!(requests[0]["note"], "Bringing skis");
+    assert!(requests[0]["earliestDeparture"].is_null());
+    assert!(!requests[0]["latestDeparture"].is_null());
+
+    let (status, _) = call(&app, TestRequest::delete().uri(&uri), &bob_cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, requests) = call(&app, TestRequest::get().uri(&uri), &alice_cookie).await;
+    assert_eq!(requests.as_array().map(Vec::len), Some(0));
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn drivers_invite_riders_who_asked_into_seats_they_have() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let carol = harness.oauth.add_user("carol", &[]);
+    let dave = harness.oauth.add_user("dave", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+    let carol_cookie = harness.login(&app, "carol").await;
+    let dave_cookie = harness.login(&app, "dave").await;
+    let frank = insert_user(db, "frank").await;
+    let event_id = insert_event(db, &alice, 1).await;
+    let alice_car = insert_car(db, event_id, &alice, &[]).await;
+    let bob_car = insert_car(db, event_id, &bob, &[]).await;
+    let frank_car = insert_car(db, event_id, &frank, &[]).await;
+    let request_uri = format!("/api/v1/event/{}/request/", event_id);
+    let invite_uri =
+        |car_id: i32| format!("/api/v1/event/{}/car/{}/rider/invite", event_id, car_id);
+
+    for cookie in [&carol_cookie, &dave_cookie] {
+        let (status, _) = call(
+            &app,
+            TestRequest::post().uri(&request_uri).set_json(json!({})),
+            cookie,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // Only the driver invites, and only people who asked.
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&invite_uri(alice_car))
+            .set_json(json!({ "rider": carol })),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&invite_uri(alice_car))
+            .set_json(json!({ "rider": frank })),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&invite_uri(alice_car))
+            .set_json(json!({ "rider": carol })),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        car_riders(db, event_id, alice_car).await,
+        vec![carol.clone()]
+    );
+    harness.run_jobs().await;
+    let pings = harness.pings.take();
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].route, "add");
+    assert_eq!(pings[0].username, "carol");
+
+    // Alice's only seat is taken.
+    let (status, body) = call(
+        &app,
+        TestRequest::post()
+            .uri(&invite_uri(alice_car))
+            .set_json(json!({ "rider": dave })),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "Car is full.");
+    assert_eq!(car_riders(db, event_id, alice_car).await, vec![carol]);
+
+    // Frank got to Dave first, without the board.
+    Car::add_rider(frank_car, &dave, db).await.unwrap();
+    let (status, body) = call(
+        &app,
+        TestRequest::post()
+            .uri(&invite_uri(bob_car))
+            .set_json(json!({ "rider": dave })),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "User is already in a car.");
+    assert!(car_riders(db, event_id, bob_car).await.is_empty());
+
+    // Dave's request is still there, since both invites were rolled back.
+    let (_, requests) = call(&app, TestRequest::get().uri(&request_uri), &bob_cookie).await;
+    let requests = requests.as_array().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["rider"]["id"], dave);
+
+    harness.stop().await;
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use chrono::{TimeDelta, Utc};
+
+use rideboard_v2::config::{RetentionConfig, RetentionMode};
+use rideboard_v2::db::car::Car;
+use rideboard_v2::db::delivery::Delivery;
+use rideboard_v2::db::event::Event;
+use rideboard_v2::db::user::DELETED_USER_ID;
+use rideboard_v2::retention::{self, RetentionReport};
+
+use common::{car_riders, insert_car, insert_event, insert_user, TestApp};
+
+fn policy(mode: RetentionMode) -> RetentionConfig {
+    RetentionConfig {
+        months: 3,
+        mode,
+        ..RetentionConfig::default()
+    }
+}
+
+#[actix_web::test]
+async fn anonymizing_keeps_old_events_without_their_riders() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let alice = insert_user(db, "alice").await;
+    let bob = insert_user(db, "bob").await;
+    let old = insert_event(db, &alice, -100).await;
+    let recent = insert_event(db, &alice, -10).await;
+    let old_car = insert_car(db, old, &alice, &[&bob]).await;
+    let recent_car = insert_car(db, recent, &alice, &[&bob]).await;
+    sqlx::query("UPDATE car SET comment = 'Meet at the loop'")
+        .execute(db)
+        .await
+        .unwrap();
+
+    let policy = policy(RetentionMode::Anonymize);
+    let report = retention::run(&policy, db).await.unwrap();
+    assert_eq!(
+        report,
+        RetentionReport {
+            events_purged: 0,
+            // Bob's ride, the comment, the driver and the creator.
+            rows_anonymized: 4,
+            deliveries_deleted: 0,
+        }
+    );
+    let event = Event::select_one(old, db).await.unwrap().unwrap();
+    assert_eq!(event.creator.id, DELETED_USER_ID);
+    let car = Car::select_one(old, old_car, db).await.unwrap().unwrap();
+    assert_eq!(car.driver.id, DELETED_USER_ID);
+    assert!(car_riders(db, old, old_car).await.is_empty());
+    let car = Car::select_one(recent, recent_car, db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(car.driver.id, alice);
+    assert_eq!(car_riders(db, recent, recent_car).await, vec![bob]);
+
+    let report = retention::run(&policy, db).await.unwrap();
+    assert_eq!(report, RetentionReport::default());
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn purging_deletes_old_events_and_deliveries() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let alice = insert_user(db, "alice").await;
+    let old = insert_event(db, &alice, -100).await;
+    let recent = insert_event(db, &alice, -10).await;
+    Delivery::insert("old-job", &alice, db).await.unwrap();
+    Delivery::insert("new-job", &alice, db).await.unwrap();
+    sqlx::query("UPDATE notification_delivery SET delivered_at = $1 WHERE job_id = 'old-job'")
+        .bind(Utc::now() - TimeDelta::days(8))
+        .execute(db)
+        .await
+        .unwrap();
+
+    // Without a retention period, events are kept forever.
+    let keep = RetentionConfig {
+        mode: RetentionMode::Purge,
+        ..RetentionConfig::default()
+    };
+    let report = retention::run(&keep, db).await.unwrap();
+    assert_eq!(report.events_purged, 0);
+    assert_eq!(report.deliveries_deleted, 1);
+    assert!(!Delivery::exists("old-job", &alice, db).await.unwrap());
+    assert!(Delivery::exists("new-job", &alice, db).await.unwrap());
+
+    let report = retention::run(&policy(RetentionMode::Purge), db)
+        .await
+        .unwrap();
+    assert_eq!(report.events_purged, 1);
+    assert!(Event::select_one(old, db).await.unwrap().is_none());
+    assert!(Event::select_one(recent, db).await.unwrap().is_some());
+
+    harness.stop().await;
+}
@@ -0,0 +1,492 @@
+mod common;
+
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use chrono::{TimeDelta, Utc};
+use serde_json::{json, Value};
+
+use rideboard_v2::db::car::Car;
+use rideboard_v2::db::event::Event;
+use rideboard_v2::db::user::DELETED_USER_ID;
+
+use common::{call, car_riders, insert_car, insert_event, TestApp};
+
+async fn create_event<S, B>(app: &S, cookie: &Cookie<'_>) -> i64
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let start = Utc::now() + TimeDelta::days(1);
+    let (status, id) = call(
+        app,
+        TestRequest::post().uri("/api/v1/event/").set_json(json!({
+            "name": "Ski Trip",
+            "location": "Bristol Mountain",
+            "startTime": start,
+            "endTime": start + TimeDelta::hours(8),
+        })),
+        cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    id.as_i64().unwrap()
+}
+
+async fn create_car<S, B>(
+    app: &S,
+    cookie: &Cookie<'_>,
+    event_id: i64,
+    max_capacity: i32,
+    riders: &[&str],
+) -> i64
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let departure = Utc::now() + TimeDelta::days(1);
+    let (status, id) = call(
+        app,
+        TestRequest::post()
+            .uri(&format!("/api/v1/event/{}/car/", event_id))
+            .set_json(json!({
+                "maxCapacity": max_capacity,
+                "departureTime": departure,
+                "returnTime": departure + TimeDelta::hours(8),
+                "comment": "",
+                "riders": riders,
+            })),
+        cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "Failed to create car: {}", id);
+    id.as_i64().unwrap()
+}
+
+fn rider_ids(car: &Value) -> Vec<&str> {
+    car["riders"]
+        .as_array()
+        .map(|riders| {
+            riders
+                .iter()
+                .filter_map(|rider| rider["id"].as_str())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[actix_web::test]
+async fn joining_and_leaving_pings_the_driver() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+
+    let event_id = create_event(&app, &alice_cookie).await;
+    let car_id = create_car(&app, &alice_cookie, event_id, 2, &[]).await;
+    harness.run_jobs().await;
+    assert!(harness.pings.take().is_empty());
+
+    let rider_uri = format!("/api/v1/event/{}/car/{}/rider/", event_id, car_id);
+    let (status, _) = call(&app, TestRequest::post().uri(&rider_uri), &bob_cookie).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, car) = call(
+        &app,
+        TestRequest::get().uri(&format!("/api/v1/event/{}/car/{}", event_id, car_id)),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(rider_ids(&car), vec![bob.as_str()]);
+
+    harness.run_jobs().await;
+    let pings = harness.pings.take();
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].route, "join");
+    assert_eq!(pings[0].username, "alice");
+    assert_eq!(
+        pings[0].body,
+        "bob Tester joined your ride to \"Ski Trip\"."
+    );
+
+    let (status, _) = call(&app, TestRequest::delete().uri(&rider_uri), &bob_cookie).await;
+    assert_eq!(status, StatusCode::OK);
+
+    harness.run_jobs().await;
+    let pings = harness.pings.take();
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].route, "leave");
+    assert_eq!(pings[0].username, "alice");
+    assert_eq!(pings[0].body, "bob Tester left your ride \"Ski Trip\".");
+
+    let metrics = rideboard_v2::metrics::render();
+    for series in [
+        r#"rideboard_jobs_total{job="Join",outcome="processed"}"#,
+        r#"rideboard_jobs_total{job="Leave",outcome="processed"}"#,
+        r#"rideboard_pings_total{kind="join",outcome="sent"}"#,
+        r#"rideboard_pings_total{kind="leave",outcome="sent"}"#,
+    ] {
+        assert!(
+            metrics.contains(series),
+            "{} is missing from {}",
+            series,
+            metrics
+        );
+    }
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn riders_added_by_the_driver_are_pinged() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let carol = harness.oauth.add_user("carol", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    // Riders have to have logged in once to exist.
+    harness.login(&app, "bob").await;
+    harness.login(&app, "carol").await;
+
+    let event_id = create_event(&app, &alice_cookie).await;
+    let car_id = create_car(&app, &alice_cookie, event_id, 3, &[&bob, &carol]).await;
+
+    let (_, car) = call(
+        &app,
+        TestRequest::get().uri(&format!("/api/v1/event/{}/car/{}", event_id, car_id)),
+        &alice_cookie,
+    )
+    .await;
+    let mut riders = rider_ids(&car);
+    riders.sort();
+    assert_eq!(riders, vec![bob.as_str(), carol.as_str()]);
+
+    harness.run_jobs().await;
+    let mut pings = harness.pings.take();
+    pings.sort_by(|a, b| a.username.cmp(&b.username));
+    assert_eq!(pings.len(), 2);
+    for (ping, username) in pings.iter().zip(["bob", "carol"]) {
+        assert_eq!(ping.route, "add");
+        assert_eq!(ping.username, username);
+        assert_eq!(
+            ping.body,
+            "You have been added to alice Tester's ride to \"Ski Trip\" by the driver."
+        );
+    }
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn pings_are_sent_in_the_recipients_locale() {
+    let Some(mut harness) = TestApp::start().await else {
+        return;
+    };
+    harness.templates = toml::from_str(
+        r#"
+[locales.es.templates]
+join = "{rider} se unió a tu viaje a \"{event}\" en {location}."
+"#,
+    )
+    .unwrap();
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    harness.oauth.add_user("bob", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+
+    let (status, body) = call(
+        &app,
+        TestRequest::put()
+            .uri("/api/v1/user/locale")
+            .set_json(json!({ "locale": "es mx" })),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "validation_failed");
+    let (status, _) = call(
+        &app,
+        TestRequest::put()
+            .uri("/api/v1/user/locale")
+            .set_json(json!({ "locale": "es-MX" })),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let event_id = create_event(&app, &alice_cookie).await;
+    let car_id = create_car(&app, &alice_cookie, event_id, 2, &[]).await;
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri(&format!("/api/v1/event/{}/car/{}/rider/", event_id, car_id)),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    harness.run_jobs().await;
+    let pings = harness.pings.take();
+    assert_eq!(pings.len(), 1);
+    assert_eq!(
+        pings[0].body,
+        "bob Tester se unió a tu viaje a \"Ski Trip\" en Bristol Mountain."
+    );
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn retried_jobs_only_ping_riders_they_missed() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let carol = harness.oauth.add_user("carol", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    harness.login(&app, "bob").await;
+    harness.login(&app, "carol").await;
+
+    let event_id = create_event(&app, &alice_cookie).await;
+    harness.pings.fail_for(&["carol"]);
+    create_car(&app, &alice_cookie, event_id, 3, &[&bob, &carol]).await;
+
+    // A 500 from Pings fails the job, whether or not bob was pinged before carol.
+    let failed = harness.try_jobs().await;
+    assert_eq!(failed.len(), 1);
+    let mut pings = harness.pings.take();
+    assert!(pings.iter().all(|ping| ping.username == "bob"));
+
+    harness.pings.fail_for(&[]);
+    assert!(harness.retry_jobs(failed).await.is_empty());
+    pings.extend(harness.pings.take());
+    let mut usernames: Vec<&str> = pings.iter().map(|ping| ping.username.as_str()).collect();
+    usernames.sort();
+    assert_eq!(usernames, vec!["bob", "carol"]);
+
+    let metrics = rideboard_v2::metrics::render();
+    let series = r#"rideboard_pings_total{kind="add",outcome="failed"}"#;
+    assert!(
+        metrics.contains(series),
+        "{} is missing from {}",
+        series,
+        metrics
+    );
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn deleting_an_account_hands_over_events_and_notifies_rides() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let carol = harness.oauth.add_user("carol", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let other_session = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+    let carol_cookie = harness.login(&app, "carol").await;
+
+    // Bob drives to Alice's first event, and she rides with him to his.
+    let shared = create_event(&app, &alice_cookie).await;
+    let alice_car = create_car(&app, &alice_cookie, shared, 2, &[&carol]).await;
+    create_car(&app, &bob_cookie, shared, 2, &[]).await;
+    let bobs = create_event(&app, &bob_cookie).await;
+    let bob_car = create_car(&app, &bob_cookie, bobs, 2, &[]).await;
+    let (status, _) = call(
+        &app,
+        TestRequest::post().uri(&format!("/api/v1/event/{}/car/{}/rider/", bobs, bob_car)),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let alone = create_event(&app, &alice_cookie).await;
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&format!("/api/v1/event/{}/request/", alone))
+            .set_json(json!({})),
+        &carol_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    // Alice drove Bob to an event that's over.
+    let past = insert_event(&harness.state.db, &alice, -10).await;
+    let past_car = insert_car(&harness.state.db, past, &alice, &[&bob]).await;
+    harness.run_jobs().await;
+    harness.pings.take();
+
+    let (status, _) = call(
+        &app,
+        TestRequest::delete().uri("/api/v1/user/me"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(
+        &app,
+        TestRequest::get().uri("/api/v1/user/identity"),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(
+        &app,
+        TestRequest::get().uri("/api/v1/user/identity"),
+        &other_session,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (_, event) = call(
+        &app,
+        TestRequest::get().uri(&format!("/api/v1/event/{}", shared)),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(event["creator"]["id"], bob);
+    let (status, _) = call(
+        &app,
+        TestRequest::get().uri(&format!("/api/v1/event/{}", alone)),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = call(
+        &app,
+        TestRequest::get().uri(&format!("/api/v1/event/{}/car/{}", shared, alice_car)),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, car) = call(
+        &app,
+        TestRequest::get().uri(&format!("/api/v1/event/{}/car/{}", bobs, bob_car)),
+        &bob_cookie,
+    )
+    .await;
+    assert!(rider_ids(&car).is_empty());
+    let db = &harness.state.db;
+    let event = Event::select_one(past, db).await.unwrap().unwrap();
+    assert_eq!(event.creator.id, DELETED_USER_ID);
+    let car = Car::select_one(past, past_car, db).await.unwrap().unwrap();
+    assert_eq!(car.driver.id, DELETED_USER_ID);
+    assert_eq!(car_riders(db, past, past_car).await, vec![bob.clone()]);
+
+    harness.run_jobs().await;
+    let mut pings = harness.pings.take();
+    pings.sort_by(|a, b| (&a.username, &a.body).cmp(&(&b.username, &b.body)));
+    assert_eq!(pings.len(), 3);
+    assert_eq!(pings[0].route, "leave");
+    assert_eq!(pings[0].username, "bob");
+    assert_eq!(pings[0].body, "alice Tester left your ride \"Ski Trip\".");
+    assert_eq!(pings[1].route, "remove");
+    assert_eq!(pings[1].username, "carol");
+    assert_eq!(
+        pings[1].body,
+        "\"Ski Trip\" was cancelled because its organizer deleted their account."
+    );
+    assert_eq!(pings[2].route, "remove");
+    assert_eq!(pings[2].username, "carol");
+    assert_eq!(
+        pings[2].body,
+        "alice Tester deleted their account, so their ride to \"Ski Trip\" is cancelled."
+    );
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn full_cars_cannot_be_joined() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    harness.oauth.add_user("carol", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    harness.login(&app, "bob").await;
+    let carol_cookie = harness.login(&app, "carol").await;
+
+    let event_id = create_event(&app, &alice_cookie).await;
+    let car_id = create_car(&app, &alice_cookie, event_id, 1, &[&bob]).await;
+
+    let (status, body) = call(
+        &app,
+        TestRequest::post().uri(&format!("/api/v1/event/{}/car/{}/rider/", event_id, car_id)),
+        &carol_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "bad_request");
+    assert_eq!(body["error"], "Car is full.");
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn riders_already_in_a_car_are_reported_by_index() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let carol = harness.oauth.add_user("carol", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    harness.login(&app, "bob").await;
+    let carol_cookie = harness.login(&app, "carol").await;
+
+    let event_id = create_event(&app, &alice_cookie).await;
+    create_car(&app, &alice_cookie, event_id, 2, &[&bob]).await;
+
+    let departure = Utc::now() + TimeDelta::days(1);
+    let (status, body) = call(
+        &app,
+        TestRequest::post()
+            .uri(&format!("/api/v1/event/{}/car/", event_id))
+            .set_json(json!({
+                "maxCapacity": 2,
+                "departureTime": departure,
+                "returnTime": departure + TimeDelta::hours(8),
+                "comment": "",
+                "riders": [carol, bob],
+            })),
+        &carol_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let fields: Vec<(&str, &str)> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|err| {
+            (
+                err["field"].as_str().unwrap(),
+                err["code"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        fields,
+        vec![("riders[0]", "is_driver"), ("riders[1]", "already_taken")]
+    );
+
+    harness.stop().await;
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use chrono::{TimeDelta, Utc};
+use serde_json::Value;
+
+use rideboard_v2::db::session::SessionInfo;
+
+use common::{call, logged_in, TestApp};
+
+fn session_ids(sessions: &Value) -> Vec<(&str, bool)> {
+    sessions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|session| {
+            (
+                session["id"].as_str().unwrap(),
+                session["current"].as_bool().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[actix_web::test]
+async fn users_can_end_their_other_sessions() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    let laptop = harness.login(&app, "alice").await;
+    let phone = harness.login(&app, "alice").await;
+
+    let (status, sessions) = call(
+        &app,
+        TestRequest::get().uri("/api/v1/auth/sessions"),
+        &laptop,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let sessions = session_ids(&sessions);
+    assert_eq!(sessions.len(), 2);
+    let (phone_id, _) = sessions
+        .iter()
+        .find(|(_, current)| !current)
+        .expect("Only the laptop's session is current");
+
+    let uri = format!("/api/v1/auth/sessions/{}", phone_id);
+    let (status, _) = call(&app, TestRequest::delete().uri(&uri), &laptop).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!logged_in(&app, &phone).await);
+    assert!(logged_in(&app, &laptop).await);
+    let (status, _) = call(&app, TestRequest::delete().uri(&uri), &laptop).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, sessions) = call(
+        &app,
+        TestRequest::get().uri("/api/v1/auth/sessions"),
+        &laptop,
+    )
+    .await;
+    assert_eq!(session_ids(&sessions).len(), 1);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn only_admins_can_log_other_users_out() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    harness.oauth.add_user("bob", &[]);
+    harness.oauth.add_user("root", &["rtp"]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+    let root_cookie = harness.login(&app, "root").await;
+
+    let uri = format!("/api/v1/user/{}/sessions", alice);
+    let (status, _) = call(&app, TestRequest::delete().uri(&uri), &bob_cookie).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(logged_in(&app, &alice_cookie).await);
+
+    let res = test::call_service(
+        &app,
+        TestRequest::delete()
+            .uri(&uri)
+            .cookie(root_cookie.clone())
+            .to_request(),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(test::read_body(res).await, "Ended 1 sessions");
+    assert!(!logged_in(&app, &alice_cookie).await);
+    assert!(logged_in(&app, &root_cookie).await);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn expired_sessions_are_deleted() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    for (key, expires_at) in [
+        ("expired", Utc::now() - TimeDelta::minutes(1)),
+        ("active", Utc::now() + TimeDelta::hours(1)),
+    ] {
+        SessionInfo::insert_new(key, None, None, "{}", expires_at, db)
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(SessionInfo::delete_expired(db).await.unwrap(), 1);
+    assert!(SessionInfo::select_state("active", db)
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(SessionInfo::delete_expired(db).await.unwrap(), 0);
+
+    harness.stop().await;
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde_json::{json, Value};
+
+use rideboard_v2::db::car::{Car, CarData};
+use rideboard_v2::db::event::Event;
+use rideboard_v2::db::ride_request::{RideRequest, RideRequestData};
+use rideboard_v2::db::user::DELETED_USER_ID;
+
+use common::{call, insert_car, insert_event, insert_user, TestApp};
+
+fn time(value: &Value) -> DateTime<Utc> {
+    serde_json::from_value(value.clone()).unwrap()
+}
+
+#[actix_web::test]
+async fn summaries_count_seats_by_the_hour_cars_leave_in() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let cookie = harness.login(&app, "alice").await;
+    let bob = insert_user(db, "bob").await;
+    let carol = insert_user(db, "carol").await;
+    let dave = insert_user(db, "dave").await;
+    let erin = insert_user(db, "erin").await;
+    let frank = insert_user(db, "frank").await;
+    let event_id = insert_event(db, &alice, 1).await;
+    let event = Event::select_one(event_id, db).await.unwrap().unwrap();
+    let hour = (event.start_time + TimeDelta::hours(1))
+        .duration_trunc(TimeDelta::hours(1))
+        .unwrap();
+
+    // Leaving on the hour and a second before the next one share a window.
+    let cars = [
+        (&alice, hour, 3, vec![&bob, &carol]),
+        (&frank, hour + TimeDelta::seconds(3599), 2, vec![]),
+        (&erin, hour + TimeDelta::hours(1), 1, vec![&dave]),
+    ];
+    for (driver, departure_time, max_capacity, riders) in cars {
+        let data = CarData {
+            max_capacity,
+            departure_time,
+            return_time: event.end_time,
+            comment: String::new(),
+            riders: Vec::new(),
+        };
+        let car = Car::insert_new(event_id, driver.to_string(), &data, db)
+            .await
+            .unwrap();
+        for rider in riders {
+            Car::add_rider(car.id, rider, db).await.unwrap();
+        }
+    }
+    let lonely = insert_user(db, "gina").await;
+    let request = RideRequestData {
+        earliest_departure: None,
+        latest_departure: None,
+        note: String::new(),
+    };
+    RideRequest::insert_new(event_id, lonely, &request, db)
+        .await
+        .unwrap();
+
+    let (status, summary) = call(
+        &app,
+        TestRequest::get().uri(&format!("/api/v1/event/{}/summary", event_id)),
+        &cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["eventId"], event_id);
+    assert_eq!(summary["drivers"], 3);
+    assert_eq!(summary["totalCapacity"], 6);
+    assert_eq!(summary["riders"], 3);
+    assert_eq!(summary["openSeats"], 3);
+    assert_eq!(summary["unplaced"], 1);
+
+    let departures = summary["departures"].as_array().unwrap();
+    assert_eq!(departures.len(), 2);
+    assert_eq!(time(&departures[0]["start"]), hour);
+    assert_eq!(departures[0]["cars"], 2);
+    assert_eq!(departures[0]["capacity"], 5);
+    assert_eq!(departures[0]["riders"], 2);
+    assert_eq!(departures[0]["openSeats"], 3);
+    assert_eq!(time(&departures[1]["start"]), hour + TimeDelta::hours(1));
+    assert_eq!(departures[1]["cars"], 1);
+    assert_eq!(departures[1]["capacity"], 1);
+    assert_eq!(departures[1]["riders"], 1);
+    assert_eq!(departures[1]["openSeats"], 0);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn site_stats_leave_out_deleted_drivers() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &["rtp"]);
+    let cookie = harness.login(&app, "alice").await;
+    let bob = insert_user(db, "bob").await;
+    let carol = insert_user(db, "carol").await;
+    let dave = insert_user(db, "dave").await;
+    let past = insert_event(db, &alice, -3).await;
+    let upcoming = insert_event(db, &alice, 1).await;
+
+    // Bob gave the most rides, then deleted his account.
+    let past_car = insert_car(db, past, &bob, &[&carol, &dave]).await;
+    sqlx::query("UPDATE car SET driver = $1 WHERE id = $2")
+        .bind(DELETED_USER_ID)
+        .bind(past_car)
+        .execute(db)
+        .await
+        .unwrap();
+    insert_car(db, upcoming, &carol, &[&dave]).await;
+    insert_car(db, upcoming, &alice, &[]).await;
+
+    let (status, stats) = call(&app, TestRequest::get().uri("/api/v1/stats/"), &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["ridesGiven"], 3);
+
+    let months = stats["eventsPerMonth"].as_array().unwrap();
+    let starts: Vec<DateTime<Utc>> = months.iter().map(|month| time(&month["month"])).collect();
+    assert!(starts.is_sorted());
+    let events: i64 = months
+        .iter()
+        .map(|month| month["events"].as_i64().unwrap())
+        .sum();
+    assert_eq!(events, 2);
+
+    assert_eq!(
+        stats["topDrivers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|driver| json!([driver["driver"]["id"], driver["cars"], driver["riders"]]))
+            .collect::<Vec<_>>(),
+        vec![json!([carol, 1, 1]), json!([alice, 1, 0])]
+    );
+
+    harness.stop().await;
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+use serde_json::{json, Value};
+
+use common::{call, insert_event, TestApp};
+
+#[actix_web::test]
+async fn tokens_act_as_their_user_only_within_their_scopes() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &["rtp"]);
+    let cookie = harness.login(&app, "alice").await;
+    let event_id = insert_event(&harness.state.db, &alice, 1).await;
+
+    let mut tokens = Vec::new();
+    for scope in ["read", "manage_cars"] {
+        let (status, token) = call(
+            &app,
+            TestRequest::post()
+                .uri("/api/v1/auth/tokens/")
+                .set_json(json!({ "name": scope, "scopes": [scope] })),
+            &cookie,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        tokens.push(token);
+    }
+    let (read, cars) = (&tokens[0]["token"], &tokens[1]["token"]);
+
+    let bearer = |req: TestRequest, token: &Value| {
+        req.insert_header((
+            header::AUTHORIZATION,
+            format!("Bearer {}", token.as_str().unwrap()),
+        ))
+        .to_request()
+    };
+    let request_uri = format!("/api/v1/event/{}/request/", event_id);
+
+    let res = test::call_service(&app, bearer(TestRequest::get().uri(&request_uri), read)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    // Nothing about the token ends up in a session.
+    assert_eq!(res.response().cookies().count(), 0);
+
+    let res = test::call_service(
+        &app,
+        bearer(
+            TestRequest::post().uri(&request_uri).set_json(json!({})),
+            read,
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = test::call_service(
+        &app,
+        bearer(TestRequest::get().uri("/api/v1/auth/sessions"), read),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = test::call_service(
+        &app,
+        bearer(
+            TestRequest::post().uri(&request_uri).set_json(json!({})),
+            cars,
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let (_, requests) = call(&app, TestRequest::get().uri(&request_uri), &cookie).await;
+    assert_eq!(requests[0]["rider"]["id"], alice.as_str());
+
+    // Alice is an admin, but her tokens aren't.
+    let (status, _) = call(&app, TestRequest::get().uri("/api/v1/stats/"), &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    let res =
+        test::call_service(&app, bearer(TestRequest::get().uri("/api/v1/stats/"), read)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn revoked_and_expired_tokens_are_rejected() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    let cookie = harness.login(&app, "alice").await;
+
+    let mut tokens = Vec::new();
+    for name in ["revoked", "expired"] {
+        let (_, token) = call(
+            &app,
+            TestRequest::post()
+                .uri("/api/v1/auth/tokens/")
+                .set_json(json!({ "name": name, "scopes": ["read"] })),
+            &cookie,
+        )
+        .await;
+        tokens.push(token);
+    }
+    let (status, _) = call(
+        &app,
+        TestRequest::delete().uri(&format!("/api/v1/auth/tokens/{}", tokens[0]["id"])),
+        &cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    sqlx::query("UPDATE api_token SET expires_at = NOW() WHERE id = $1")
+        .bind(tokens[1]["id"].as_i64().unwrap() as i32)
+        .execute(&harness.state.db)
+        .await
+        .unwrap();
+
+    for token in tokens
+        .iter()
+        .map(|token| token["token"].as_str().unwrap())
+        .chain(["rbt_not-a-token"])
+    {
+        let res = test::call_service(
+            &app,
+            TestRequest::get()
+                .uri("/api/v1/event/")
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{}", token);
+    }
+
+    harness.stop().await;
+}
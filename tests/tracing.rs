@@ -0,0 +1,102 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use chrono::{TimeDelta, Utc};
+use opentelemetry::trace::TraceId;
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+use serde_json::json;
+
+use common::{call, TestApp};
+use rideboard_v2::telemetry;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+fn span<'a>(spans: &'a [SpanData], name: &str) -> &'a SpanData {
+    spans
+        .iter()
+        .find(|span| span.name == name)
+        .unwrap_or_else(|| panic!("No {} span", name))
+}
+
+#[actix_web::test]
+async fn pings_join_the_trace_of_the_request_that_caused_them() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let exporter = InMemorySpanExporter::default();
+    let _telemetry = telemetry::init_with_exporter(exporter.clone(), "test").unwrap();
+
+    let app = test::init_service(harness.app()).await;
+    harness.oauth.add_user("alice", &[]);
+    harness.oauth.add_user("bob", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+
+    let start = Utc::now() + TimeDelta::days(1);
+    let (_, event_id) = call(
+        &app,
+        TestRequest::post().uri("/api/v1/event/").set_json(json!({
+            "name": "Ski Trip",
+            "location": "Bristol Mountain",
+            "startTime": start,
+            "endTime": start + TimeDelta::hours(8),
+        })),
+        &alice_cookie,
+    )
+    .await;
+    let (_, car_id) = call(
+        &app,
+        TestRequest::post()
+            .uri(&format!("/api/v1/event/{}/car/", event_id))
+            .set_json(json!({
+                "maxCapacity": 2,
+                "departureTime": start,
+                "returnTime": start + TimeDelta::hours(8),
+                "comment": "",
+                "riders": [],
+            })),
+        &alice_cookie,
+    )
+    .await;
+    harness.run_jobs().await;
+    exporter.reset();
+
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&format!("/api/v1/event/{}/car/{}/rider/", event_id, car_id))
+            .insert_header((
+                "traceparent",
+                format!("00-{}-00f067aa0ba902b7-01", TRACE_ID),
+            )),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    harness.run_jobs().await;
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let trace_id = TraceId::from_hex(TRACE_ID).unwrap();
+    for name in [
+        "HTTP request",
+        "create_rider",
+        "car::select_one",
+        "insert_job",
+        "worker::work",
+        "pings::send",
+    ] {
+        assert_eq!(
+            span(&spans, name).span_context.trace_id(),
+            trace_id,
+            "{} is not part of the request's trace",
+            name
+        );
+    }
+    assert_eq!(
+        span(&spans, "worker::work").parent_span_id,
+        span(&spans, "insert_job").span_context.span_id()
+    );
+
+    harness.stop().await;
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use serde_json::json;
+
+use rideboard_v2::db::car::Car;
+use rideboard_v2::db::ride_request::RideRequest;
+
+use common::{call, car_riders, insert_car, insert_event, TestApp};
+
+#[actix_web::test]
+async fn only_the_driver_offers_and_only_the_target_accepts() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let carol = harness.oauth.add_user("carol", &[]);
+    harness.oauth.add_user("dave", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+    let carol_cookie = harness.login(&app, "carol").await;
+    let dave_cookie = harness.login(&app, "dave").await;
+    let event_id = insert_event(db, &alice, 1).await;
+    let car_id = insert_car(db, event_id, &alice, &[&bob]).await;
+    let uri = format!("/api/v1/event/{}/car/{}/transfer/", event_id, car_id);
+    let accept_uri = format!("{}accept", uri);
+
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&format!("/api/v1/event/{}/request/", event_id))
+            .set_json(json!({})),
+        &carol_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A rider can't give the car away.
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&uri)
+            .set_json(json!({ "newDriver": carol })),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&uri)
+            .set_json(json!({ "newDriver": carol })),
+        &alice_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    harness.run_jobs().await;
+    let pings = harness.pings.take();
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].route, "transfer");
+    assert_eq!(pings[0].username, "carol");
+
+    // Only Carol can take it.
+    for cookie in [&dave_cookie, &bob_cookie] {
+        let (status, _) = call(&app, TestRequest::post().uri(&accept_uri), cookie).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+    let (status, _) = call(&app, TestRequest::post().uri(&accept_uri), &carol_cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    let car = Car::select_one(event_id, car_id, db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(car.driver.id, carol);
+    assert!(RideRequest::select_all(event_id, db)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Accepting again finds no offer.
+    let (status, _) = call(&app, TestRequest::post().uri(&accept_uri), &carol_cookie).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    harness.run_jobs().await;
+    let pings = harness.pings.take();
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].username, "bob");
+    assert_eq!(
+        pings[0].body,
+        "carol Tester is now driving your ride to \"Ski Trip\" instead of alice Tester."
+    );
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+async fn riders_who_take_over_leave_their_seat() {
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let app = test::init_service(harness.app()).await;
+    let alice = harness.oauth.add_user("alice", &[]);
+    let bob = harness.oauth.add_user("bob", &[]);
+    let carol = harness.oauth.add_user("carol", &[]);
+    let alice_cookie = harness.login(&app, "alice").await;
+    let bob_cookie = harness.login(&app, "bob").await;
+    let carol_cookie = harness.login(&app, "carol").await;
+    let event_id = insert_event(db, &alice, 1).await;
+    let car_id = insert_car(db, event_id, &alice, &[&bob, &carol]).await;
+    let uri = format!("/api/v1/event/{}/car/{}/transfer/", event_id, car_id);
+    let accept_uri = format!("{}accept", uri);
+
+    // An offer replaced by another one is stale.
+    for new_driver in [&carol, &bob] {
+        let (status, _) = call(
+            &app,
+            TestRequest::post()
+                .uri(&uri)
+                .set_json(json!({ "newDriver": new_driver })),
+            &alice_cookie,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, _) = call(&app, TestRequest::post().uri(&accept_uri), &carol_cookie).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = call(&app, TestRequest::post().uri(&accept_uri), &bob_cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    let car = Car::select_one(event_id, car_id, db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(car.driver.id, bob);
+    assert_eq!(car_riders(db, event_id, car_id).await, vec![carol]);
+
+    // A declined offer can't be accepted afterwards.
+    let (status, _) = call(
+        &app,
+        TestRequest::post()
+            .uri(&uri)
+            .set_json(json!({ "newDriver": alice })),
+        &bob_cookie,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, TestRequest::delete().uri(&uri), &alice_cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, TestRequest::post().uri(&accept_uri), &alice_cookie).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    harness.stop().await;
+}
@@ -0,0 +1,332 @@
+use chrono::{DateTime, TimeDelta, Utc};
+
+use rideboard_v2::db::car::{Car, CarData};
+use rideboard_v2::db::event::EventData;
+use rideboard_v2::db::ride_request::RideRequestData;
+use rideboard_v2::db::user::UserData;
+use rideboard_v2::error::FieldError;
+use rideboard_v2::validation::ValidationRules;
+
+fn event() -> EventData {
+    let start = Utc::now() + TimeDelta::days(1);
+    EventData {
+        name: "Ski Trip".to_string(),
+        location: "Bristol Mountain".to_string(),
+        start_time: start,
+        end_time: start + TimeDelta::hours(8),
+    }
+}
+
+fn car() -> CarData {
+    let departure = Utc::now() + TimeDelta::days(1);
+    CarData {
+        max_capacity: 3,
+        departure_time: departure,
+        return_time: departure + TimeDelta::hours(8),
+        comment: String::new(),
+        riders: Vec::new(),
+    }
+}
+
+fn user(id: &str) -> UserData {
+    UserData {
+        id: id.to_string(),
+        realm: "csh".to_string(),
+        name: id.to_string(),
+        email: format!("{}@csh.rit.edu", id),
+    }
+}
+
+fn other_car(driver: &str, riders: &[&str], departure: DateTime<Utc>) -> Car {
+    Car {
+        id: 1,
+        event_id: Some(1),
+        driver: user(driver),
+        riders: Some(riders.iter().map(|id| user(id)).collect()),
+        max_capacity: 4,
+        departure_time: departure,
+        return_time: departure,
+        comment: String::new(),
+    }
+}
+
+/// The (field, code) of every error, to compare without the messages.
+fn codes(result: Result<(), Vec<FieldError>>) -> Vec<(String, String)> {
+    result
+        .err()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|err| (err.field.unwrap_or_default(), err.code))
+        .collect()
+}
+
+fn pair(field: &str, code: &str) -> (String, String) {
+    (field.to_string(), code.to_string())
+}
+
+#[test]
+fn valid_event_passes() {
+    assert!(event().validate(&ValidationRules::default()).is_ok());
+}
+
+#[test]
+fn event_name_is_required() {
+    let mut data = event();
+    data.name = "  ".to_string();
+    assert_eq!(
+        codes(data.validate(&ValidationRules::default())),
+        vec![pair("name", "required")]
+    );
+}
+
+#[test]
+fn event_name_length_is_limited() {
+    let rules = ValidationRules {
+        max_name_length: 5,
+        ..ValidationRules::default()
+    };
+    let mut data = event();
+    data.name = "Ski Trip".to_string();
+    let errs = data.validate(&rules).unwrap_err();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].field.as_deref(), Some("name"));
+    assert_eq!(errs[0].code, "too_long");
+    assert_eq!(errs[0].params["max"], 5);
+
+    data.name = "Skiii".to_string();
+    assert!(data.validate(&rules).is_ok());
+}
+
+#[test]
+fn event_location_is_required() {
+    let mut data = event();
+    data.location = String::new();
+    assert_eq!(
+        codes(data.validate(&ValidationRules::default())),
+        vec![pair("location", "required")]
+    );
+}
+
+#[test]
+fn event_location_length_is_limited() {
+    let rules = ValidationRules {
+        max_location_length: 4,
+        ..ValidationRules::default()
+    };
+    assert_eq!(
+        codes(event().validate(&rules)),
+        vec![pair("location", "too_long")]
+    );
+}
+
+#[test]
+fn event_cannot_end_before_it_starts() {
+    let mut data = event();
+    data.end_time = data.start_time - TimeDelta::hours(1);
+    assert_eq!(
+        codes(data.validate(&ValidationRules::default())),
+        vec![pair("endTime", "before_start")]
+    );
+}
+
+#[test]
+fn event_duration_is_limited() {
+    let rules = ValidationRules {
+        max_event_duration: TimeDelta::hours(4),
+        ..ValidationRules::default()
+    };
+    let errs = event().validate(&rules).unwrap_err();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].field.as_deref(), Some("endTime"));
+    assert_eq!(errs[0].code, "too_long");
+    assert_eq!(errs[0].params["maxHours"], 4);
+}
+
+#[test]
+fn event_cannot_be_in_the_past() {
+    let mut data = event();
+    data.start_time = Utc::now() - TimeDelta::days(2);
+    data.end_time = Utc::now() - TimeDelta::days(1);
+    assert_eq!(
+        codes(data.validate(&ValidationRules::default())),
+        vec![pair("endTime", "in_past")]
+    );
+}
+
+#[test]
+fn every_event_problem_is_reported() {
+    let data = EventData {
+        name: String::new(),
+        location: String::new(),
+        start_time: Utc::now() - TimeDelta::days(1),
+        end_time: Utc::now() - TimeDelta::days(2),
+    };
+    assert_eq!(
+        codes(data.validate(&ValidationRules::default())),
+        vec![
+            pair("name", "required"),
+            pair("location", "required"),
+            pair("endTime", "before_start"),
+            pair("endTime", "in_past"),
+        ]
+    );
+}
+
+#[test]
+fn valid_car_passes() {
+    let mut data = car();
+    data.riders = vec!["bob".to_string()];
+    assert!(data
+        .validate(
+            &"alice".to_string(),
+            Vec::new(),
+            &ValidationRules::default()
+        )
+        .is_ok());
+}
+
+#[test]
+fn car_cannot_return_before_departure() {
+    let mut data = car();
+    data.return_time = data.departure_time - TimeDelta::hours(1);
+    assert_eq!(
+        codes(data.validate(
+            &"alice".to_string(),
+            Vec::new(),
+            &ValidationRules::default()
+        )),
+        vec![pair("returnTime", "before_departure")]
+    );
+}
+
+#[test]
+fn car_cannot_leave_in_the_past() {
+    let mut data = car();
+    data.departure_time = Utc::now() - TimeDelta::hours(1);
+    assert_eq!(
+        codes(data.validate(
+            &"alice".to_string(),
+            Vec::new(),
+            &ValidationRules::default()
+        )),
+        vec![pair("departureTime", "in_past")]
+    );
+}
+
+#[test]
+fn car_capacity_cannot_be_negative() {
+    let mut data = car();
+    data.max_capacity = -1;
+    assert_eq!(
+        codes(data.validate(
+            &"alice".to_string(),
+            Vec::new(),
+            &ValidationRules::default()
+        )),
+        vec![pair("maxCapacity", "too_small")]
+    );
+}
+
+#[test]
+fn car_capacity_is_limited() {
+    let rules = ValidationRules {
+        max_capacity: 2,
+        ..ValidationRules::default()
+    };
+    let errs = car()
+        .validate(&"alice".to_string(), Vec::new(), &rules)
+        .unwrap_err();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].field.as_deref(), Some("maxCapacity"));
+    assert_eq!(errs[0].code, "too_large");
+    assert_eq!(errs[0].params["max"], 2);
+}
+
+#[test]
+fn car_riders_cannot_exceed_capacity() {
+    let mut data = car();
+    data.max_capacity = 1;
+    data.riders = vec!["bob".to_string(), "carol".to_string()];
+    assert_eq!(
+        codes(data.validate(
+            &"alice".to_string(),
+            Vec::new(),
+            &ValidationRules::default()
+        )),
+        vec![pair("riders", "too_many")]
+    );
+}
+
+#[test]
+fn car_comment_length_is_limited() {
+    let rules = ValidationRules {
+        max_comment_length: 10,
+        ..ValidationRules::default()
+    };
+    let mut data = car();
+    data.comment = "Leaving from the south lot".to_string();
+    assert_eq!(
+        codes(data.validate(&"alice".to_string(), Vec::new(), &rules)),
+        vec![pair("comment", "too_long")]
+    );
+}
+
+#[test]
+fn ride_request_notes_and_windows_are_checked() {
+    let rules = ValidationRules {
+        max_note_length: 10,
+        ..ValidationRules::default()
+    };
+    let earliest = Utc::now() + TimeDelta::days(1);
+    let data = RideRequestData {
+        earliest_departure: Some(earliest),
+        latest_departure: Some(earliest - TimeDelta::hours(1)),
+        note: "I can leave after my last class".to_string(),
+    };
+    assert_eq!(
+        codes(data.validate(&rules)),
+        vec![
+            pair("latestDeparture", "before_start"),
+            pair("note", "too_long")
+        ]
+    );
+
+    let data = RideRequestData {
+        earliest_departure: None,
+        latest_departure: None,
+        note: "After 5pm".to_string(),
+    };
+    assert_eq!(codes(data.validate(&rules)), Vec::new());
+}
+
+#[test]
+fn driver_cannot_ride_in_their_own_car() {
+    let mut data = car();
+    data.riders = vec!["bob".to_string(), "alice".to_string()];
+    assert_eq!(
+        codes(data.validate(
+            &"alice".to_string(),
+            Vec::new(),
+            &ValidationRules::default()
+        )),
+        vec![pair("riders[1]", "is_driver")]
+    );
+}
+
+#[test]
+fn riders_cannot_be_in_another_car() {
+    let mut data = car();
+    data.riders = vec!["bob".to_string(), "carol".to_string(), "dave".to_string()];
+    let others = vec![other_car("carol", &["dave"], data.departure_time)];
+    let errs = data
+        .validate(&"alice".to_string(), others, &ValidationRules::default())
+        .unwrap_err();
+    assert_eq!(
+        codes(Err(errs.clone())),
+        vec![
+            pair("riders[1]", "already_taken"),
+            pair("riders[2]", "already_taken")
+        ]
+    );
+    assert_eq!(errs[0].params["user"], "carol");
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use std::time::Duration;
+
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use redis_work_queue::{Item, KeyPrefix, WorkQueue};
+
+use rideboard_v2::app::{MultipleRiderChange, RedisJob, SimpleRiderChange};
+use rideboard_v2::config::WorkerConfig;
+use rideboard_v2::redis::RedisQueue;
+use rideboard_v2::worker::work_loop;
+
+use common::{insert_car, insert_event, insert_user, redis_client, TestApp};
+
+/// Leases short enough that the tests don't wait long for them to expire.
+fn config() -> WorkerConfig {
+    WorkerConfig {
+        concurrency: 1,
+        lease_seconds: 1,
+        ..WorkerConfig::default()
+    }
+}
+
+/// Queue `job` like the server does.
+async fn add_job(redis: &mut MultiplexedConnection, work_queue: &WorkQueue, job: RedisJob) -> Item {
+    let mut queue = RedisQueue::Memory(Vec::new());
+    queue.insert_job(job).await.unwrap();
+    let job = queue.take_jobs().remove(0);
+    assert!(work_queue.add_item(redis, &job).await.unwrap());
+    job
+}
+
+/// Check that every job was completed and nothing is left under `prefix`.
+async fn assert_queue_is_empty(redis: &mut MultiplexedConnection, prefix: &str) {
+    let keys: Vec<String> = redis.keys(format!("{}:*", prefix)).await.unwrap();
+    assert!(keys.is_empty(), "{:?} were left behind", keys);
+}
+
+#[actix_web::test]
+#[ignore = "needs Redis at TEST_REDIS_URL"]
+async fn jobs_left_by_a_stopped_worker_are_run_again() {
+    let (client, prefix) = redis_client();
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let alice = insert_user(db, "alice").await;
+    let bob = insert_user(db, "bob").await;
+    let event_id = insert_event(db, &alice, 1).await;
+    let car_id = insert_car(db, event_id, &alice, &[&bob]).await;
+
+    let mut redis = client.get_multiplexed_async_connection().await.unwrap();
+    let work_queue = WorkQueue::new(KeyPrefix::from(prefix.as_str()));
+    let job = add_job(
+        &mut redis,
+        &work_queue,
+        RedisJob::Join(SimpleRiderChange {
+            event_id,
+            car_id,
+            rider_id: bob,
+        }),
+    )
+    .await;
+    // Another worker takes the job and stops before finishing it.
+    let leased = work_queue
+        .lease(&mut redis, None, Duration::from_secs(1))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(leased.id, job.id);
+
+    work_loop(
+        &client,
+        &prefix,
+        &config(),
+        db.clone(),
+        harness.pings.client(),
+        harness.templates.clone(),
+        harness.pings.wait_for(1, Duration::from_secs(20)),
+    )
+    .await
+    .unwrap();
+
+    let pings = harness.pings.take();
+    assert_eq!(pings.len(), 1);
+    assert_eq!(pings[0].route, "join");
+    assert_eq!(pings[0].username, "alice");
+    assert_queue_is_empty(&mut redis, &prefix).await;
+
+    harness.stop().await;
+}
+
+#[actix_web::test]
+#[ignore = "needs Redis at TEST_REDIS_URL"]
+async fn failed_jobs_are_retried_without_pinging_anyone_twice() {
+    let (client, prefix) = redis_client();
+    let Some(harness) = TestApp::start().await else {
+        return;
+    };
+    let db = &harness.state.db;
+    let alice = insert_user(db, "alice").await;
+    let bob = insert_user(db, "bob").await;
+    let carol = insert_user(db, "carol").await;
+    let event_id = insert_event(db, &alice, 1).await;
+    let car_id = insert_car(db, event_id, &alice, &[&bob, &carol]).await;
+
+    let mut redis = client.get_multiplexed_async_connection().await.unwrap();
+    let work_queue = WorkQueue::new(KeyPrefix::from(prefix.as_str()));
+    harness.pings.fail_for(&["carol"]);
+    add_job(
+        &mut redis,
+        &work_queue,
+        RedisJob::RiderUpdate(MultipleRiderChange {
+            event_id,
+            car_id,
+            old_riders: Vec::new(),
+            new_riders: vec![bob, carol],
+        }),
+    )
+    .await;
+
+    let pings = &harness.pings;
+    // Pings comes back once the first attempt failed, and the retry has to reach carol.
+    let shutdown = async {
+        let recovered = tokio::time::timeout(Duration::from_secs(20), async {
+            while pings.refused() == 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        });
+        if recovered.await.is_ok() {
+            pings.fail_for(&[]);
+            pings.wait_for(2, Duration::from_secs(20)).await;
+        }
+    };
+    work_loop(
+        &client,
+        &prefix,
+        &config(),
+        db.clone(),
+        pings.client(),
+        harness.templates.clone(),
+        shutdown,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(pings.refused(), 1);
+    let mut usernames: Vec<String> = pings.take().into_iter().map(|ping| ping.username).collect();
+    usernames.sort();
+    assert_eq!(usernames, vec!["bob", "carol"]);
+    assert_queue_is_empty(&mut redis, &prefix).await;
+
+    harness.stop().await;
+}